@@ -0,0 +1,36 @@
+//! Errors decoding what arrives from the network
+
+use std::fmt;
+
+/// Why a datagram (or a part of it) could not be decoded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Shorter than the named structure requires
+    Truncated(&'static str),
+    /// Control packet of a `Type` this implementation does not know
+    UnknownControlType(u16),
+    /// Field (named) holding a value outside its range
+    BadValue(&'static str, u32),
+    /// Malformed handshake extension of an `Extension Type`
+    BadExtension(u16),
+    /// `KMRSP` reporting the peer failed to use the key material (`KM State`)
+    KeyMaterialState(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated(what) => write!(f, "Truncated {what}"),
+            Self::UnknownControlType(r#type) => write!(f, "Unknown control type 0x{type:x}"),
+            Self::BadValue(field, value) => write!(f, "Invalid {field} 0x{value:x}"),
+            Self::BadExtension(r#type) => write!(f, "Malformed handshake extension {type}"),
+            Self::KeyMaterialState(state) => {
+                write!(f, "Peer rejected key material (state {state})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -1,9 +1,32 @@
 //! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt>
 
+pub mod error;
 pub mod macros;
+pub mod options;
 pub mod protocol;
 pub mod server;
 
-pub use server::callback::{connection::CallbackConnection, listener::CallbackListener};
+pub use error::Error;
+pub use options::Options;
+pub use protocol::{
+    congestion::CongestionType,
+    filter::FilterConfig,
+    group::GroupType,
+    ops::{AcceptRequest, CloseReason},
+    packet::control::handshake::reject_reason::{CoreReason, RejectReason},
+    stats::{Counters, Stats},
+    stream_id::StreamId,
+};
+pub use server::callback::{
+    caller::CallbackCaller,
+    connection::CallbackConnection,
+    listener::CallbackListener,
+};
 #[cfg(feature = "tokio")]
-pub use server::tokio::{connection::AsyncConnection, listener::AsyncListener};
+pub use server::tokio::{
+    caller::AsyncCaller,
+    connection::AsyncConnection,
+    group::AsyncGroup,
+    listener::AsyncListener,
+    rendezvous::AsyncRendezvous,
+};
@@ -9,12 +9,12 @@ macro_rules! auto_try_from {
         }
 
         impl std::convert::TryFrom<$vtype> for $name {
-            type Error = anyhow::Error;
+            type Error = $crate::error::Error;
 
             fn try_from(v: $vtype) -> Result<Self, Self::Error> {
                 match v {
                     $(x if x == $name::$vname as $vtype => Ok($name::$vname),)*
-                    _ => Err(anyhow::anyhow!("Unknown value: 0x{v:x}")),
+                    _ => Err($crate::error::Error::BadValue(stringify!($name), v.into())),
                 }
             }
         }
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use crate::protocol::{
+    congestion::CongestionType,
+    constants::{
+        DEFAULT_KM_PREANNOUNCE,
+        DEFAULT_KM_REFRESH_RATE,
+        DEFAULT_LATENCY,
+        DEFAULT_MAX_BANDWIDTH,
+        DEFAULT_OVERHEAD,
+        DEFAULT_PEER_IDLE_TIMEOUT,
+    },
+    filter::FilterConfig,
+    packet::control::handshake::extension::key_material::Cipher,
+    stream_id::StreamId,
+};
+
+/// Connection settings of a listener or a caller
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Stream ID sent by a caller (e.g. `#!::r=live/cam1,m=publish`)
+    pub stream_id: Option<StreamId>,
+
+    /// Deliver received packets at their timestamp plus [`Self::latency`] (TSBPD)
+    /// (used for each direction whose sender and receiver both enable it)
+    pub tsbpd: bool,
+    /// TSBPD latency proposed to the peer
+    /// (the larger of both sides' values is used)
+    pub latency: Duration,
+    /// Skip packets that are not delivered in time instead of waiting for them (`TLPKTDROP`)
+    /// (used only if both sides enable it)
+    pub too_late_drop: bool,
+    /// Keep message boundaries; otherwise payloads form a byte stream (`STREAM`)
+    /// (both sides must use the same mode)
+    pub message_api: bool,
+    /// Close the connection if nothing arrives from the peer for this long
+    pub peer_idle_timeout: Duration,
+
+    /// Congestion control: [`CongestionType::Live`] or [`CongestionType::File`]
+    /// (both sides must use the same one)
+    pub congestion: CongestionType,
+    /// (bytes/s) Cap of the live sending rate
+    /// (`0`: [`Self::input_bandwidth`] plus [`Self::overhead`])
+    pub max_bandwidth: u64,
+    /// (bytes/s) Expected live input rate (`0`: measure it)
+    pub input_bandwidth: u64,
+    /// (%) Bandwidth on top of the live input rate kept for retransmissions
+    pub overhead: u32,
+
+    /// Packet filter (e.g. `fec,cols:10,rows:5`), combined with the peer's configuration
+    /// (a filter set on one side only is used by both)
+    pub packet_filter: Option<FilterConfig>,
+
+    /// Encrypt payloads with keys derived from this passphrase (10 to 79 characters)
+    /// (a listener with a passphrase rejects unencrypted callers)
+    pub passphrase: Option<String>,
+    /// Payload cipher proposed by a caller: [`Cipher::AesCtr`] or [`Cipher::AesGcm`]
+    /// (AES-GCM also authenticates payloads and drops forged packets)
+    pub cipher: Cipher,
+    /// Reject callers that do not use [`Cipher::AesGcm`] (listener only)
+    pub require_gcm: bool,
+    /// (bytes) Length of generated encryption keys: 16, 24 or 32
+    pub key_length: usize,
+    /// Packets sent with one key before switching to a fresh one
+    /// (only the caller, which generates the keys, refreshes them)
+    pub km_refresh_rate: u32,
+    /// Packets before a key switch to announce the next key (and after it to retire the old one)
+    pub km_preannounce: u32,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self {
+            stream_id: None,
+            tsbpd: true,
+            latency: Duration::from_micros(DEFAULT_LATENCY.into()),
+            too_late_drop: true,
+            message_api: true,
+            peer_idle_timeout: Duration::from_micros(DEFAULT_PEER_IDLE_TIMEOUT.into()),
+            congestion: CongestionType::Live,
+            max_bandwidth: DEFAULT_MAX_BANDWIDTH,
+            input_bandwidth: 0,
+            overhead: DEFAULT_OVERHEAD,
+            packet_filter: None,
+            passphrase: None,
+            cipher: Cipher::AesCtr,
+            require_gcm: false,
+            key_length: 16,
+            km_refresh_rate: DEFAULT_KM_REFRESH_RATE,
+            km_preannounce: DEFAULT_KM_PREANNOUNCE,
+        }
+    }
+
+    /// Settings for reliable bulk transfer (`SRTT_FILE` of `libsrt`): a byte stream
+    /// under [`CongestionType::File`], without TSBPD or too-late drop
+    ///
+    /// Set [`Self::message_api`] to transfer whole messages (e.g. files) instead.
+    pub fn file() -> Self {
+        Self {
+            tsbpd: false,
+            too_late_drop: false,
+            message_api: false,
+            congestion: CongestionType::File,
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,2 +1,17 @@
+pub mod ack_history;
+pub mod congestion;
 pub mod constants;
+pub mod crypto;
+pub mod filter;
+pub mod group;
+pub mod loss_list;
+pub mod ops;
 pub mod packet;
+pub mod receive_buffer;
+pub mod send_buffer;
+pub mod sequence;
+pub mod stats;
+pub mod stream_id;
+pub mod syn_cookie;
+pub mod time_window;
+pub mod tsbpd;
@@ -0,0 +1,83 @@
+//! Receiver's record of sent full ACKs, matched by the peer's ACKACKs to measure RTT
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.10>
+
+use std::{collections::VecDeque, time::Instant};
+
+/// Unconfirmed full ACKs kept at most (older ones are forgotten)
+const CAPACITY: usize = 1024;
+
+pub struct AckHistory {
+    /// `(Acknowledgement Number, Last Acknowledged Packet Sequence Number, send time)`,
+    /// oldest first
+    sent: VecDeque<(u32, u32, Instant)>,
+    /// Sequence number of the latest full ACK confirmed by an ACKACK
+    confirmed: Option<u32>,
+}
+
+impl AckHistory {
+    pub fn new() -> Self {
+        Self {
+            sent: VecDeque::new(),
+            confirmed: None,
+        }
+    }
+
+    /// Full ACK `ack_number` acknowledging packets before `sequence_number` sent at `now`
+    pub fn push(&mut self, ack_number: u32, sequence_number: u32, now: Instant) {
+        if self.sent.len() == CAPACITY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((ack_number, sequence_number, now));
+    }
+
+    /// (micros) RTT sample from the ACKACK of `ack_number` arriving at `now`
+    /// (`None` if that ACK is unknown or already confirmed)
+    ///
+    /// Older ACKs are forgotten: their ACKACKs were lost or reordered.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn acknowledge(&mut self, ack_number: u32, now: Instant) -> Option<u32> {
+        let position = self
+            .sent
+            .iter()
+            .position(|(number, ..)| *number == ack_number)?;
+        let (_, sequence_number, sent) = self.sent.drain(..=position).next_back()?;
+        self.confirmed = Some(sequence_number);
+
+        Some(now.saturating_duration_since(sent).as_micros() as u32)
+    }
+
+    /// Sequence number the peer has seen acknowledged
+    /// (no need for another full ACK until it advances)
+    pub fn confirmed(&self) -> Option<u32> {
+        self.confirmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_acknowledge() {
+        let mut history = AckHistory::new();
+        let start = Instant::now();
+
+        history.push(1, 100, start);
+        history.push(2, 110, start + Duration::from_millis(10));
+        history.push(3, 120, start + Duration::from_millis(20));
+
+        // Matched to its own ACK, not the latest one
+        let now = start + Duration::from_millis(25);
+        assert_eq!(history.acknowledge(2, now), Some(15_000));
+        assert_eq!(history.confirmed(), Some(110));
+
+        // ACK 1 was forgotten with ACK 2, and ACK 2 can't be confirmed twice
+        assert_eq!(history.acknowledge(1, now), None);
+        assert_eq!(history.acknowledge(2, now), None);
+        assert_eq!(history.acknowledge(3, now), Some(5_000));
+        assert_eq!(history.confirmed(), Some(120));
+    }
+}
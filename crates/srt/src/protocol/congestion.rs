@@ -0,0 +1,157 @@
+//! Sender-side congestion control: how fast data packets go out and how many may be in flight
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-5>
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+
+use crate::{
+    options::Options,
+    protocol::{
+        congestion::{file::FileCC, live::LiveCC},
+        constants::{MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE, TIMER_RESOLUTION},
+    },
+};
+
+pub mod file;
+pub mod live;
+
+/// (bytes) IPv4/UDP and SRT headers sent with every payload
+const HEADER_SIZE: usize = MAX_PACKET_SIZE - MAX_PAYLOAD_SIZE;
+
+/// Congestion control type, negotiated in the handshake (`SRT_CMD_CONGESTION`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CongestionType {
+    /// Paced at the input rate, for live streaming (`"live"`)
+    #[default]
+    Live,
+    /// Probes for the available bandwidth, for bulk transfer (`"file"`)
+    File,
+}
+
+impl FromStr for CongestionType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "live" => Ok(Self::Live),
+            "file" => Ok(Self::File),
+            _ => bail!("Unknown congestion control {s:?}"),
+        }
+    }
+}
+
+impl fmt::Display for CongestionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Live => write!(f, "live"),
+            Self::File => write!(f, "file"),
+        }
+    }
+}
+
+/// Receiver's feedback carried by a full ACK
+#[derive(Clone, Copy, Debug)]
+pub struct AckInfo {
+    /// Packets before this one are acknowledged
+    pub sequence_number: u32,
+    /// (micros)
+    pub rtt: u32,
+    /// (packets/s)
+    pub receive_rate: u32,
+    /// (packets/s)
+    pub link_capacity: u32,
+}
+
+/// Sender's pacing strategy, driven by ACK, NAK and timer events
+pub trait CongestionController: Send {
+    /// Interval between sent data packets
+    fn send_period(&self) -> Duration;
+
+    /// (packets) Most unacknowledged packets in flight
+    fn window(&self) -> u32;
+
+    /// Original data packet with `bytes` of payload sent at `now`
+    fn on_send(&mut self, now: Instant, bytes: usize);
+
+    /// Full ACK arrived at `now`
+    fn on_ack(&mut self, now: Instant, ack: &AckInfo);
+
+    /// NAK reported `lost` ranges, while `next_sequence_number` is the next one to send
+    fn on_loss(&mut self, lost: &[(u32, u32)], next_sequence_number: u32);
+
+    /// Periodic check with `in_flight` packets unacknowledged
+    fn on_timer(&mut self, now: Instant, in_flight: usize);
+}
+
+/// Controller of the negotiated `congestion` type, configured by `options`
+pub fn controller(
+    congestion: CongestionType,
+    options: &Options,
+    initial_sequence_number: u32,
+) -> Box<dyn CongestionController> {
+    match congestion {
+        CongestionType::Live => Box::new(LiveCC::new(
+            options.max_bandwidth,
+            options.input_bandwidth,
+            options.overhead,
+        )),
+        CongestionType::File => Box::new(FileCC::new(initial_sequence_number)),
+    }
+}
+
+/// Spaces data packets by the controller's send period
+pub struct Pacer {
+    next_send: Instant,
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self {
+            next_send: Instant::now(),
+        }
+    }
+
+    /// Take the next send slot for a packet at `now`: how long to wait before sending it
+    ///
+    /// Waits shorter than the timer resolution are let through, so short bursts
+    /// make up for them while the average rate is kept.
+    pub fn reserve(&mut self, now: Instant, period: Duration) -> Option<Duration> {
+        let wait = self.next_send.saturating_duration_since(now);
+        self.next_send = self.next_send.max(now) + period;
+
+        (wait >= Duration::from_micros(TIMER_RESOLUTION.into())).then_some(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacer() {
+        let mut pacer = Pacer::new();
+        let now = Instant::now();
+        let period = Duration::from_micros(400);
+
+        // Burst within the timer resolution
+        assert_eq!(pacer.reserve(now, period), None);
+        assert_eq!(pacer.reserve(now, period), None);
+        assert_eq!(pacer.reserve(now, period), None);
+        assert!(
+            pacer
+                .reserve(now, period)
+                .is_some_and(|wait| wait >= Duration::from_micros(1000))
+        );
+
+        // Idle time is not saved up
+        let later = now + Duration::from_secs(1);
+        assert_eq!(pacer.reserve(later, period), None);
+        assert_eq!(pacer.next_send, later + period);
+    }
+}
@@ -0,0 +1,251 @@
+//! File congestion control (`FileCC` of `libsrt`, after UDT's native algorithm):
+//! slow start, then AIMD on the sending period driven by ACKs and losses
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-5.2>
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use super::{AckInfo, CongestionController};
+use crate::protocol::{
+    constants::{
+        FULL_ACK_INTERVAL,
+        INITIAL_CONGESTION_WINDOW,
+        MAX_FLOW_WINDOW_SIZE,
+        MAX_PAYLOAD_SIZE,
+        RTT_INIT,
+    },
+    sequence,
+};
+
+/// (packets/s) Least increase of the sending rate per rate control interval
+const MIN_INCREASE: f64 = 0.01;
+
+/// Factor the sending period grows by on a loss
+const DECREASE_FACTOR: f64 = 1.03;
+
+/// Most further decreases within one congestion period
+const MAX_DECREASES: u32 = 5;
+
+pub struct FileCC {
+    /// (micros) Interval between sent packets
+    send_period: f64,
+    /// (packets)
+    window: f64,
+    slow_start: bool,
+    /// Loss reported since the last rate increase
+    loss: bool,
+
+    /// Acknowledged sequence number of the last rate update
+    last_ack: u32,
+    /// Time of the last rate update (or acknowledgement progress)
+    last_update: Instant,
+    /// (micros) From the peer's last full ACK
+    rtt: u32,
+    /// (packets/s) From the peer's last full ACK
+    receive_rate: u32,
+
+    /// Largest sequence number sent when the rate was last decreased
+    last_decrease_sequence: u32,
+    /// (micros) Sending period before the last decrease
+    last_decrease_period: f64,
+    /// # of NAKs in the current congestion period
+    nak_count: u32,
+    /// # of decreases in the current congestion period
+    decrease_count: u32,
+    /// Decrease every this many NAKs (spreads the decreases of competing flows)
+    decrease_random: u32,
+    /// Moving average of NAKs per congestion period
+    average_nak_count: u32,
+}
+
+impl FileCC {
+    pub fn new(initial_sequence_number: u32) -> Self {
+        Self {
+            send_period: 1.0,
+            window: INITIAL_CONGESTION_WINDOW.into(),
+            slow_start: true,
+            loss: false,
+            last_ack: initial_sequence_number,
+            last_update: Instant::now(),
+            rtt: RTT_INIT,
+            receive_rate: 0,
+            last_decrease_sequence: sequence::prev(initial_sequence_number),
+            last_decrease_period: 1.0,
+            nak_count: 0,
+            decrease_count: 0,
+            decrease_random: 1,
+            average_nak_count: 0,
+        }
+    }
+
+    /// Leave slow start, pacing at the receive rate (or the window per RTT)
+    fn end_slow_start(&mut self) {
+        self.slow_start = false;
+        self.send_period = if self.receive_rate > 0 {
+            1_000_000.0 / f64::from(self.receive_rate)
+        } else {
+            f64::from(self.rtt + FULL_ACK_INTERVAL) / self.window
+        };
+    }
+}
+
+impl CongestionController for FileCC {
+    fn send_period(&self) -> Duration {
+        Duration::from_secs_f64(self.send_period / 1_000_000.0)
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn window(&self) -> u32 {
+        self.window as u32
+    }
+
+    fn on_send(&mut self, _now: Instant, _bytes: usize) {}
+
+    #[allow(clippy::cast_precision_loss)]
+    fn on_ack(&mut self, now: Instant, ack: &AckInfo) {
+        self.rtt = ack.rtt;
+        if ack.receive_rate > 0 {
+            self.receive_rate = ack.receive_rate;
+        }
+
+        // Once per rate control interval
+        if now.saturating_duration_since(self.last_update)
+            < Duration::from_micros(FULL_ACK_INTERVAL.into())
+        {
+            return;
+        }
+        self.last_update = now;
+
+        if self.slow_start {
+            let acknowledged = sequence::offset(self.last_ack, ack.sequence_number).max(0);
+            self.window += f64::from(acknowledged);
+            self.last_ack = ack.sequence_number;
+
+            if self.window > f64::from(MAX_FLOW_WINDOW_SIZE) {
+                self.end_slow_start();
+            }
+            return;
+        }
+        self.window = f64::from(self.receive_rate) / 1_000_000.0
+            * f64::from(self.rtt + FULL_ACK_INTERVAL)
+            + f64::from(INITIAL_CONGESTION_WINDOW);
+
+        if self.loss {
+            self.loss = false;
+            return;
+        }
+
+        // Additive increase, larger the more spare capacity the link has
+        let capacity = f64::from(ack.link_capacity);
+        let mut spare = capacity - 1_000_000.0 / self.send_period;
+        if self.send_period > self.last_decrease_period && capacity / 9.0 < spare {
+            spare = capacity / 9.0;
+        }
+        let increase = if spare <= 0.0 {
+            MIN_INCREASE
+        } else {
+            let bits = spare * MAX_PAYLOAD_SIZE as f64 * 8.0;
+            (10_f64.powf(bits.log10().ceil()) * 0.000_001_5 / MAX_PAYLOAD_SIZE as f64)
+                .max(MIN_INCREASE)
+        };
+        let interval = f64::from(FULL_ACK_INTERVAL);
+        self.send_period = self.send_period * interval / (self.send_period * increase + interval);
+    }
+
+    fn on_loss(&mut self, lost: &[(u32, u32)], next_sequence_number: u32) {
+        let Some(&(first_lost, _)) = lost.first() else {
+            return;
+        };
+
+        if self.slow_start {
+            self.end_slow_start();
+            if self.receive_rate > 0 {
+                return;
+            }
+        }
+        self.loss = true;
+
+        // Multiplicative decrease, once per congestion period and then at random NAKs
+        if sequence::lt(self.last_decrease_sequence, first_lost) {
+            self.last_decrease_period = self.send_period;
+            self.send_period *= DECREASE_FACTOR;
+
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            {
+                self.average_nak_count = (f64::from(self.average_nak_count) * 0.97
+                    + f64::from(self.nak_count) * 0.03)
+                    .ceil() as u32;
+            }
+            self.nak_count = 1;
+            self.decrease_count = 1;
+            self.last_decrease_sequence = sequence::prev(next_sequence_number);
+            self.decrease_random = if self.average_nak_count > 0 {
+                rand::rng().random_range(1..=self.average_nak_count)
+            } else {
+                1
+            };
+        } else if self.decrease_count < MAX_DECREASES {
+            self.nak_count += 1;
+            if self.nak_count.is_multiple_of(self.decrease_random) {
+                self.decrease_count += 1;
+                self.send_period *= DECREASE_FACTOR;
+                self.last_decrease_sequence = sequence::prev(next_sequence_number);
+            }
+        }
+    }
+
+    fn on_timer(&mut self, now: Instant, in_flight: usize) {
+        // Nothing acknowledged for a while (retransmission timeout)
+        let timeout = Duration::from_micros(u64::from(self.rtt) * 4 + u64::from(FULL_ACK_INTERVAL));
+        if self.slow_start
+            && in_flight > 0
+            && now.saturating_duration_since(self.last_update) > timeout
+        {
+            self.end_slow_start();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_start_and_loss() {
+        let mut file = FileCC::new(0);
+        let mut now = file.last_update;
+        assert_eq!(file.window(), 16);
+
+        // Window grows by the acknowledged packets
+        now += Duration::from_millis(20);
+        let ack = AckInfo {
+            sequence_number: 100,
+            rtt: 10_000,
+            receive_rate: 10_000,
+            link_capacity: 20_000,
+        };
+        file.on_ack(now, &ack);
+        assert!(file.slow_start);
+        assert_eq!(file.window(), 116);
+
+        // First loss ends slow start at the receive rate
+        file.on_loss(&[(90, 95)], 120);
+        assert!(!file.slow_start);
+        assert_eq!(file.send_period(), Duration::from_micros(100));
+
+        // Next loss slows down
+        file.on_loss(&[(110, 110)], 130);
+        assert_eq!(file.last_decrease_sequence, 129);
+        assert!((file.send_period - 103.0).abs() < 1e-9);
+
+        // Loss period consumed by the next ACK, then the rate increases
+        now += Duration::from_millis(20);
+        file.on_ack(now, &ack);
+        assert!((file.send_period - 103.0).abs() < 1e-9);
+        now += Duration::from_millis(20);
+        file.on_ack(now, &ack);
+        assert!(file.send_period < 103.0);
+    }
+}
@@ -0,0 +1,140 @@
+//! Live congestion control (`LiveCC` of `libsrt`): paces packets at the input rate
+//! plus an overhead for retransmissions, within a maximum bandwidth
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-5.1>
+
+use std::time::{Duration, Instant};
+
+use super::{AckInfo, CongestionController, HEADER_SIZE};
+use crate::protocol::constants::{MAX_FLOW_WINDOW_SIZE, MAX_PAYLOAD_SIZE};
+
+/// Period over which the input rate is measured
+const INPUT_RATE_PERIOD: Duration = Duration::from_secs(1);
+
+pub struct LiveCC {
+    /// (bytes/s) Fixed cap (`0`: follow the input rate)
+    max_bandwidth: u64,
+    /// (bytes/s) Known input rate (`0`: measure it)
+    input_bandwidth: u64,
+    /// (%) Added to the input rate for retransmissions
+    overhead: u32,
+
+    /// (bytes) Moving average of the payload size
+    average_payload_size: f64,
+    /// (bytes/s) Measured input rate
+    measured_input: u64,
+    /// Payload bytes sent since `measure_start`
+    measured_bytes: u64,
+    measure_start: Instant,
+
+    send_period: Duration,
+}
+
+impl LiveCC {
+    pub fn new(max_bandwidth: u64, input_bandwidth: u64, overhead: u32) -> Self {
+        let mut live = Self {
+            max_bandwidth,
+            input_bandwidth,
+            overhead,
+            // 7 MPEG-TS packets
+            average_payload_size: 1316.0,
+            measured_input: 0,
+            measured_bytes: 0,
+            measure_start: Instant::now(),
+            send_period: Duration::ZERO,
+        };
+        live.update_period();
+
+        live
+    }
+
+    /// (bytes/s) Rate packets are paced at (`0`: unpaced until the input is measured)
+    fn bandwidth(&self) -> u64 {
+        if self.max_bandwidth != 0 {
+            return self.max_bandwidth;
+        }
+
+        let input = if self.input_bandwidth != 0 {
+            self.input_bandwidth
+        } else {
+            self.measured_input
+        };
+        input * (100 + u64::from(self.overhead)) / 100
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn update_period(&mut self) {
+        let bandwidth = self.bandwidth();
+
+        self.send_period = if bandwidth == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(
+                (self.average_payload_size + HEADER_SIZE as f64) / bandwidth as f64,
+            )
+        };
+    }
+}
+
+impl CongestionController for LiveCC {
+    fn send_period(&self) -> Duration {
+        self.send_period
+    }
+
+    fn window(&self) -> u32 {
+        MAX_FLOW_WINDOW_SIZE
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn on_send(&mut self, now: Instant, bytes: usize) {
+        let bytes = bytes.min(MAX_PAYLOAD_SIZE);
+        self.average_payload_size = (self.average_payload_size * 127.0 + bytes as f64) / 128.0;
+
+        self.measured_bytes += bytes as u64;
+        let elapsed = now.saturating_duration_since(self.measure_start);
+        if elapsed >= INPUT_RATE_PERIOD {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            {
+                self.measured_input = (self.measured_bytes as f64 / elapsed.as_secs_f64()) as u64;
+            }
+            self.measured_bytes = 0;
+            self.measure_start = now;
+            self.update_period();
+        }
+    }
+
+    fn on_ack(&mut self, _now: Instant, _ack: &AckInfo) {
+        self.update_period();
+    }
+
+    fn on_loss(&mut self, _lost: &[(u32, u32)], _next_sequence_number: u32) {}
+
+    fn on_timer(&mut self, _now: Instant, _in_flight: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_period() {
+        // 1316 + 44 bytes at 1.36 MB/s
+        let live = LiveCC::new(1_360_000, 0, 25);
+        assert_eq!(live.send_period(), Duration::from_millis(1));
+
+        // Input rate plus 25 %
+        let live = LiveCC::new(0, 1_088_000, 25);
+        assert_eq!(live.send_period(), Duration::from_millis(1));
+
+        // Measured input rate
+        let mut live = LiveCC::new(0, 0, 25);
+        assert_eq!(live.send_period(), Duration::ZERO);
+        let start = live.measure_start;
+        for i in 1..=1000 {
+            live.on_send(start + Duration::from_micros(1250 * i), 1316);
+        }
+        let period = live.send_period().as_secs_f64();
+        // 1316 bytes every 1.25 ms plus 25 %
+        assert!((0.00103..0.00104).contains(&period), "{period}");
+    }
+}
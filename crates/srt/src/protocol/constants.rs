@@ -15,3 +15,120 @@ pub const RTT_VAR_INIT: u32 = 50_000;
 
 /// (micros)
 pub const FULL_ACK_INTERVAL: u32 = 10_000;
+
+/// Received packets between light ACKs (in addition to the full ACK timer)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.8.1>
+pub const LIGHT_ACK_PACKETS: u32 = 64;
+
+/// (packets)
+pub const MAX_FLOW_WINDOW_SIZE: u32 = 8192;
+
+/// Sequence numbers are 31 bits wide
+pub const MAX_SEQUENCE_NUMBER: u32 = 0x7FFF_FFFF;
+
+/// SRT library version reported in `HSREQ`/`HSRSP` (`1.5.0`)
+pub const SRT_VERSION: u32 = 0x00_01_05_00;
+
+/// (micros)
+///
+/// Interval between retransmissions of a caller's handshake request
+pub const HANDSHAKE_RETRANSMIT_INTERVAL: u32 = 250_000;
+
+/// (micros)
+pub const CONNECTION_TIMEOUT: u32 = 3_000_000;
+
+/// Message numbers are 26 bits wide
+pub const MAX_MESSAGE_NUMBER: u32 = 0x03FF_FFFF;
+
+/// (bytes)
+///
+/// [`MAX_PACKET_SIZE`] without IPv4/UDP (28) and SRT (16) headers
+pub const MAX_PAYLOAD_SIZE: usize = MAX_PACKET_SIZE - 28 - 16;
+
+/// (micros)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.5>
+pub const DEFAULT_LATENCY: u32 = 120_000;
+
+/// (micros)
+///
+/// Shortest wait between timer checks
+pub const TIMER_RESOLUTION: u32 = 1_000;
+
+/// (micros)
+///
+/// Least age of an unacknowledged packet before the sender gives up on it
+/// (with too-late drop)
+pub const SEND_DROP_MIN_THRESHOLD: u32 = 1_000_000;
+
+/// (micros)
+///
+/// Shortest period of NAK reports
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.8.2>
+pub const MIN_NAK_INTERVAL: u32 = 20_000;
+
+/// (micros)
+///
+/// Interval between repeated in-band `KMREQ`s, until the peer confirms them
+pub const KM_RETRANSMIT_INTERVAL: u32 = 500_000;
+
+/// Packets sent with one encryption key before switching to the next
+pub const DEFAULT_KM_REFRESH_RATE: u32 = 1 << 24;
+
+/// Packets before a key switch to announce the next key
+/// (and after it to retire the previous one)
+pub const DEFAULT_KM_PREANNOUNCE: u32 = 1 << 12;
+
+/// (micros)
+///
+/// Time bucket of SYN cookies
+/// (a cookie is accepted in its own bucket and the following one)
+pub const SYN_COOKIE_INTERVAL: u32 = 64_000_000;
+
+/// (bytes)
+///
+/// Longest stream ID a handshake can carry
+pub const MAX_STREAM_ID_LENGTH: usize = 512;
+
+/// (bytes)
+///
+/// Longest congestion control type a handshake can carry
+pub const MAX_CONGESTION_LENGTH: usize = 512;
+
+/// (bytes)
+///
+/// Longest packet filter configuration a handshake can carry
+pub const MAX_FILTER_LENGTH: usize = 512;
+
+/// (micros)
+///
+/// Silence after which this side sends a keepalive, so the peer does not time out
+pub const KEEPALIVE_INTERVAL: u32 = 1_000_000;
+
+/// (micros)
+///
+/// Default time without any packet from the peer before the connection is closed
+pub const DEFAULT_PEER_IDLE_TIMEOUT: u32 = 5_000_000;
+
+/// (bytes/s)
+///
+/// Default cap of the live sending rate (1 Gbit/s)
+pub const DEFAULT_MAX_BANDWIDTH: u64 = 125_000_000;
+
+/// (%)
+///
+/// Default bandwidth on top of the input rate kept for retransmissions
+pub const DEFAULT_OVERHEAD: u32 = 25;
+
+/// (packets)
+///
+/// Congestion window of file transfers when slow start begins
+pub const INITIAL_CONGESTION_WINDOW: u32 = 16;
+
+/// (micros)
+///
+/// Silence of a main/backup member since it was sent data after which another
+/// member takes over
+pub const GROUP_STABILITY_TIMEOUT: u32 = 60_000;
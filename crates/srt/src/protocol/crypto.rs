@@ -0,0 +1,619 @@
+//! Payload encryption with Stream Encrypting Keys (SEK) exchanged in `KMREQ`/`KMRSP`
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-6>
+
+use std::time::{Duration, Instant};
+
+use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm, AesGcm, KeyInit, Nonce, Tag, aead::consts::U12};
+use aes_kw::{KekAes128, KekAes192, KekAes256};
+use anyhow::{Result, anyhow, bail};
+use ctr::{
+    Ctr128BE,
+    cipher::{KeyIvInit, StreamCipher},
+};
+use sha1::Sha1;
+
+use crate::protocol::{
+    constants::KM_RETRANSMIT_INTERVAL,
+    packet::{
+        control::handshake::extension::{
+            extension_types,
+            key_material::{Cipher, KeyBasedEncryption, KeyMaterialExtension},
+        },
+        data::{DataPacketInfo, EncryptionFlag},
+    },
+};
+
+/// (bytes)
+const SALT_LENGTH: usize = 16;
+/// (bytes) Authentication tag appended to AES-GCM payloads
+const GCM_TAG_LENGTH: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 2048;
+
+/// `PT` of a Key Material message
+const PACKET_TYPE_KM: u8 = 2;
+/// `SE` for SRT (MPEG-TS/SRT stream encapsulation)
+const STREAM_ENCAPSULATION_SRT: u8 = 2;
+
+/// Passphrase length limits (characters)
+const PASSPHRASE_LENGTH: std::ops::RangeInclusive<usize> = 10..=79;
+
+/// Key refresh schedule (packets)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-6.1.6>
+#[derive(Clone, Copy, Debug)]
+pub struct Refresh {
+    /// Packets sent with a key before switching to the next one
+    pub rate: u32,
+    /// Packets before a switch to announce the next key and after it to retire the old one
+    pub preannounce: u32,
+}
+
+pub struct Crypto {
+    passphrase: String,
+    /// [`Cipher::AesCtr`] or [`Cipher::AesGcm`]
+    cipher: Cipher,
+    salt: Vec<u8>,
+    /// (bytes) Length of every key
+    key_length: usize,
+    even_key: Option<Vec<u8>>,
+    odd_key: Option<Vec<u8>>,
+    /// Key used for sending
+    active: EncryptionFlag,
+
+    /// `None` if the peer owns the keys
+    refresh: Option<Refresh>,
+    /// Packets sent with the active key
+    sent: u32,
+    /// Key material the peer has not confirmed yet, with time of the last `KMREQ`
+    pending: Option<(Option<Instant>, KeyMaterialExtension)>,
+}
+
+impl Crypto {
+    /// Fresh random salt and even key, `key_length` of 16, 24 or 32 bytes
+    pub fn generate(
+        passphrase: &str,
+        cipher: Cipher,
+        key_length: usize,
+        refresh: Refresh,
+    ) -> Result<Self> {
+        if !matches!(cipher, Cipher::AesCtr | Cipher::AesGcm) {
+            bail!("Unsupported cipher: {cipher:?}");
+        }
+        if ![16, 24, 32].contains(&key_length) {
+            bail!("Invalid key length: {key_length}");
+        }
+        if refresh.preannounce == 0 || refresh.preannounce > refresh.rate / 2 {
+            bail!("Key preannounce must be within 1 and half of the refresh rate");
+        }
+
+        let mut salt = vec![0; SALT_LENGTH];
+        rand::fill(&mut salt[..]);
+
+        Ok(Self {
+            passphrase: passphrase.to_owned(),
+            cipher,
+            salt,
+            key_length,
+            even_key: Some(random_key(key_length)),
+            odd_key: None,
+            active: EncryptionFlag::EvenKey,
+            refresh: Some(refresh),
+            sent: 0,
+            pending: None,
+        })
+    }
+
+    /// Unwrap keys sent by the peer
+    pub fn from_key_material(
+        key_material: &KeyMaterialExtension,
+        passphrase: &str,
+    ) -> Result<Self> {
+        if !matches!(key_material.cipher, Cipher::AesCtr | Cipher::AesGcm) {
+            bail!("Unsupported cipher: {:?}", key_material.cipher);
+        }
+
+        let kek = kek(passphrase, &key_material.salt, key_material.key_length)?;
+        let keys = unwrap(&kek, &key_material.wrapped_key)?;
+        let (first, second) = keys.split_at(key_material.key_length);
+
+        let (even_key, odd_key, active) = match key_material.key_based_encryption {
+            KeyBasedEncryption::EvenKey => (Some(first.to_vec()), None, EncryptionFlag::EvenKey),
+            KeyBasedEncryption::OddKey => (None, Some(first.to_vec()), EncryptionFlag::OddKey),
+            KeyBasedEncryption::Both => (
+                Some(first.to_vec()),
+                Some(second.to_vec()),
+                EncryptionFlag::EvenKey,
+            ),
+        };
+
+        Ok(Self {
+            passphrase: passphrase.to_owned(),
+            cipher: key_material.cipher,
+            salt: key_material.salt.clone(),
+            key_length: key_material.key_length,
+            even_key,
+            odd_key,
+            active,
+            refresh: None,
+            sent: 0,
+            pending: None,
+        })
+    }
+
+    /// Keys wrapped for the peer, as extension of `type` (`KMREQ` or `KMRSP`)
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn key_material(&self, r#type: u16) -> Result<KeyMaterialExtension> {
+        let (key_based_encryption, keys) = match (&self.even_key, &self.odd_key) {
+            (Some(even), Some(odd)) => (KeyBasedEncryption::Both, [&even[..], &odd[..]].concat()),
+            (Some(even), None) => (KeyBasedEncryption::EvenKey, even.clone()),
+            (None, Some(odd)) => (KeyBasedEncryption::OddKey, odd.clone()),
+            (None, None) => bail!("No keys to share"),
+        };
+
+        let kek = kek(&self.passphrase, &self.salt, self.key_length)?;
+        let wrapped_key = wrap(&kek, &keys)?;
+
+        Ok(KeyMaterialExtension {
+            r#type,
+            length: ((16 + self.salt.len() + wrapped_key.len()) / 4) as u16,
+            packet_type: PACKET_TYPE_KM,
+            key_based_encryption,
+            keki: 0,
+            cipher: self.cipher,
+            auth: u8::from(self.cipher == Cipher::AesGcm),
+            stream_encapsulation: STREAM_ENCAPSULATION_SRT,
+            salt: self.salt.clone(),
+            key_length: self.key_length,
+            wrapped_key,
+        })
+    }
+
+    /// Take over keys refreshed by the peer (in-band `KMREQ`)
+    ///
+    /// While both keys are announced the active one is kept
+    pub fn update(&mut self, key_material: &KeyMaterialExtension) -> Result<()> {
+        let update = Self::from_key_material(key_material, &self.passphrase)?;
+
+        if key_material.key_based_encryption != KeyBasedEncryption::Both {
+            self.active = update.active;
+        }
+        self.even_key = update.even_key;
+        self.odd_key = update.odd_key;
+
+        Ok(())
+    }
+
+    /// Peer confirmed key material (`KMRSP`)
+    pub fn confirm(&mut self, key_material: &KeyMaterialExtension) {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|(_, pending)| pending.wrapped_key == key_material.wrapped_key)
+        {
+            self.pending = None;
+        }
+    }
+
+    /// Key material to (re)send at `now` as `KMREQ`, until the peer confirms it
+    pub fn announcement(&mut self, now: Instant) -> Option<KeyMaterialExtension> {
+        let (sent, key_material) = self.pending.as_mut()?;
+
+        let interval = Duration::from_micros(KM_RETRANSMIT_INTERVAL.into());
+        if sent.is_some_and(|sent| now.saturating_duration_since(sent) < interval) {
+            return None;
+        }
+        *sent = Some(now);
+
+        Some(key_material.clone())
+    }
+
+    /// Encrypt `data` in place with the active key
+    ///
+    /// New packets advance the refresh schedule
+    pub fn encrypt(&mut self, data: &mut DataPacketInfo) -> Result<()> {
+        let key = match self.active {
+            EncryptionFlag::OddKey => self.odd_key.as_deref(),
+            _ => self.even_key.as_deref(),
+        }
+        .ok_or_else(|| anyhow!("Missing {:?}", self.active))?;
+
+        data.encryption = self.active;
+        match self.cipher {
+            Cipher::AesGcm => seal(
+                key,
+                &self.gcm_iv(data.packet_sequence_number),
+                &aad(data),
+                &mut data.content,
+            )?,
+            _ => apply_keystream(
+                key,
+                &self.ctr_iv(data.packet_sequence_number),
+                &mut data.content,
+            )?,
+        }
+
+        if !data.retransmitted {
+            self.advance()?;
+        }
+
+        Ok(())
+    }
+
+    /// Announce, switch to and retire keys as [`Refresh`] prescribes
+    fn advance(&mut self) -> Result<()> {
+        let Some(refresh) = self.refresh else {
+            return Ok(());
+        };
+        self.sent += 1;
+
+        let inactive = match self.active {
+            EncryptionFlag::OddKey => &mut self.even_key,
+            _ => &mut self.odd_key,
+        };
+
+        if self.sent >= refresh.rate {
+            self.active = match self.active {
+                EncryptionFlag::OddKey => EncryptionFlag::EvenKey,
+                _ => EncryptionFlag::OddKey,
+            };
+            self.sent = 0;
+            tracing::debug!("Switched to {:?}", self.active);
+            return Ok(());
+        } else if self.sent == refresh.preannounce && inactive.is_some() {
+            tracing::debug!("Retiring the previous key");
+            *inactive = None;
+        } else if self.sent == refresh.rate - refresh.preannounce {
+            tracing::debug!("Announcing the next key");
+            *inactive = Some(random_key(self.key_length));
+        } else {
+            return Ok(());
+        }
+
+        self.pending = Some((None, self.key_material(extension_types::KMREQ)?));
+
+        Ok(())
+    }
+
+    /// Decrypt `data` in place with the key selected by its [`EncryptionFlag`]
+    ///
+    /// With [`Cipher::AesGcm`] fails on forged or corrupted packets
+    pub fn decrypt(&self, data: &mut DataPacketInfo) -> Result<()> {
+        let key = match data.encryption {
+            EncryptionFlag::NoEncryption => return Ok(()),
+            EncryptionFlag::EvenKey => self.even_key.as_deref(),
+            EncryptionFlag::OddKey => self.odd_key.as_deref(),
+        }
+        .ok_or_else(|| anyhow!("Missing {:?}", data.encryption))?;
+
+        match self.cipher {
+            Cipher::AesGcm => open(
+                key,
+                &self.gcm_iv(data.packet_sequence_number),
+                &aad(data),
+                &mut data.content,
+            )?,
+            _ => apply_keystream(
+                key,
+                &self.ctr_iv(data.packet_sequence_number),
+                &mut data.content,
+            )?,
+        }
+        data.encryption = EncryptionFlag::NoEncryption;
+
+        Ok(())
+    }
+
+    /// (bytes) Added to every payload by encryption
+    pub fn overhead(&self) -> usize {
+        match self.cipher {
+            Cipher::AesGcm => GCM_TAG_LENGTH,
+            _ => 0,
+        }
+    }
+
+    /// `IV = MSB(112, Salt) XOR (Packet Index << 16)`
+    fn ctr_iv(&self, packet_sequence_number: u32) -> [u8; 16] {
+        let mut iv = [0; 16];
+        iv[10..14].copy_from_slice(&packet_sequence_number.to_be_bytes());
+
+        for (byte, salt) in iv[..14].iter_mut().zip(&self.salt) {
+            *byte ^= salt;
+        }
+
+        iv
+    }
+
+    /// `IV = MSB(96, Salt) XOR Packet Index`
+    fn gcm_iv(&self, packet_sequence_number: u32) -> [u8; 12] {
+        let mut iv = [0; 12];
+        iv[8..12].copy_from_slice(&packet_sequence_number.to_be_bytes());
+
+        for (byte, salt) in iv.iter_mut().zip(&self.salt) {
+            *byte ^= salt;
+        }
+
+        iv
+    }
+}
+
+/// Authenticated header words (`Packet Sequence Number` and `Message Number`),
+/// without the `R` flag that retransmission changes
+fn aad(data: &DataPacketInfo) -> Vec<u8> {
+    DataPacketInfo {
+        packet_sequence_number: data.packet_sequence_number,
+        position: data.position,
+        order: data.order,
+        encryption: data.encryption,
+        retransmitted: false,
+        message_number: data.message_number,
+        content: Vec::new(),
+    }
+    .raw_header()
+}
+
+fn random_key(key_length: usize) -> Vec<u8> {
+    let mut key = vec![0; key_length];
+    rand::fill(&mut key[..]);
+    key
+}
+
+/// Key Encrypting Key, `PBKDF2(passphrase, LSB(64, Salt), 2048, key_length)`
+fn kek(passphrase: &str, salt: &[u8], key_length: usize) -> Result<Vec<u8>> {
+    if !PASSPHRASE_LENGTH.contains(&passphrase.len()) {
+        bail!("Passphrase must be 10 to 79 characters long");
+    }
+
+    let mut kek = vec![0; key_length];
+    pbkdf2::pbkdf2_hmac::<Sha1>(
+        passphrase.as_bytes(),
+        &salt[salt.len().saturating_sub(8)..],
+        PBKDF2_ITERATIONS,
+        &mut kek,
+    );
+
+    Ok(kek)
+}
+
+/// AES key wrap (RFC 3394)
+fn wrap(kek: &[u8], keys: &[u8]) -> Result<Vec<u8>> {
+    let wrapped = match kek.len() {
+        16 => KekAes128::from(<[u8; 16]>::try_from(kek)?).wrap_vec(keys),
+        24 => KekAes192::from(<[u8; 24]>::try_from(kek)?).wrap_vec(keys),
+        32 => KekAes256::from(<[u8; 32]>::try_from(kek)?).wrap_vec(keys),
+        n => bail!("Invalid key length: {n}"),
+    };
+
+    wrapped.map_err(|e| anyhow!("Failed to wrap keys: {e}"))
+}
+
+/// Reverse of [`wrap`], fails on a wrong passphrase
+fn unwrap(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>> {
+    let keys = match kek.len() {
+        16 => KekAes128::from(<[u8; 16]>::try_from(kek)?).unwrap_vec(wrapped),
+        24 => KekAes192::from(<[u8; 24]>::try_from(kek)?).unwrap_vec(wrapped),
+        32 => KekAes256::from(<[u8; 32]>::try_from(kek)?).unwrap_vec(wrapped),
+        n => bail!("Invalid key length: {n}"),
+    };
+
+    keys.map_err(|_| anyhow!("Failed to unwrap keys (wrong passphrase)"))
+}
+
+fn apply_keystream(key: &[u8], iv: &[u8; 16], content: &mut [u8]) -> Result<()> {
+    let invalid = |_| anyhow!("Invalid key length: {}", key.len());
+
+    match key.len() {
+        16 => Ctr128BE::<Aes128>::new_from_slices(key, iv)
+            .map_err(invalid)?
+            .apply_keystream(content),
+        24 => Ctr128BE::<Aes192>::new_from_slices(key, iv)
+            .map_err(invalid)?
+            .apply_keystream(content),
+        32 => Ctr128BE::<Aes256>::new_from_slices(key, iv)
+            .map_err(invalid)?
+            .apply_keystream(content),
+        n => bail!("Invalid key length: {n}"),
+    }
+
+    Ok(())
+}
+
+/// AES-GCM encryption, appending the authentication tag to `content`
+fn seal(key: &[u8], iv: &[u8; 12], aad: &[u8], content: &mut Vec<u8>) -> Result<()> {
+    let tag = match key.len() {
+        16 => seal_with::<Aes128Gcm>(key, iv, aad, content),
+        24 => seal_with::<AesGcm<Aes192, U12>>(key, iv, aad, content),
+        32 => seal_with::<Aes256Gcm>(key, iv, aad, content),
+        n => bail!("Invalid key length: {n}"),
+    }?;
+    content.extend(tag);
+
+    Ok(())
+}
+
+fn seal_with<A: AeadInPlace<NonceSize = U12> + KeyInit>(
+    key: &[u8],
+    iv: &[u8; 12],
+    aad: &[u8],
+    content: &mut [u8],
+) -> Result<Tag> {
+    A::new_from_slice(key)
+        .map_err(|_| anyhow!("Invalid key length: {}", key.len()))?
+        .encrypt_in_place_detached(Nonce::<U12>::from_slice(iv), aad, content)
+        .map_err(|_| anyhow!("Failed to encrypt"))
+        .map(|tag| Tag::clone_from_slice(&tag))
+}
+
+/// AES-GCM decryption, verifying and removing the authentication tag of `content`
+fn open(key: &[u8], iv: &[u8; 12], aad: &[u8], content: &mut Vec<u8>) -> Result<()> {
+    let Some(split) = content.len().checked_sub(GCM_TAG_LENGTH) else {
+        bail!("Payload shorter than the authentication tag");
+    };
+    let tag = Tag::clone_from_slice(&content[split..]);
+    content.truncate(split);
+
+    match key.len() {
+        16 => open_with::<Aes128Gcm>(key, iv, aad, content, &tag),
+        24 => open_with::<AesGcm<Aes192, U12>>(key, iv, aad, content, &tag),
+        32 => open_with::<Aes256Gcm>(key, iv, aad, content, &tag),
+        n => bail!("Invalid key length: {n}"),
+    }
+}
+
+fn open_with<A: AeadInPlace<NonceSize = U12> + KeyInit>(
+    key: &[u8],
+    iv: &[u8; 12],
+    aad: &[u8],
+    content: &mut [u8],
+    tag: &Tag,
+) -> Result<()> {
+    A::new_from_slice(key)
+        .map_err(|_| anyhow!("Invalid key length: {}", key.len()))?
+        .decrypt_in_place_detached(
+            Nonce::<U12>::from_slice(iv),
+            aad,
+            content,
+            aes_gcm::aead::generic_array::GenericArray::from_slice(tag),
+        )
+        .map_err(|_| anyhow!("Authentication failed (forged or corrupted packet)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::packet::data::PacketPosition;
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    fn packet(packet_sequence_number: u32, content: &[u8]) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number,
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: 1,
+            content: content.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_key_material_exchange() -> Result<()> {
+        let refresh = Refresh {
+            rate: 1 << 24,
+            preannounce: 1 << 12,
+        };
+        let mut sender = Crypto::generate(PASSPHRASE, Cipher::AesCtr, 24, refresh)?;
+        let key_material = sender.key_material(extension_types::KMREQ)?;
+
+        let raw = key_material.to_raw();
+        assert_eq!(raw.len(), 4 + 4 * key_material.length as usize);
+
+        let parsed = KeyMaterialExtension::from_raw(&raw)?;
+        assert_eq!(parsed.wrapped_key, key_material.wrapped_key);
+        assert!(Crypto::from_key_material(&parsed, "wrong passphrase").is_err());
+
+        let receiver = Crypto::from_key_material(&parsed, PASSPHRASE)?;
+
+        let plain = b"payload of a data packet";
+        let mut data = packet(12345, plain);
+
+        sender.encrypt(&mut data)?;
+        assert_eq!(data.encryption, EncryptionFlag::EvenKey);
+        assert_ne!(data.content, plain);
+
+        receiver.decrypt(&mut data)?;
+        assert_eq!(data.content, plain);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_rotation() -> Result<()> {
+        let refresh = Refresh {
+            rate: 8,
+            preannounce: 2,
+        };
+        let mut sender = Crypto::generate(PASSPHRASE, Cipher::AesCtr, 16, refresh)?;
+        let mut receiver =
+            Crypto::from_key_material(&sender.key_material(extension_types::KMREQ)?, PASSPHRASE)?;
+        let now = Instant::now();
+
+        let mut flags = Vec::new();
+        for seq in 0..20 {
+            let mut data = packet(seq, b"payload");
+            sender.encrypt(&mut data)?;
+            flags.push(data.encryption);
+
+            if let Some(key_material) = sender.announcement(now) {
+                let raw = key_material.raw_message();
+                let key_material =
+                    KeyMaterialExtension::from_raw_message(extension_types::KMREQ, &raw)?;
+
+                receiver.update(&key_material)?;
+                sender.confirm(&key_material);
+            }
+
+            receiver.decrypt(&mut data)?;
+            assert_eq!(data.content, b"payload");
+        }
+
+        use EncryptionFlag::{EvenKey, OddKey};
+        assert_eq!(flags[..8], [EvenKey; 8]);
+        assert_eq!(flags[8..16], [OddKey; 8]);
+        assert_eq!(flags[16..], [EvenKey; 4]);
+
+        // Previous (even) key retired 2 packets after the switch
+        assert!(receiver.even_key.is_some() && receiver.odd_key.is_none());
+        assert!(sender.announcement(now).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gcm_authentication() -> Result<()> {
+        let refresh = Refresh {
+            rate: 1 << 24,
+            preannounce: 1 << 12,
+        };
+        let mut sender = Crypto::generate(PASSPHRASE, Cipher::AesGcm, 32, refresh)?;
+        let key_material = sender.key_material(extension_types::KMREQ)?;
+        assert_eq!(key_material.auth, 1);
+
+        let parsed = KeyMaterialExtension::from_raw(&key_material.to_raw())?;
+        assert_eq!(parsed.cipher, Cipher::AesGcm);
+        let receiver = Crypto::from_key_material(&parsed, PASSPHRASE)?;
+
+        let plain = b"payload of a data packet";
+        let mut data = packet(12345, plain);
+        sender.encrypt(&mut data)?;
+        assert_eq!(data.content.len(), plain.len() + sender.overhead());
+
+        // Retransmission flag is not authenticated
+        let mut retransmitted = DataPacketInfo {
+            retransmitted: true,
+            ..data.clone()
+        };
+        receiver.decrypt(&mut retransmitted)?;
+        assert_eq!(retransmitted.content, plain);
+
+        let mut forged = data.clone();
+        forged.content[0] ^= 1;
+        assert!(receiver.decrypt(&mut forged).is_err());
+
+        let mut forged = DataPacketInfo {
+            message_number: 2,
+            ..data.clone()
+        };
+        assert!(receiver.decrypt(&mut forged).is_err());
+
+        let mut truncated = DataPacketInfo {
+            content: data.content[..8].to_vec(),
+            ..data
+        };
+        assert!(receiver.decrypt(&mut truncated).is_err());
+
+        Ok(())
+    }
+}
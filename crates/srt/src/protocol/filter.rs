@@ -0,0 +1,111 @@
+//! Packet filters, negotiated in the handshake (`SRT_CMD_FILTER`)
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/packet-filtering-and-fec.md>
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, bail};
+
+pub mod fec;
+
+/// Filter name followed by `key:value` parameters
+/// (e.g. `fec,cols:10,rows:5,layout:staircase,arq:onreq`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterConfig {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl FilterConfig {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Combine with the peer's configuration: the same filter, with parameters set by
+    /// either side (those set by both must agree)
+    pub fn merge(&self, peer: &Self) -> anyhow::Result<Self> {
+        if self.name != peer.name {
+            bail!(
+                "Peer uses packet filter {:?} instead of {:?}",
+                peer.name,
+                self.name
+            );
+        }
+
+        let mut params = self.params.clone();
+        for (key, value) in &peer.params {
+            match self.get(key) {
+                Some(own) if own != value => {
+                    bail!("Peer sets packet filter {key:?} to {value:?} instead of {own:?}")
+                }
+                Some(_) => (),
+                None => params.push((key.clone(), value.clone())),
+            }
+        }
+
+        Ok(Self {
+            name: self.name.clone(),
+            params,
+        })
+    }
+}
+
+impl FromStr for FilterConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut items = s.split(',').map(str::trim);
+
+        let name = items.next().unwrap_or_default();
+        if name.is_empty() {
+            bail!("Missing packet filter name");
+        }
+        let params = items
+            .map(|item| {
+                item.split_once(':')
+                    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                    .with_context(|| format!("Invalid packet filter parameter {item:?}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            params,
+        })
+    }
+}
+
+impl fmt::Display for FilterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (key, value) in &self.params {
+            write!(f, ",{key}:{value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() -> anyhow::Result<()> {
+        let own: FilterConfig = "fec,cols:10,arq:never".parse()?;
+        let peer: FilterConfig = "fec, cols:10, rows:5".parse()?;
+        assert_eq!(
+            own.merge(&peer)?.to_string(),
+            "fec,cols:10,arq:never,rows:5"
+        );
+
+        assert!(own.merge(&"fec,cols:8".parse()?).is_err());
+        assert!(own.merge(&"other,cols:10".parse()?).is_err());
+        assert!("fec,cols".parse::<FilterConfig>().is_err());
+
+        Ok(())
+    }
+}
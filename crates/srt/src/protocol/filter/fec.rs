@@ -0,0 +1,578 @@
+//! Built-in `fec` filter: XOR parity over the rows and columns of a packet matrix
+//!
+//! Parity packets are data packets with `Message Number` 0, carrying the sequence number
+//! of the last packet of their group and the group's XOR of timestamps. Their payload
+//! starts with the column index (`0xFF` for a row), the XOR of the encryption flags
+//! and the XOR of the payload lengths, followed by the XOR of the payloads.
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/packet-filtering-and-fec.md>
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, bail};
+
+use super::FilterConfig;
+use crate::protocol::{
+    packet::data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+    sequence,
+};
+
+/// `Message Number` of parity packets
+pub const PARITY_MESSAGE_NUMBER: u32 = 0;
+
+/// Column index of row parity
+const ROW_INDEX: u8 = 0xFF;
+
+/// (bytes) Column index, flags and length before the payload XOR
+const PARITY_HEADER_SIZE: usize = 4;
+
+/// Matrices kept by the receiver to rebuild packets from late parity
+const RECEIVE_MATRICES: i64 = 3;
+
+/// Arrangement of column groups
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Column groups cover the same rows
+    Even,
+    /// Column groups start one row apart, spreading their parity packets over time
+    Staircase,
+}
+
+/// When the receiver asks for retransmission of lost packets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arq {
+    /// Right away, while FEC works in parallel
+    Always,
+    /// Only for packets not rebuilt by the next periodic NAK report
+    OnReq,
+    /// Never, losses are up to FEC alone
+    Never,
+}
+
+/// `fec` filter parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FecConfig {
+    /// Packets per row (`cols`)
+    pub cols: u32,
+    /// Rows per column group (`rows`, `1` for rows only)
+    pub rows: u32,
+    /// Send row parity (negative `rows` for columns only)
+    pub row_groups: bool,
+    pub layout: Layout,
+    pub arq: Arq,
+}
+
+impl FecConfig {
+    fn column_groups(&self) -> bool {
+        self.rows > 1
+    }
+
+    /// Row at which the column groups of `col` start
+    fn stagger(&self, col: i64) -> i64 {
+        match self.layout {
+            Layout::Even => 0,
+            Layout::Staircase => col % i64::from(self.rows),
+        }
+    }
+
+    /// Groups the packet at `offset` belongs to
+    fn groups(&self, offset: i64) -> Vec<Group> {
+        let cols = i64::from(self.cols);
+        let (row, col) = (offset.div_euclid(cols), offset.rem_euclid(cols));
+
+        let mut groups = Vec::new();
+        if self.row_groups {
+            groups.push(Group::Row(row));
+        }
+        if self.column_groups() {
+            let index = (row - self.stagger(col)).div_euclid(self.rows.into());
+            groups.push(Group::Column(col, index));
+        }
+
+        groups
+    }
+
+    /// First row and column of `group`, with the step (in offsets) to its next packet
+    fn layout(&self, group: Group) -> (i64, i64, i64) {
+        let cols = i64::from(self.cols);
+        match group {
+            Group::Row(row) => (row * cols, 1, cols),
+            Group::Column(col, index) => (
+                (index * i64::from(self.rows) + self.stagger(col)) * cols + col,
+                cols,
+                self.rows.into(),
+            ),
+        }
+    }
+
+    /// Offsets of `group`'s packets (those before the first packet don't exist)
+    fn members(&self, group: Group) -> Vec<i64> {
+        let (first, step, count) = self.layout(group);
+
+        (0..count)
+            .map(|i| first + i * step)
+            .filter(|offset| *offset >= 0)
+            .collect()
+    }
+
+    /// Offset of `group`'s last packet, which its parity is sent after
+    fn last(&self, group: Group) -> i64 {
+        let (first, step, count) = self.layout(group);
+
+        first + (count - 1) * step
+    }
+
+    /// Group of a parity packet for column `index` sent after the packet at `offset`
+    fn parity_group(&self, offset: i64, index: u8) -> Option<Group> {
+        let cols = i64::from(self.cols);
+        let (row, col) = (offset.div_euclid(cols), offset.rem_euclid(cols));
+
+        let group = if index == ROW_INDEX {
+            self.row_groups.then_some(Group::Row(row))?
+        } else {
+            if !self.column_groups() || i64::from(index) != col {
+                return None;
+            }
+            let first_row = row - i64::from(self.rows) + 1;
+            Group::Column(
+                col,
+                (first_row - self.stagger(col)).div_euclid(self.rows.into()),
+            )
+        };
+
+        (self.last(group) == offset).then_some(group)
+    }
+}
+
+impl TryFrom<&FilterConfig> for FecConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(config: &FilterConfig) -> Result<Self, Self::Error> {
+        if config.name != "fec" {
+            bail!("Unsupported packet filter {:?}", config.name);
+        }
+        if let Some((key, _)) = config
+            .params
+            .iter()
+            .find(|(key, _)| !["cols", "rows", "layout", "arq"].contains(&key.as_str()))
+        {
+            bail!("Unknown FEC parameter {key:?}");
+        }
+
+        let cols: u32 = config
+            .get("cols")
+            .context("Missing FEC cols")?
+            .parse()
+            .context("Invalid FEC cols")?;
+        if !(1..u32::from(ROW_INDEX)).contains(&cols) {
+            bail!("FEC cols must be 1 to {}", ROW_INDEX - 1);
+        }
+        let rows: i32 = config
+            .get("rows")
+            .map_or(Ok(1), str::parse)
+            .context("Invalid FEC rows")?;
+        if rows == 0 || rows.unsigned_abs() >= u32::from(ROW_INDEX) {
+            bail!("FEC rows must be 1 to {0} or -2 to -{0}", ROW_INDEX - 1);
+        }
+        if rows < 0 && rows > -2 {
+            bail!("FEC without rows needs at least 2 rows per column");
+        }
+
+        Ok(Self {
+            cols,
+            rows: rows.unsigned_abs(),
+            row_groups: rows > 0,
+            layout: match config.get("layout") {
+                None | Some("staircase") => Layout::Staircase,
+                Some("even") => Layout::Even,
+                Some(layout) => bail!("Unknown FEC layout {layout:?}"),
+            },
+            arq: match config.get("arq") {
+                None | Some("onreq") => Arq::OnReq,
+                Some("always") => Arq::Always,
+                Some("never") => Arq::Never,
+                Some(arq) => bail!("Unknown FEC ARQ mode {arq:?}"),
+            },
+        })
+    }
+}
+
+/// Row, or column with its index among the column's groups
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Group {
+    Row(i64),
+    Column(i64, i64),
+}
+
+/// XOR of a group's packets
+#[derive(Clone, Debug, Default)]
+struct Clip {
+    timestamp: u32,
+    flags: u8,
+    length: u16,
+    payload: Vec<u8>,
+}
+
+impl Clip {
+    #[allow(clippy::cast_possible_truncation)]
+    fn add(&mut self, timestamp: u32, data: &DataPacketInfo) {
+        self.timestamp ^= timestamp;
+        self.flags ^= match data.encryption {
+            EncryptionFlag::NoEncryption => 0,
+            EncryptionFlag::EvenKey => 1,
+            EncryptionFlag::OddKey => 2,
+        };
+        self.length ^= data.content.len() as u16;
+
+        if self.payload.len() < data.content.len() {
+            self.payload.resize(data.content.len(), 0);
+        }
+        for (clip, byte) in self.payload.iter_mut().zip(&data.content) {
+            *clip ^= byte;
+        }
+    }
+
+    /// Parity packet for column `index` (or [`ROW_INDEX`]) sent after `sequence_number`
+    fn parity(&self, index: u8, sequence_number: u32) -> (u32, DataPacketInfo) {
+        let mut content = vec![index, self.flags];
+        content.extend(self.length.to_be_bytes());
+        content.extend(&self.payload);
+
+        let parity = DataPacketInfo {
+            packet_sequence_number: sequence_number,
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: PARITY_MESSAGE_NUMBER,
+            content,
+        };
+
+        (self.timestamp, parity)
+    }
+
+    /// Column index and clip of a parity packet
+    fn from_parity(timestamp: u32, parity: &DataPacketInfo) -> Option<(u8, Self)> {
+        let header = parity.content.get(..PARITY_HEADER_SIZE)?;
+        let clip = Self {
+            timestamp,
+            flags: header[1],
+            length: u16::from_be_bytes([header[2], header[3]]),
+            payload: parity.content[PARITY_HEADER_SIZE..].to_vec(),
+        };
+
+        Some((header[0], clip))
+    }
+
+    /// The one packet missing from the clip (`None` if the clip is inconsistent)
+    fn rebuild(mut self, sequence_number: u32) -> Option<(u32, DataPacketInfo)> {
+        let encryption = match self.flags {
+            0 => EncryptionFlag::NoEncryption,
+            1 => EncryptionFlag::EvenKey,
+            2 => EncryptionFlag::OddKey,
+            _ => return None,
+        };
+        if usize::from(self.length) > self.payload.len() {
+            return None;
+        }
+        self.payload.truncate(self.length.into());
+
+        let data = DataPacketInfo {
+            packet_sequence_number: sequence_number,
+            position: PacketPosition::Single,
+            order: false,
+            encryption,
+            retransmitted: false,
+            message_number: 1,
+            content: self.payload,
+        };
+
+        Some((self.timestamp, data))
+    }
+}
+
+/// Maps sequence numbers to matrix offsets, which don't wrap around
+#[derive(Clone, Copy)]
+struct Offsets {
+    /// Newest sequence number with its offset
+    sequence_number: u32,
+    offset: i64,
+}
+
+impl Offsets {
+    fn get(&self, sequence_number: u32) -> i64 {
+        self.offset + i64::from(sequence::offset(self.sequence_number, sequence_number))
+    }
+
+    /// Make `sequence_number` the newest, if it is
+    fn advance(&mut self, sequence_number: u32) {
+        let offset = self.get(sequence_number);
+        if offset > self.offset {
+            *self = Self {
+                sequence_number,
+                offset,
+            };
+        }
+    }
+}
+
+/// FEC of a connection: parity for sent packets and recovery of received ones
+pub struct Fec {
+    config: FecConfig,
+
+    sent: Offsets,
+    /// Parity of the groups being sent
+    sending: HashMap<Group, Clip>,
+
+    received: Offsets,
+    /// Recently received (and rebuilt) packets by offset, with their timestamps
+    packets: BTreeMap<i64, (u32, DataPacketInfo)>,
+    /// Received parity whose group is still incomplete
+    parity: HashMap<Group, Clip>,
+}
+
+impl Fec {
+    /// Matrices of both directions start at `initial_sequence_number`
+    pub fn new(config: FecConfig, initial_sequence_number: u32) -> Self {
+        let start = Offsets {
+            sequence_number: initial_sequence_number,
+            offset: 0,
+        };
+
+        Self {
+            config,
+            sent: start,
+            sending: HashMap::new(),
+            received: start,
+            packets: BTreeMap::new(),
+            parity: HashMap::new(),
+        }
+    }
+
+    pub fn arq(&self) -> Arq {
+        self.config.arq
+    }
+
+    /// Parity packets (`(timestamp, packet)`) due after sending `data` for the first time
+    /// (already encrypted) with `timestamp`
+    pub fn sent(&mut self, timestamp: u32, data: &DataPacketInfo) -> Vec<(u32, DataPacketInfo)> {
+        let offset = self.sent.get(data.packet_sequence_number);
+        self.sent.advance(data.packet_sequence_number);
+
+        let mut parity = Vec::new();
+        for group in self.config.groups(offset) {
+            self.sending.entry(group).or_default().add(timestamp, data);
+
+            if self.config.last(group) == offset
+                && let Some(clip) = self.sending.remove(&group)
+            {
+                let index = match group {
+                    Group::Row(_) => ROW_INDEX,
+                    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                    Group::Column(col, _) => col as u8,
+                };
+                parity.push(clip.parity(index, data.packet_sequence_number));
+            }
+        }
+
+        parity
+    }
+
+    /// Take in an arriving data or parity packet (still encrypted)
+    ///
+    /// Returns the lost packets (`(timestamp, packet)`) it lets rebuild.
+    pub fn received(
+        &mut self,
+        timestamp: u32,
+        data: &DataPacketInfo,
+    ) -> Vec<(u32, DataPacketInfo)> {
+        let offset = self.received.get(data.packet_sequence_number);
+        if offset < self.floor() {
+            return Vec::new();
+        }
+        self.advance(data.packet_sequence_number);
+
+        let pending = if data.message_number == PARITY_MESSAGE_NUMBER {
+            let Some((index, clip)) = Clip::from_parity(timestamp, data) else {
+                tracing::debug!("Malformed FEC packet {}", data.packet_sequence_number);
+                return Vec::new();
+            };
+            let Some(group) = self.config.parity_group(offset, index) else {
+                tracing::debug!("FEC packet {} fits no group", data.packet_sequence_number);
+                return Vec::new();
+            };
+
+            self.parity.insert(group, clip);
+            vec![group]
+        } else {
+            if self.packets.contains_key(&offset) {
+                return Vec::new();
+            }
+            self.packets.insert(offset, (timestamp, data.clone()));
+
+            self.config.groups(offset)
+        };
+
+        self.rebuild(pending)
+    }
+
+    /// Offset of the oldest packet kept for recovery
+    fn floor(&self) -> i64 {
+        let matrix = i64::from(self.config.cols) * i64::from(self.config.rows);
+
+        self.received.offset - RECEIVE_MATRICES * matrix
+    }
+
+    /// Note the arrival of `sequence_number`, forgetting what has become too old
+    fn advance(&mut self, sequence_number: u32) {
+        self.received.advance(sequence_number);
+
+        let floor = self.floor();
+        self.packets = self.packets.split_off(&floor);
+        let config = self.config;
+        self.parity
+            .retain(|group, _| config.members(*group).first().is_some_and(|f| *f >= floor));
+    }
+
+    /// Rebuild the packets missing alone from `pending` groups with parity
+    /// (and from groups those complete in turn)
+    fn rebuild(&mut self, mut pending: Vec<Group>) -> Vec<(u32, DataPacketInfo)> {
+        let mut rebuilt = Vec::new();
+
+        while let Some(group) = pending.pop() {
+            let Some(parity) = self.parity.get(&group) else {
+                continue;
+            };
+
+            let members = self.config.members(group);
+            let mut missing = members
+                .iter()
+                .filter(|offset| !self.packets.contains_key(offset));
+            let (Some(&lost), None) = (missing.next(), missing.next()) else {
+                continue;
+            };
+
+            let mut clip = parity.clone();
+            for (timestamp, data) in members.iter().filter_map(|o| self.packets.get(o)) {
+                clip.add(*timestamp, data);
+            }
+            self.parity.remove(&group);
+
+            #[allow(clippy::cast_possible_truncation)]
+            let sequence_number = sequence::add(
+                self.received.sequence_number,
+                (lost - self.received.offset) as u32,
+            );
+            let Some(packet) = clip.rebuild(sequence_number) else {
+                tracing::debug!(
+                    "Inconsistent FEC group ending at {}",
+                    self.config.last(group)
+                );
+                continue;
+            };
+
+            self.packets.insert(lost, packet.clone());
+            pending.extend(self.config.groups(lost));
+            rebuilt.push(packet);
+        }
+
+        rebuilt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(packet_sequence_number: u32) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number,
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::EvenKey,
+            retransmitted: false,
+            message_number: 1,
+            content: vec![
+                u8::try_from(packet_sequence_number % 256).unwrap_or_default();
+                packet_sequence_number as usize % 7 + 1
+            ],
+        }
+    }
+
+    /// Send 4 rows of 3 packets, dropping `lost`, and return the rebuilt ones
+    fn transfer(config: &str, lost: &[u32]) -> anyhow::Result<Vec<u32>> {
+        let config = FecConfig::try_from(&config.parse::<FilterConfig>()?)?;
+        let mut sender = Fec::new(config, 100);
+        let mut receiver = Fec::new(config, 100);
+
+        let mut rebuilt = Vec::new();
+        for seq in 100..112 {
+            let data = packet(seq);
+            let parity = sender.sent(seq * 10, &data);
+
+            if !lost.contains(&seq) {
+                assert!(receiver.received(seq * 10, &data).is_empty());
+            }
+            for (timestamp, parity) in parity {
+                for (timestamp, data) in receiver.received(timestamp, &parity) {
+                    let seq = data.packet_sequence_number;
+                    assert_eq!(timestamp, seq * 10);
+                    assert_eq!(data.content, packet(seq).content);
+                    assert_eq!(data.encryption, EncryptionFlag::EvenKey);
+                    rebuilt.push(seq);
+                }
+            }
+        }
+
+        Ok(rebuilt)
+    }
+
+    #[test]
+    fn test_rows() -> anyhow::Result<()> {
+        assert_eq!(transfer("fec,cols:3", &[101, 105])?, [101, 105]);
+        // Two losses in a row
+        assert!(transfer("fec,cols:3", &[100, 101])?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_columns() -> anyhow::Result<()> {
+        // Row parity of the first row can't help until column parity rebuilds 100
+        assert_eq!(
+            transfer("fec,cols:3,rows:2,layout:even", &[100, 101, 104])?,
+            [100, 101, 104]
+        );
+        assert_eq!(
+            transfer("fec,cols:3,rows:-2,layout:even", &[100, 101])?,
+            [100, 101]
+        );
+
+        // Column 1 of the staircase starts at row 1
+        assert_eq!(
+            transfer("fec,cols:3,rows:2,arq:never", &[101, 102])?,
+            [101, 102]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_config() -> anyhow::Result<()> {
+        let config = FecConfig::try_from(&"fec,cols:10,rows:5".parse::<FilterConfig>()?)?;
+        assert_eq!(config.layout, Layout::Staircase);
+        assert_eq!(config.arq, Arq::OnReq);
+
+        for invalid in [
+            "fec",
+            "fec,cols:0",
+            "fec,cols:4,rows:-1",
+            "fec,cols:4,size:1",
+            "xor,cols:4",
+        ] {
+            assert!(FecConfig::try_from(&invalid.parse::<FilterConfig>()?).is_err());
+        }
+
+        Ok(())
+    }
+}
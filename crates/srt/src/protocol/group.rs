@@ -0,0 +1,260 @@
+//! Socket groups (connection bonding): several member connections carrying one stream
+//!
+//! The sending side keeps the members' packet sequence numbers aligned, so that the
+//! receiving side can merge what they deliver by sequence number.
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/socket-groups.md>
+
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::protocol::{
+    constants::MAX_SEQUENCE_NUMBER,
+    packet::control::handshake::extension::group_membership::group_type,
+    sequence,
+};
+
+/// Socket IDs of groups have this bit set (`SRTGROUP_MASK`)
+pub const GROUP_ID_FLAG: u32 = 0x4000_0000;
+
+/// How a group uses its members
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupType {
+    /// Every message goes over all members, the receiver keeps the first copy
+    Broadcast,
+    /// Messages go over the member with the highest weight, another one takes over
+    /// once it goes quiet
+    MainBackup,
+}
+
+impl GroupType {
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Broadcast => group_type::BROADCAST,
+            Self::MainBackup => group_type::MAIN_BACKUP,
+        }
+    }
+
+    /// `None` for types not supported (balancing, multicast)
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            group_type::BROADCAST => Some(Self::Broadcast),
+            group_type::MAIN_BACKUP => Some(Self::MainBackup),
+            _ => None,
+        }
+    }
+}
+
+pub fn random_group_id() -> u32 {
+    rand::random_range(1..=MAX_SEQUENCE_NUMBER & !GROUP_ID_FLAG) | GROUP_ID_FLAG
+}
+
+/// Receiving side's merge of the messages delivered by the members
+#[derive(Default)]
+pub struct Merger {
+    /// Sequence number of the latest message passed on (of its first packet)
+    delivered: Option<u32>,
+}
+
+impl Merger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the message starting at `sequence_number` is to be passed on:
+    /// copies and messages overtaken over another member are not
+    pub fn accept(&mut self, sequence_number: u32) -> bool {
+        if self
+            .delivered
+            .is_some_and(|delivered| !sequence::lt(delivered, sequence_number))
+        {
+            return false;
+        }
+        self.delivered = Some(sequence_number);
+
+        true
+    }
+}
+
+/// Member of a main/backup group
+struct Link {
+    id: u32,
+    weight: u16,
+    /// First send since the peer was last heard from
+    unanswered_since: Option<Instant>,
+}
+
+/// Main/backup choice of the member to send over
+pub struct Backup {
+    links: Vec<Link>,
+    active: Option<u32>,
+    /// Silence after sending that makes a member quiet
+    timeout: Duration,
+}
+
+impl Backup {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            links: Vec::new(),
+            active: None,
+            timeout,
+        }
+    }
+
+    /// Member `id`, preferred over those with a lower `weight`
+    pub fn add(&mut self, id: u32, weight: u16) {
+        self.links.push(Link {
+            id,
+            weight,
+            unanswered_since: None,
+        });
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.links.retain(|link| link.id != id);
+    }
+
+    /// Data sent over member `id` at `now`
+    pub fn sent(&mut self, id: u32, now: Instant) {
+        if let Some(link) = self.links.iter_mut().find(|link| link.id == id) {
+            link.unanswered_since.get_or_insert(now);
+        }
+    }
+
+    /// Peer of member `id` last heard from at `last_received`
+    pub fn heard(&mut self, id: u32, last_received: Instant) {
+        if let Some(link) = self.links.iter_mut().find(|link| link.id == id)
+            && link
+                .unanswered_since
+                .is_some_and(|since| last_received >= since)
+        {
+            link.unanswered_since = None;
+        }
+    }
+
+    /// Member to send over at `now`, and whether it takes over from another one
+    ///
+    /// The active member stays while it responds, otherwise the responsive member
+    /// with the highest weight takes over.
+    pub fn active(&mut self, now: Instant) -> Option<(u32, bool)> {
+        let quiet = |link: &Link| {
+            link.unanswered_since
+                .is_some_and(|since| now.duration_since(since) >= self.timeout)
+        };
+
+        if let Some(active) = self.active
+            && self
+                .links
+                .iter()
+                .any(|link| link.id == active && !quiet(link))
+        {
+            return Some((active, false));
+        }
+
+        // Earlier members win ties; if all are quiet, the active one stays
+        let best = self
+            .links
+            .iter()
+            .filter(|link| !quiet(link))
+            .rev()
+            .max_by_key(|link| link.weight)
+            .or_else(|| self.links.iter().find(|link| Some(link.id) == self.active))
+            .or_else(|| self.links.first())?
+            .id;
+
+        let switched = self.active.is_some_and(|active| active != best);
+        self.active = Some(best);
+
+        Some((best, switched))
+    }
+}
+
+/// Recently sent messages, repeated over a member taking over
+pub struct History {
+    /// `(send time, sequence number of the first packet, message)`, oldest first
+    messages: VecDeque<(Instant, u32, Arc<[u8]>)>,
+    /// Age after which messages are no longer repeated
+    keep: Duration,
+}
+
+impl History {
+    pub fn new(keep: Duration) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            keep,
+        }
+    }
+
+    pub fn push(&mut self, now: Instant, sequence_number: u32, message: Arc<[u8]>) {
+        while self
+            .messages
+            .front()
+            .is_some_and(|(sent, ..)| now.duration_since(*sent) > self.keep)
+        {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((now, sequence_number, message));
+    }
+
+    /// Messages still worth repeating at `now`, with their sequence numbers
+    pub fn recent(&self, now: Instant) -> Vec<(u32, Arc<[u8]>)> {
+        self.messages
+            .iter()
+            .filter(|(sent, ..)| now.duration_since(*sent) <= self.keep)
+            .map(|(_, sequence_number, message)| (*sequence_number, message.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut merger = Merger::new();
+
+        assert!(merger.accept(MAX_SEQUENCE_NUMBER - 1));
+        // Copy from another member
+        assert!(!merger.accept(MAX_SEQUENCE_NUMBER - 1));
+        assert!(merger.accept(1));
+        // Overtaken
+        assert!(!merger.accept(MAX_SEQUENCE_NUMBER));
+        assert!(merger.accept(5));
+    }
+
+    #[test]
+    fn test_switchover() {
+        let timeout = Duration::from_millis(60);
+        let start = Instant::now();
+        let mut backup = Backup::new(timeout);
+        backup.add(1, 1);
+        backup.add(2, 5);
+        backup.add(3, 5);
+
+        assert_eq!(backup.active(start), Some((2, false)));
+        backup.sent(2, start);
+
+        // Answered in time
+        let now = start + Duration::from_millis(50);
+        backup.heard(2, now);
+        backup.sent(2, now);
+        assert_eq!(backup.active(now), Some((2, false)));
+
+        // Quiet since
+        let now = now + timeout;
+        assert_eq!(backup.active(now), Some((3, true)));
+        backup.sent(3, now);
+        assert_eq!(backup.active(now), Some((3, false)));
+
+        backup.remove(3);
+        assert_eq!(backup.active(now), Some((1, true)));
+
+        // Stays on the active member once it recovers
+        backup.heard(2, now + timeout);
+        assert_eq!(backup.active(now + timeout), Some((1, false)));
+    }
+}
@@ -0,0 +1,119 @@
+//! Receiver's list of missing packets
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.8.2>
+
+use std::collections::VecDeque;
+
+use crate::protocol::sequence;
+
+/// Inclusive `(from, to)` ranges of lost sequence numbers, oldest first
+#[derive(Debug, Default)]
+pub struct LossList {
+    ranges: VecDeque<(u32, u32)>,
+}
+
+impl LossList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Number of lost packets
+    #[allow(clippy::cast_sign_loss)]
+    pub fn len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|(from, to)| sequence::offset(*from, *to) as usize + 1)
+            .sum()
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = (u32, u32)> {
+        self.ranges.iter().copied()
+    }
+
+    pub fn contains(&self, seq: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|(from, to)| !sequence::lt(seq, *from) && !sequence::lt(*to, seq))
+    }
+
+    /// Add `from..=to` (newer than anything in the list)
+    pub fn push(&mut self, from: u32, to: u32) {
+        match self.ranges.back_mut() {
+            Some((_, last)) if sequence::next(*last) == from => *last = to,
+            _ => self.ranges.push_back((from, to)),
+        }
+    }
+
+    /// Remove a recovered packet
+    pub fn remove(&mut self, seq: u32) {
+        self.remove_range(seq, seq);
+    }
+
+    /// Remove every packet within `from..=to`
+    pub fn remove_range(&mut self, from: u32, to: u32) {
+        let mut res = VecDeque::with_capacity(self.ranges.len() + 1);
+
+        for (start, end) in self.ranges.drain(..) {
+            // No overlap
+            if sequence::lt(end, from) || sequence::lt(to, start) {
+                res.push_back((start, end));
+                continue;
+            }
+
+            if sequence::lt(start, from) {
+                res.push_back((start, sequence::prev(from)));
+            }
+            if sequence::lt(to, end) {
+                res.push_back((sequence::next(to), end));
+            }
+        }
+
+        self.ranges = res;
+    }
+
+    /// Remove every packet preceding `seq`
+    pub fn remove_before(&mut self, seq: u32) {
+        while let Some((from, to)) = self.ranges.front_mut() {
+            if sequence::lt(*to, seq) {
+                self.ranges.pop_front();
+            } else {
+                if sequence::lt(*from, seq) {
+                    *from = seq;
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_remove() {
+        let mut list = LossList::new();
+
+        list.push(5, 7);
+        list.push(8, 8);
+        list.push(12, 20);
+        assert_eq!(list.ranges().collect::<Vec<_>>(), [(5, 8), (12, 20)]);
+        assert_eq!(list.len(), 13);
+
+        list.remove(6);
+        list.remove_range(15, 30);
+        assert_eq!(
+            list.ranges().collect::<Vec<_>>(),
+            [(5, 5), (7, 8), (12, 14)]
+        );
+        assert!(list.contains(13));
+        assert!(!list.contains(6));
+
+        list.remove_before(8);
+        assert_eq!(list.ranges().collect::<Vec<_>>(), [(8, 8), (12, 14)]);
+    }
+}
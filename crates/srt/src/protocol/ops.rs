@@ -1,53 +1,1004 @@
-// pub fn handshake_v4(socket: &UdpSocket) -> anyhow::Result<Connection> {
-//     let mut buf = [0; 80];
-
-//     //
-//     // Induction
-//     //
-
-//     let (n, addr) = socket.recv_from(&mut buf)?;
-//     let data = &buf[..n];
-
-//     let in_packet = Packet::from_raw(data)?;
-//     let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = in_packet.content else {
-//         return Err(anyhow::anyhow!("Failed to unwrap handshake"));
-//     };
-
-//     let stream_id = handshake
-//         .stream_id_extension
-//         .as_ref()
-//         .map(|x| x.stream_id.clone());
-
-//     let out_packet_v4 = Packet {
-//         timestamp: in_packet.timestamp + 1,
-//         dest_socket_id: handshake.srt_socket_id,
-//         content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
-//             srt_socket_id: 42,
-//             syn_cookie: 42,
-//             ..handshake
-//         })),
-//     };
-//     socket.send_to(&out_packet_v4.to_raw(), addr)?;
-
-//     //
-//     // Conclusion
-//     //
-
-//     let (n, addr) = socket.recv_from(&mut buf)?;
-//     let data = &buf[..n];
-
-//     let in_packet = Packet::from_raw(data)?;
-//     let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = in_packet.content else {
-//         return Err(anyhow::anyhow!("Failed to unwrap handshake"));
-//     };
-
-//     let out_packet_v4 = Packet {
-//         timestamp: in_packet.timestamp + 1,
-//         dest_socket_id: handshake.srt_socket_id,
-//         content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
-//             version: 4,
-//             ..handshake
-//         })),
-//     };
-//     socket.send_to(&out_packet_v4.to_raw(), addr)?;
-// }
+use std::{
+    cmp::Ordering,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    options::Options,
+    protocol::{
+        congestion::CongestionType,
+        constants::{
+            FULL_ACK_INTERVAL,
+            HANDSHAKE_MAGIC_CODE,
+            MAX_FLOW_WINDOW_SIZE,
+            MAX_PACKET_SIZE,
+            MAX_SEQUENCE_NUMBER,
+            MIN_NAK_INTERVAL,
+            SEND_DROP_MIN_THRESHOLD,
+            SRT_VERSION,
+        },
+        crypto::{Crypto, Refresh},
+        filter::{
+            FilterConfig,
+            fec::{Arq, FecConfig},
+        },
+        group::GroupType,
+        packet::control::{
+            ControlPacketInfo,
+            handshake::{
+                Handshake,
+                HandshakeEncryption,
+                HandshakeType,
+                extension::{
+                    Extension,
+                    congestion::CongestionExtension,
+                    extension_flags,
+                    extension_types,
+                    filter::FilterExtension,
+                    group_membership::GroupMembershipExtension,
+                    handshake::{HandshakeExtension, handshake_extension_message_flags},
+                    key_material::{Cipher, KeyMaterialExtension},
+                    stream_id::StreamIdExtension,
+                },
+                reject_reason::{CoreReason, RejectReason},
+            },
+            other_subtypes,
+        },
+        stream_id::StreamId,
+        tsbpd::Tsbpd,
+    },
+};
+
+/// `Extension Field` of UDT (HSv4) handshakes: the `UDT_DGRAM` socket type
+const UDT_DGRAM: u16 = 2;
+
+/// SRT options supported by this implementation (reported in `HSREQ`/`HSRSP`)
+fn srt_flags(options: &Options) -> u32 {
+    let mut flags = handshake_extension_message_flags::REXMITFLG
+        | handshake_extension_message_flags::PERIODICNAK;
+
+    if options.tsbpd {
+        flags |= handshake_extension_message_flags::TSBPDSND
+            | handshake_extension_message_flags::TSBPDRCV;
+    }
+    if options.too_late_drop {
+        flags |= handshake_extension_message_flags::TLPKTDROP;
+    }
+    if !options.message_api {
+        flags |= handshake_extension_message_flags::STREAM;
+    }
+    // Packet filters are supported even if none is configured
+    flags |= handshake_extension_message_flags::PACKET_FILTER;
+
+    flags
+}
+
+/// TSBPD settings settled by the listener's `HSRSP`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Terms {
+    /// TSBPD delay of received packets
+    /// (`None` if the peer does not timestamp its packets for TSBPD)
+    pub latency: Option<Duration>,
+    /// TSBPD delay the peer applies to sent packets
+    pub peer_latency: Option<Duration>,
+    /// Both sides skip packets that are too late (`TLPKTDROP`)
+    pub too_late_drop: bool,
+    /// Receiver repeats its loss list periodically (`PERIODICNAK`)
+    pub periodic_nak: bool,
+}
+
+/// Connection parameters agreed on during the handshake
+#[derive(Clone, Debug)]
+pub struct Negotiated {
+    pub srt_socket_id: u32,
+    pub peer_srt_socket_id: u32,
+    pub initial_packet_sequence_number: u32,
+    pub stream_id: Option<StreamId>,
+    /// Congestion control both sides use
+    pub congestion: CongestionType,
+    /// Packet filter both sides use
+    pub filter: Option<FilterConfig>,
+
+    pub terms: Terms,
+    /// `Timestamp` of the peer's Conclusion handshake
+    pub peer_timestamp: u32,
+    /// Local time the peer's Conclusion handshake arrived
+    pub time_base: Instant,
+}
+
+impl Negotiated {
+    pub fn tsbpd(&self) -> Option<Tsbpd> {
+        self.terms
+            .latency
+            .map(|latency| Tsbpd::new(self.time_base, self.peer_timestamp, latency))
+    }
+
+    /// FEC settings of the packet filter (`None` without one)
+    pub fn fec(&self) -> Option<FecConfig> {
+        // Validated when agreed on
+        self.filter
+            .as_ref()
+            .and_then(|filter| FecConfig::try_from(filter).ok())
+    }
+
+    /// When lost packets are reported: right away without FEC, otherwise as configured
+    pub fn arq(&self) -> Arq {
+        self.fec().map_or(Arq::Always, |fec| fec.arq)
+    }
+
+    /// Whether the loss list is repeated every [`nak_interval`]
+    /// (the only reports of FEC's [`Arq::OnReq`])
+    pub fn periodic_nak(&self) -> bool {
+        match self.arq() {
+            Arq::Always => self.terms.periodic_nak,
+            Arq::OnReq => true,
+            Arq::Never => false,
+        }
+    }
+
+    /// (micros) Age at which the sender gives up on an unacknowledged packet
+    /// (`None` without too-late drop)
+    pub fn send_drop_threshold(&self) -> Option<u32> {
+        if !self.terms.too_late_drop {
+            return None;
+        }
+
+        let peer_latency: u32 = self.terms.peer_latency?.as_micros().try_into().ok()?;
+
+        Some(peer_latency.max(SEND_DROP_MIN_THRESHOLD) + 2 * FULL_ACK_INTERVAL)
+    }
+}
+
+/// Why a connection ended (`SRT_CLS_*` codes of `libsrt`)
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// Failed on an error of its own (e.g. sending to the peer)
+    Internal = 1,
+    /// Peer sent `Shutdown`
+    Peer = 2,
+    /// Closed by this side
+    Api = 3,
+    /// Nothing arrived from the peer within [`Options::peer_idle_timeout`]
+    PeerIdle = 7,
+}
+
+impl CloseReason {
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Internal => write!(f, "Connection failed"),
+            Self::Peer => write!(f, "Closed by peer"),
+            Self::Api => write!(f, "Shut down"),
+            Self::PeerIdle => write!(f, "Peer idle timeout"),
+        }
+    }
+}
+
+impl std::error::Error for CloseReason {}
+
+/// (micros) Period of NAK reports, given (micros) RTT and its variance
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.8.2>
+pub fn nak_interval(rtt: u32, rtt_var: u32) -> u32 {
+    ((rtt + 4 * rtt_var) / 2).max(MIN_NAK_INTERVAL)
+}
+
+fn millis(duration: Duration) -> u16 {
+    duration.as_millis().try_into().unwrap_or(u16::MAX)
+}
+
+pub fn random_socket_id() -> u32 {
+    rand::random_range(1..=MAX_SEQUENCE_NUMBER)
+}
+
+/// Random socket ID not yet `taken` by another connection
+pub fn unique_socket_id(taken: impl Fn(u32) -> bool) -> u32 {
+    loop {
+        let socket_id = random_socket_id();
+        if !taken(socket_id) {
+            return socket_id;
+        }
+    }
+}
+
+pub fn random_sequence_number() -> u32 {
+    rand::random_range(0..=MAX_SEQUENCE_NUMBER)
+}
+
+/// Encode an address the way `libsrt` puts it into `Peer IP Address`
+/// (IPv4 address occupies the first word)
+pub fn peer_ip_address(ip: IpAddr) -> (u32, u32, u32, u32) {
+    match ip {
+        IpAddr::V4(ip) => (u32::from_le_bytes(ip.octets()), 0, 0, 0),
+        IpAddr::V6(ip) => {
+            let words: Vec<u32> = ip
+                .octets()
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .collect();
+            (words[0], words[1], words[2], words[3])
+        }
+    }
+}
+
+/// Caller's Induction request
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.1>
+pub fn induction_request(
+    srt_socket_id: u32,
+    initial_packet_sequence_number: u32,
+    peer: IpAddr,
+) -> Handshake {
+    Handshake {
+        version: 4,
+        encryption: HandshakeEncryption::NoEncryption,
+        extension_field: UDT_DGRAM,
+        initial_packet_sequence_number,
+        #[allow(clippy::cast_possible_truncation)]
+        maximum_transmission_unit_size: MAX_PACKET_SIZE as u32,
+        maximum_flow_window_size: MAX_FLOW_WINDOW_SIZE,
+        handshake_type: HandshakeType::Induction,
+        srt_socket_id,
+        syn_cookie: 0,
+        peer_ip_address: peer_ip_address(peer),
+        extensions: Vec::new(),
+    }
+}
+
+/// Listener's Induction response, carrying the `syn_cookie` the caller has to return
+///
+/// No socket ID is allocated for the caller until its Conclusion request arrives.
+/// HSv4 callers send the same request and ignore the version and magic code of the
+/// response, so only their Conclusion request tells them apart (see [`is_legacy`]).
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.1>
+pub fn induction_response(request: &Handshake, syn_cookie: u32) -> Handshake {
+    Handshake {
+        version: 5,
+        extension_field: HANDSHAKE_MAGIC_CODE,
+        srt_socket_id: 0,
+        syn_cookie,
+        ..request.clone()
+    }
+}
+
+/// Caller's Conclusion request, built from the listener's Induction response
+/// (`group` if the caller joins the listener as a member of a group)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.2>
+pub fn conclusion_request(
+    induction_response: &Handshake,
+    induction_request: &Handshake,
+    options: &Options,
+    key_material: Option<KeyMaterialExtension>,
+    group: Option<GroupMembershipExtension>,
+) -> Result<Handshake> {
+    if induction_response.version < 5 || induction_response.extension_field != HANDSHAKE_MAGIC_CODE
+    {
+        bail!("Peer does not support HSv5");
+    }
+
+    conclusion_with_request(
+        induction_request,
+        induction_response.syn_cookie,
+        options,
+        key_material,
+        group,
+    )
+}
+
+fn stream_id_extension(options: &Options) -> Result<Option<StreamIdExtension>> {
+    options
+        .stream_id
+        .as_ref()
+        .map(|stream_id| StreamIdExtension::new(stream_id.to_string()))
+        .transpose()
+}
+
+/// `CONGESTION` extension, sent only for a type other than the default [`CongestionType::Live`]
+fn congestion_extension(congestion: CongestionType) -> Result<Option<CongestionExtension>> {
+    (congestion != CongestionType::Live)
+        .then(|| CongestionExtension::new(&congestion.to_string()))
+        .transpose()
+}
+
+/// `FILTER` extension, if a packet filter is configured
+fn filter_extension(filter: Option<&FilterConfig>) -> Result<Option<FilterExtension>> {
+    filter
+        .map(|filter| FilterExtension::new(&filter.to_string()))
+        .transpose()
+}
+
+/// Conclusion request with `HSREQ` (and `KMREQ`, `SID`, `CONGESTION`, `FILTER`, `GROUP`),
+/// based on this side's previous handshake
+fn conclusion_with_request(
+    previous: &Handshake,
+    syn_cookie: u32,
+    options: &Options,
+    key_material: Option<KeyMaterialExtension>,
+    group: Option<GroupMembershipExtension>,
+) -> Result<Handshake> {
+    let stream_id = stream_id_extension(options)?;
+    let congestion = congestion_extension(options.congestion)?;
+    let filter = filter_extension(options.packet_filter.as_ref())?;
+
+    let mut extension_field = extension_flags::HSREQ;
+    if key_material.is_some() {
+        extension_field |= extension_flags::KMREQ;
+    }
+    if stream_id.is_some() || congestion.is_some() || filter.is_some() || group.is_some() {
+        extension_field |= extension_flags::CONFIG;
+    }
+
+    Ok(Handshake {
+        version: 5,
+        extension_field,
+        handshake_type: HandshakeType::Conclusion,
+        syn_cookie,
+        extensions: [
+            Some(Extension::Handshake(HandshakeExtension {
+                r#type: extension_types::HSREQ,
+                length: 3,
+                srt_version: SRT_VERSION,
+                srt_flags: srt_flags(options),
+                receiver_delay: millis(options.latency),
+                sender_delay: millis(options.latency),
+            })),
+            key_material.map(Extension::KeyMaterial),
+            stream_id.map(Extension::StreamId),
+            congestion.map(Extension::Congestion),
+            filter.map(Extension::Filter),
+            group.map(Extension::GroupMembership),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        ..previous.clone()
+    })
+}
+
+/// Listener's (or rendezvous responder's) Conclusion response with `HSRSP`
+/// (and `KMRSP`, `CONGESTION`, `FILTER`, `GROUP`)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.2>
+pub fn conclusion_response(
+    request: &Handshake,
+    srt_socket_id: u32,
+    handshake_extension: HandshakeExtension,
+    key_material: Option<KeyMaterialExtension>,
+    congestion: CongestionType,
+    filter: Option<&FilterConfig>,
+    group: Option<GroupMembershipExtension>,
+) -> Result<Handshake> {
+    let mut extension_field = extension_flags::HSREQ;
+    if key_material.is_some() {
+        extension_field |= extension_flags::KMREQ;
+    }
+    let congestion = congestion_extension(congestion)?;
+    let filter = filter_extension(filter)?;
+    if congestion.is_some() || filter.is_some() || group.is_some() {
+        extension_field |= extension_flags::CONFIG;
+    }
+
+    Ok(Handshake {
+        extension_field,
+        srt_socket_id,
+        extensions: [
+            Some(Extension::Handshake(handshake_extension)),
+            key_material.map(Extension::KeyMaterial),
+            congestion.map(Extension::Congestion),
+            filter.map(Extension::Filter),
+            group.map(Extension::GroupMembership),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        ..request.clone()
+    })
+}
+
+/// Side of a rendezvous connection, settled by the cookie contest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RendezvousRole {
+    /// Proposes the settings like a caller (`HSREQ`, `KMREQ`)
+    Initiator,
+    /// Answers them like a listener (`HSRSP`, `KMRSP`)
+    Responder,
+}
+
+/// Rendezvous Wave-A-Hand, with a random `SYN Cookie` for the contest
+///
+/// This side's later handshakes carry the same socket ID, sequence number and cookie.
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.2>
+pub fn wave_hand(
+    srt_socket_id: u32,
+    initial_packet_sequence_number: u32,
+    peer: IpAddr,
+) -> Handshake {
+    Handshake {
+        version: 5,
+        extension_field: 0,
+        handshake_type: HandshakeType::WaveHand,
+        syn_cookie: rand::random(),
+        ..induction_request(srt_socket_id, initial_packet_sequence_number, peer)
+    }
+}
+
+/// Cookie contest: the side with the greater cookie (compared as signed, like `libsrt`)
+/// becomes the initiator (`None` on a draw)
+#[allow(clippy::cast_possible_wrap)]
+pub fn cookie_contest(own: u32, peer: u32) -> Option<RendezvousRole> {
+    match (own as i32).cmp(&(peer as i32)) {
+        Ordering::Greater => Some(RendezvousRole::Initiator),
+        Ordering::Less => Some(RendezvousRole::Responder),
+        Ordering::Equal => None,
+    }
+}
+
+/// Rendezvous initiator's Conclusion request with `HSREQ` (and `KMREQ`, `SID`)
+pub fn rendezvous_request(
+    wave_hand: &Handshake,
+    options: &Options,
+    key_material: Option<KeyMaterialExtension>,
+) -> Result<Handshake> {
+    conclusion_with_request(wave_hand, wave_hand.syn_cookie, options, key_material, None)
+}
+
+/// Rendezvous responder's Conclusion without extensions, telling the initiator
+/// to send its `HSREQ`
+pub fn rendezvous_attention(wave_hand: &Handshake) -> Handshake {
+    Handshake {
+        handshake_type: HandshakeType::Conclusion,
+        ..wave_hand.clone()
+    }
+}
+
+/// Whether `handshake` is a Conclusion carrying an `HSREQ` or `HSRSP` of `extension_type`
+pub fn is_conclusion_with(handshake: &Handshake, extension_type: u16) -> bool {
+    handshake.handshake_type == HandshakeType::Conclusion
+        && handshake
+            .handshake_extension()
+            .is_some_and(|extension| extension.r#type == extension_type)
+}
+
+/// Rendezvous initiator's final Agreement
+pub fn rendezvous_agreement(wave_hand: &Handshake) -> Handshake {
+    Handshake {
+        handshake_type: HandshakeType::Agreement,
+        ..wave_hand.clone()
+    }
+}
+
+/// Caller's keys and `KMREQ`, if a passphrase is set
+pub fn key_material_request(options: &Options) -> Result<Option<(Crypto, KeyMaterialExtension)>> {
+    let Some(passphrase) = &options.passphrase else {
+        return Ok(None);
+    };
+    if options.cipher == Cipher::AesGcm && options.packet_filter.is_some() {
+        bail!("AES-GCM does not support packet filters");
+    }
+
+    let refresh = Refresh {
+        rate: options.km_refresh_rate,
+        preannounce: options.km_preannounce,
+    };
+    let crypto = Crypto::generate(passphrase, options.cipher, options.key_length, refresh)?;
+    let request = crypto.key_material(extension_types::KMREQ)?;
+
+    Ok(Some((crypto, request)))
+}
+
+/// Listener's keys from a caller's `KMREQ`, with the `KMRSP` echoing it
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.2>
+pub fn key_material_response(
+    request: Option<&KeyMaterialExtension>,
+    options: &Options,
+) -> Result<Option<(Crypto, KeyMaterialExtension)>> {
+    match (request, &options.passphrase) {
+        (None, None) => Ok(None),
+        (Some(request), Some(passphrase)) => {
+            if options.require_gcm && request.cipher != Cipher::AesGcm {
+                bail!("Caller does not use AES-GCM");
+            }
+
+            let crypto = Crypto::from_key_material(request, passphrase)?;
+            let response = KeyMaterialExtension {
+                r#type: extension_types::KMRSP,
+                ..request.clone()
+            };
+
+            Ok(Some((crypto, response)))
+        }
+        (None, Some(_)) => bail!("Caller did not send key material"),
+        (Some(_), None) => bail!("Caller requires a passphrase"),
+    }
+}
+
+/// Caller's stream ID from its Conclusion request
+pub fn requested_stream_id(request: &Handshake) -> Result<Option<StreamId>> {
+    request
+        .stream_id_extension()
+        .map(|extension| {
+            extension
+                .stream_id
+                .parse()
+                .with_context(|| format!("Invalid stream ID {:?}", extension.stream_id))
+        })
+        .transpose()
+}
+
+/// Congestion control requested by the peer's Conclusion request
+/// ([`CongestionType::Live`] without a `CONGESTION` extension)
+pub fn requested_congestion(request: &Handshake) -> Result<CongestionType> {
+    request
+        .congestion_extension()
+        .map_or(Ok(CongestionType::Live), |extension| {
+            extension.congestion.parse()
+        })
+}
+
+/// Congestion control of a connection, which both sides must agree on
+pub fn agreed_congestion(request: &Handshake, options: &Options) -> Result<CongestionType> {
+    let congestion = requested_congestion(request)?;
+    if congestion != options.congestion {
+        bail!(
+            "Peer uses {congestion} congestion control instead of {}",
+            options.congestion
+        );
+    }
+
+    Ok(congestion)
+}
+
+/// Packet filter requested by the peer's Conclusion request
+pub fn requested_filter(request: &Handshake) -> Result<Option<FilterConfig>> {
+    request
+        .filter_extension()
+        .map(|extension| {
+            extension
+                .filter
+                .parse()
+                .with_context(|| format!("Invalid packet filter {:?}", extension.filter))
+        })
+        .transpose()
+}
+
+/// Packet filter of a connection: the peer's and this side's configurations combined
+/// (a filter configured on one side only is imposed on the other)
+pub fn agreed_filter(request: &Handshake, options: &Options) -> Result<Option<FilterConfig>> {
+    let filter = match (&options.packet_filter, requested_filter(request)?) {
+        (None, None) => return Ok(None),
+        (Some(own), None) => {
+            let capable = request.handshake_extension().is_some_and(|extension| {
+                extension.srt_flags & handshake_extension_message_flags::PACKET_FILTER != 0
+            });
+            if !capable {
+                bail!("Peer does not support packet filters");
+            }
+            own.clone()
+        }
+        (None, Some(peer)) => peer,
+        (Some(own), Some(peer)) => own.merge(&peer)?,
+    };
+
+    FecConfig::try_from(&filter)?;
+    if request
+        .key_material_extension()
+        .is_some_and(|km| km.cipher == Cipher::AesGcm)
+    {
+        bail!("AES-GCM does not support packet filters");
+    }
+
+    Ok(Some(filter))
+}
+
+/// Packet filter settled by the peer's Conclusion response, which has to match
+/// this side's configuration
+pub fn response_filter(response: &Handshake, options: &Options) -> Result<Option<FilterConfig>> {
+    let filter = requested_filter(response)?;
+    match (&options.packet_filter, &filter) {
+        (Some(_), None) => bail!("Peer does not support packet filters"),
+        (Some(own), Some(filter)) => {
+            own.merge(filter)?;
+        }
+        (None, _) => (),
+    }
+    if let Some(filter) = &filter {
+        FecConfig::try_from(filter)?;
+    }
+
+    Ok(filter)
+}
+
+/// Type of the group the peer's Conclusion request joins (`None` for a single connection)
+pub fn requested_group(request: &Handshake) -> Result<Option<GroupType>> {
+    request
+        .group_membership_extension()
+        .map(|extension| {
+            GroupType::from_code(extension.r#type)
+                .with_context(|| format!("Unsupported group type {}", extension.r#type))
+        })
+        .transpose()
+}
+
+/// Check that the peer's Conclusion response admits this side's `request` into a group
+/// of the same type
+pub fn check_response_group(request: &Handshake, response: &Handshake) -> Result<()> {
+    match (
+        request.group_membership_extension(),
+        response.group_membership_extension(),
+    ) {
+        (Some(_), None) => bail!("Peer does not support groups"),
+        (Some(own), Some(peer)) if own.r#type != peer.r#type => {
+            bail!(
+                "Peer uses group type {} instead of {}",
+                peer.r#type,
+                own.r#type
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that the peer's `HSREQ`/`HSRSP` uses the same message or stream mode
+pub fn check_message_api(extension: &HandshakeExtension, options: &Options) -> Result<()> {
+    let stream = extension.srt_flags & handshake_extension_message_flags::STREAM != 0;
+    if stream == options.message_api {
+        bail!(
+            "Peer uses {} mode",
+            if stream { "stream" } else { "message" }
+        );
+    }
+
+    Ok(())
+}
+
+/// Congestion control settled by the peer's Conclusion response
+/// (which may leave out `CONGESTION` even if it agrees)
+pub fn response_congestion(response: &Handshake, options: &Options) -> Result<CongestionType> {
+    match response.congestion_extension() {
+        Some(_) => agreed_congestion(response, options),
+        None => Ok(options.congestion),
+    }
+}
+
+/// Connection request as seen by a listener's accept callback
+#[derive(Clone, Copy, Debug)]
+pub struct AcceptRequest<'a> {
+    pub addr: SocketAddr,
+    pub stream_id: Option<&'a StreamId>,
+    /// Key length the caller encrypts with (from its `KMREQ`)
+    pub encryption: HandshakeEncryption,
+    /// Caller's `HSREQ` (`None` for HSv4 callers, which send it once connected)
+    pub handshake_extension: Option<&'a HandshakeExtension>,
+}
+
+/// Listener's decision on a caller's Conclusion request
+///
+/// The request has to be well-formed and match the listener's `options`
+/// before `accept` gets to see it.
+pub fn admit(
+    addr: SocketAddr,
+    request: &Handshake,
+    options: &Options,
+    accept: impl Fn(&AcceptRequest) -> Result<(), RejectReason>,
+) -> Result<(), RejectReason> {
+    if is_legacy(request) {
+        // Congestion control and packet filters came with HSv5
+        if options.congestion != CongestionType::Live || options.packet_filter.is_some() {
+            return Err(RejectReason::Core(CoreReason::Version));
+        }
+
+        return accept(&AcceptRequest {
+            addr,
+            stream_id: None,
+            encryption: HandshakeEncryption::NoEncryption,
+            handshake_extension: None,
+        });
+    }
+
+    let Some(handshake_extension) = request.handshake_extension() else {
+        return Err(RejectReason::Core(CoreReason::Rogue));
+    };
+    let stream_id = requested_stream_id(request).map_err(|_| RejectReason::BAD_REQUEST)?;
+    let encryption = match request.key_material_extension().map(|km| km.key_length) {
+        None => HandshakeEncryption::NoEncryption,
+        Some(16) => HandshakeEncryption::AES128,
+        Some(24) => HandshakeEncryption::AES192,
+        Some(32) => HandshakeEncryption::AES256,
+        Some(_) => return Err(RejectReason::Core(CoreReason::Crypto)),
+    };
+    match (request.key_material_extension(), &options.passphrase) {
+        (None, None) => {}
+        (Some(key_material), Some(passphrase)) => {
+            if !matches!(key_material.cipher, Cipher::AesCtr | Cipher::AesGcm)
+                || (options.require_gcm && key_material.cipher != Cipher::AesGcm)
+            {
+                return Err(RejectReason::Core(CoreReason::Crypto));
+            }
+            if Crypto::from_key_material(key_material, passphrase).is_err() {
+                return Err(RejectReason::Core(CoreReason::BadSecret));
+            }
+        }
+        // Only one side encrypts
+        _ => return Err(RejectReason::Core(CoreReason::Unsecure)),
+    }
+    if check_message_api(handshake_extension, options).is_err() {
+        return Err(RejectReason::Core(CoreReason::MessageApi));
+    }
+    if agreed_congestion(request, options).is_err() {
+        return Err(RejectReason::Core(CoreReason::Congestion));
+    }
+    if agreed_filter(request, options).is_err() {
+        return Err(RejectReason::Core(CoreReason::Filter));
+    }
+    if requested_group(request).is_err() {
+        return Err(RejectReason::Core(CoreReason::Group));
+    }
+
+    accept(&AcceptRequest {
+        addr,
+        stream_id: stream_id.as_ref(),
+        encryption,
+        handshake_extension: Some(handshake_extension),
+    })
+}
+
+/// Listener's refusal of a caller's Conclusion request
+///
+/// <https://github.com/Haivision/srt/blob/master/docs/API/rejection-codes.md>
+pub fn rejection(request: &Handshake, reason: RejectReason) -> Handshake {
+    Handshake {
+        extension_field: 0,
+        handshake_type: HandshakeType::Rejected(reason),
+        srt_socket_id: 0,
+        extensions: Vec::new(),
+        ..request.clone()
+    }
+}
+
+/// Listener's `HSRSP` to a caller's `HSREQ`, with the agreed TSBPD settings
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.4>
+pub fn handshake_response(
+    request: &HandshakeExtension,
+    options: &Options,
+) -> (HandshakeExtension, Terms) {
+    let latency = millis(options.latency);
+
+    // Caller's sender delay is the proposal for this side's receiver and vice versa
+    let receiver_delay = latency.max(request.sender_delay);
+    let sender_delay = latency.max(request.receiver_delay);
+
+    let response = HandshakeExtension {
+        r#type: extension_types::HSRSP,
+        length: 3,
+        srt_version: SRT_VERSION,
+        srt_flags: srt_flags(options),
+        receiver_delay,
+        sender_delay,
+    };
+    let terms = Terms {
+        latency: (options.tsbpd
+            && request.srt_flags & handshake_extension_message_flags::TSBPDSND != 0)
+            .then(|| Duration::from_millis(receiver_delay.into())),
+        peer_latency: (options.tsbpd
+            && request.srt_flags & handshake_extension_message_flags::TSBPDRCV != 0)
+            .then(|| Duration::from_millis(sender_delay.into())),
+        too_late_drop: options.too_late_drop
+            && request.srt_flags & handshake_extension_message_flags::TLPKTDROP != 0,
+        periodic_nak: request.srt_flags & handshake_extension_message_flags::PERIODICNAK != 0,
+    };
+
+    (response, terms)
+}
+
+/// Caller's TSBPD settings, as agreed in listener's `HSRSP`
+pub fn response_terms(response: &HandshakeExtension, options: &Options) -> Terms {
+    let flags = response.srt_flags;
+
+    Terms {
+        latency: (options.tsbpd && flags & handshake_extension_message_flags::TSBPDSND != 0)
+            .then(|| Duration::from_millis(response.sender_delay.into())),
+        peer_latency: (options.tsbpd && flags & handshake_extension_message_flags::TSBPDRCV != 0)
+            .then(|| Duration::from_millis(response.receiver_delay.into())),
+        too_late_drop: flags & handshake_extension_message_flags::TLPKTDROP != 0,
+        periodic_nak: flags & handshake_extension_message_flags::PERIODICNAK != 0,
+    }
+}
+
+/// Whether `request` is the Conclusion request of an HSv4 caller
+pub fn is_legacy(request: &Handshake) -> bool {
+    request.handshake_type == HandshakeType::Conclusion && request.version == 4
+}
+
+/// Listener's HSv4 Conclusion response: the UDT handshake, without extensions
+pub fn legacy_conclusion_response(request: &Handshake, srt_socket_id: u32) -> Handshake {
+    Handshake {
+        version: 4,
+        extension_field: UDT_DGRAM,
+        srt_socket_id,
+        extensions: Vec::new(),
+        ..request.clone()
+    }
+}
+
+/// SRT options an HSv4 caller sends as control packets once connected
+/// (`HSREQ`, and `KMREQ` if it encrypts)
+#[derive(Default)]
+pub struct LegacyRequest {
+    handshake: Option<HandshakeExtension>,
+    key_material: Option<KeyMaterialExtension>,
+}
+
+impl LegacyRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the `HSREQ` or `KMREQ` in `control`, returning whether it was one
+    pub fn collect(&mut self, control: &ControlPacketInfo) -> bool {
+        let ControlPacketInfo::Other { subtype, content } = control else {
+            return false;
+        };
+
+        match *subtype {
+            other_subtypes::HSREQ => {
+                match HandshakeExtension::from_raw_message(extension_types::HSREQ, content) {
+                    Ok(extension) => self.handshake = Some(extension),
+                    Err(e) => tracing::warn!("Invalid HSREQ: {e}"),
+                }
+            }
+            other_subtypes::KMREQ => {
+                match KeyMaterialExtension::from_raw_message(extension_types::KMREQ, content) {
+                    Ok(key_material) => self.key_material = Some(key_material),
+                    Err(e) => tracing::warn!("Invalid KMREQ: {e}"),
+                }
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Whether the options `options` call for have all arrived
+    pub fn is_complete(&self, options: &Options) -> bool {
+        self.handshake.is_some() && (options.passphrase.is_none() || self.key_material.is_some())
+    }
+
+    /// Settle the connection of the HSv4 Conclusion `request`: the negotiated settings,
+    /// keys, and the `HSRSP` (and `KMRSP`) control packets to answer with
+    ///
+    /// `peer_timestamp` and `time_base` are those of the Conclusion request.
+    pub fn respond(
+        &self,
+        request: &Handshake,
+        srt_socket_id: u32,
+        peer_timestamp: u32,
+        time_base: Instant,
+        options: &Options,
+    ) -> Result<(Negotiated, Option<Crypto>, Vec<ControlPacketInfo>)> {
+        let extension = self.handshake.as_ref().context("Missing HSREQ")?;
+        check_message_api(extension, options)?;
+        let (mut response, terms) = handshake_response(extension, options);
+        // HSv4 carries a single delay, where HSv5 has the sender's
+        response.sender_delay = response.receiver_delay;
+        let (crypto, key_material) =
+            key_material_response(self.key_material.as_ref(), options)?.unzip();
+
+        let negotiated = Negotiated {
+            srt_socket_id,
+            peer_srt_socket_id: request.srt_socket_id,
+            initial_packet_sequence_number: request.initial_packet_sequence_number,
+            stream_id: None,
+            congestion: CongestionType::Live,
+            filter: None,
+            terms,
+            peer_timestamp,
+            time_base,
+        };
+
+        let mut replies = vec![ControlPacketInfo::Other {
+            subtype: other_subtypes::HSRSP,
+            content: response.raw_message(),
+        }];
+        if let Some(key_material) = key_material {
+            replies.push(ControlPacketInfo::Other {
+                subtype: other_subtypes::KMRSP,
+                content: key_material.raw_message(),
+            });
+        }
+
+        Ok((negotiated, crypto, replies))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_contest() {
+        assert_eq!(cookie_contest(2, 1), Some(RendezvousRole::Initiator));
+        assert_eq!(cookie_contest(1, 2), Some(RendezvousRole::Responder));
+        assert_eq!(cookie_contest(7, 7), None);
+
+        // Compared as signed
+        assert_eq!(
+            cookie_contest(0x8000_0000, 1),
+            Some(RendezvousRole::Responder)
+        );
+    }
+
+    #[test]
+    fn test_legacy_request() -> Result<()> {
+        let options = Options {
+            passphrase: Some("passphrase".to_owned()),
+            ..Options::new()
+        };
+        let request = Handshake {
+            handshake_type: HandshakeType::Conclusion,
+            ..induction_request(7, 100, IpAddr::from([127, 0, 0, 1]))
+        };
+        assert!(is_legacy(&request));
+
+        let handshake = HandshakeExtension {
+            r#type: extension_types::HSREQ,
+            length: 3,
+            srt_version: 0x01_02_03,
+            srt_flags: handshake_extension_message_flags::TSBPDSND,
+            receiver_delay: 0,
+            sender_delay: 200,
+        };
+        let (_, key_material) = key_material_request(&options)?.context("No KMREQ")?;
+
+        let mut legacy = LegacyRequest::new();
+        assert!(!legacy.collect(&ControlPacketInfo::KeepAlive));
+        assert!(legacy.collect(&ControlPacketInfo::Other {
+            subtype: other_subtypes::HSREQ,
+            content: handshake.raw_message(),
+        }));
+        // Encrypted without the keys yet
+        assert!(!legacy.is_complete(&options));
+        assert!(legacy.collect(&ControlPacketInfo::Other {
+            subtype: other_subtypes::KMREQ,
+            content: key_material.raw_message(),
+        }));
+        assert!(legacy.is_complete(&options));
+
+        let (negotiated, crypto, replies) =
+            legacy.respond(&request, 9, 0, Instant::now(), &options)?;
+        assert_eq!(negotiated.peer_srt_socket_id, 7);
+        assert_eq!(negotiated.terms.latency, Some(Duration::from_millis(200)));
+        assert!(crypto.is_some());
+        let [
+            ControlPacketInfo::Other {
+                subtype: other_subtypes::HSRSP,
+                content,
+            },
+            ControlPacketInfo::Other {
+                subtype: other_subtypes::KMRSP,
+                ..
+            },
+        ] = replies.as_slice()
+        else {
+            panic!("Unexpected replies {replies:?}");
+        };
+        let response = HandshakeExtension::from_raw_message(extension_types::HSRSP, content)?;
+        assert_eq!(response.sender_delay, 200);
+
+        Ok(())
+    }
+}
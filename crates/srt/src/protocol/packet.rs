@@ -2,21 +2,44 @@ pub mod control;
 pub mod data;
 
 use self::{control::ControlPacketInfo, data::DataPacketInfo};
+use crate::error::{Error, Result};
+
+/// Length of the header every packet starts with
+pub const HEADER_SIZE: usize = 16;
+
+/// Read the big-endian 32-bit word at `offset` of `raw` (part of `what`)
+pub(crate) fn read_u32(raw: &[u8], offset: usize, what: &'static str) -> Result<u32> {
+    raw.get(offset..)
+        .and_then(<[u8]>::first_chunk)
+        .map(|word| u32::from_be_bytes(*word))
+        .ok_or(Error::Truncated(what))
+}
+
+/// Read the big-endian 16-bit word at `offset` of `raw` (part of `what`)
+pub(crate) fn read_u16(raw: &[u8], offset: usize, what: &'static str) -> Result<u16> {
+    raw.get(offset..)
+        .and_then(<[u8]>::first_chunk)
+        .map(|word| u16::from_be_bytes(*word))
+        .ok_or(Error::Truncated(what))
+}
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum PacketContent {
     Data(DataPacketInfo),
     Control(ControlPacketInfo),
 }
 
 impl PacketContent {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let packet_type = (raw[0] & 0b1000_0000) >> 7;
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        if raw.len() < HEADER_SIZE {
+            return Err(Error::Truncated("packet header"));
+        }
 
-        Ok(match packet_type {
-            1 => Self::Control(ControlPacketInfo::from_raw(raw)?),
-            0 => Self::Data(DataPacketInfo::from_raw(raw)?),
-            _ => unreachable!(),
+        Ok(if raw[0] & 0b1000_0000 != 0 {
+            Self::Control(ControlPacketInfo::from_raw(raw)?)
+        } else {
+            Self::Data(DataPacketInfo::from_raw(raw)?)
         })
     }
 
@@ -44,9 +67,9 @@ pub struct Packet {
 }
 
 impl Packet {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let timestamp = u32::from_be_bytes(raw[8..12].try_into()?);
-        let dest_socket_id = u32::from_be_bytes(raw[12..16].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let timestamp = read_u32(raw, 8, "packet header")?;
+        let dest_socket_id = read_u32(raw, 12, "packet header")?;
         let content = PacketContent::from_raw(raw)?;
 
         Ok(Self {
@@ -67,3 +90,98 @@ impl Packet {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+    use crate::protocol::packet::control::control_types;
+
+    fn control_packet(r#type: u16, type_specific: u32, cif: &[u8]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend((r#type | (1 << 15)).to_be_bytes());
+        raw.extend(0u16.to_be_bytes());
+        raw.extend(type_specific.to_be_bytes());
+        raw.extend([0; 8]);
+        raw.extend(cif);
+
+        raw
+    }
+
+    #[test]
+    fn test_garbage() {
+        // Seeded, for a failure to reproduce
+        let mut rng = StdRng::seed_from_u64(0x5352_5400);
+
+        for _ in 0..10_000 {
+            let mut raw = vec![0; rng.random_range(0..128)];
+            rng.fill(&mut raw[..]);
+
+            Packet::from_raw(&raw).ok();
+
+            // Known control type with garbage after it
+            if let Some(header) = raw.get_mut(..2) {
+                let r#type = rng.random_range(0..=control_types::PEER_ERROR);
+                header.copy_from_slice(&(r#type | (1 << 15)).to_be_bytes());
+                Packet::from_raw(&raw).ok();
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let handshake = control_packet(control_types::HANDSHAKE, 0, &[0; 24]);
+        assert_eq!(
+            Packet::from_raw(&handshake[..15]).err(),
+            Some(Error::Truncated("packet header"))
+        );
+        assert_eq!(
+            Packet::from_raw(&handshake).err(),
+            Some(Error::Truncated("handshake"))
+        );
+
+        let drop_req = control_packet(control_types::DROPREQ, 7, &[0; 4]);
+        assert_eq!(
+            Packet::from_raw(&drop_req).err(),
+            Some(Error::Truncated("DROPREQ"))
+        );
+        assert_eq!(
+            Packet::from_raw(&control_packet(control_types::ACK, 1, &[0; 8])).err(),
+            Some(Error::BadValue("ACK size", 24))
+        );
+    }
+
+    #[test]
+    fn test_control_types() {
+        let raw = control_packet(control_types::PEER_ERROR, 4000, &[]);
+        assert!(matches!(
+            Packet::from_raw(&raw).map(|pack| pack.content),
+            Ok(PacketContent::Control(ControlPacketInfo::PeerError(
+                control::peer_error::PeerError { error_code: 4000 }
+            )))
+        ));
+
+        let raw = control_packet(control_types::CONGESTION_WARNING, 0, &[]);
+        assert!(matches!(
+            Packet::from_raw(&raw).map(|pack| pack.content),
+            Ok(PacketContent::Control(ControlPacketInfo::CongestionWarning))
+        ));
+
+        assert_eq!(
+            Packet::from_raw(&control_packet(0x0123, 0, &[])).err(),
+            Some(Error::UnknownControlType(0x0123))
+        );
+    }
+
+    #[test]
+    fn test_encryption_flag() {
+        let mut raw = vec![0; 20];
+        raw[4] = 0b0001_1000;
+
+        assert_eq!(
+            Packet::from_raw(&raw).err(),
+            Some(Error::BadValue("encryption flag", 0b11))
+        );
+    }
+}
@@ -6,6 +6,8 @@ use self::{
     nak::Nak,
     peer_error::PeerError,
 };
+use super::{HEADER_SIZE, read_u16};
+use crate::error::{Error, Result};
 
 // Control Information Field of different Types
 pub mod ack;
@@ -28,8 +30,17 @@ pub mod control_types {
     pub const OTHER: u16 = 0x7FFF;
 }
 
+/// `Subtype` of [`control_types::OTHER`] packets (same numbering as handshake extension types)
+pub mod other_subtypes {
+    pub const HSREQ: u16 = 1;
+    pub const HSRSP: u16 = 2;
+    pub const KMREQ: u16 = 3;
+    pub const KMRSP: u16 = 4;
+}
+
 /// Contains `Type`, `Subtype`, `Type-specific Information`, `CIF`
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ControlPacketInfo {
     Handshake(Handshake),
     KeepAlive,
@@ -40,28 +51,37 @@ pub enum ControlPacketInfo {
     AckAck(AckAck),
     DropReq(DropReq),
     PeerError(PeerError),
-    Other,
+    /// User-defined type, refer to [`other_subtypes`]
+    Other {
+        subtype: u16,
+        content: Vec<u8>,
+    },
 }
 
 impl ControlPacketInfo {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let control_type = u16::from_be_bytes(raw[0..2].try_into()?) & !(1 << 15);
-        let _subtype = u16::from_be_bytes(raw[2..4].try_into()?);
-        let _type_specific = &raw[4..8];
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let control_type = read_u16(raw, 0, "control packet header")? & !(1 << 15);
+        let subtype = read_u16(raw, 2, "control packet header")?;
+        let cif = raw
+            .get(HEADER_SIZE..)
+            .ok_or(Error::Truncated("control packet header"))?;
 
         Ok(match control_type {
-            control_types::HANDSHAKE => Self::Handshake(Handshake::from_raw_cif(&raw[16..])?),
+            control_types::HANDSHAKE => Self::Handshake(Handshake::from_raw_cif(cif)?),
             control_types::KEEPALIVE => Self::KeepAlive,
             control_types::ACK => Self::Ack(Ack::from_raw(raw)?),
-            control_types::NAK => todo!("Nak"),
-            control_types::CONGESTION_WARNING => todo!("CongestionWarning"),
+            control_types::NAK => Self::Nak(Nak::from_raw(raw)?),
+            control_types::CONGESTION_WARNING => Self::CongestionWarning,
             control_types::SHUTDOWN => Self::Shutdown,
             control_types::ACKACK => Self::AckAck(AckAck::from_raw(raw)?),
             control_types::DROPREQ => Self::DropReq(DropReq::from_raw(raw)?),
-            control_types::PEER_ERROR => todo!("PeerError"),
-            control_types::OTHER => todo!("Other"),
+            control_types::PEER_ERROR => Self::PeerError(PeerError::from_raw(raw)?),
+            control_types::OTHER => Self::Other {
+                subtype,
+                content: cif.to_vec(),
+            },
 
-            _ => unreachable!(),
+            unknown => return Err(Error::UnknownControlType(unknown)),
         })
     }
 
@@ -85,7 +105,13 @@ impl ControlPacketInfo {
             Self::AckAck(ack_ack) => ack_ack.raw_header(),
             Self::DropReq(drop_req) => drop_req.raw_header(),
             Self::PeerError(peer_error) => peer_error.raw_header(),
-            Self::Other => Self::empty_header_with_type(control_types::OTHER),
+            Self::Other { subtype, .. } => [
+                (control_types::OTHER | (1 << 15)).to_be_bytes(),
+                subtype.to_be_bytes(),
+                [0, 0],
+                [0, 0],
+            ]
+            .concat(),
         }
     }
 
@@ -95,8 +121,8 @@ impl ControlPacketInfo {
             Self::Handshake(h) => h.raw_content(),
             Self::Ack(ack) => ack.raw_content(),
             Self::Nak(nak) => nak.raw_content(),
-            Self::DropReq(_) => todo!(),
-            Self::Other => todo!(),
+            Self::DropReq(drop_req) => drop_req.raw_content(),
+            Self::Other { content, .. } => content.clone(),
 
             // Other types don't have CIF
             _ => Vec::new(),
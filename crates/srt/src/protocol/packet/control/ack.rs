@@ -1,6 +1,10 @@
 //! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.4>
 
 use super::control_types;
+use crate::{
+    error::{Error, Result},
+    protocol::packet::read_u32,
+};
 
 #[derive(Clone, Debug)]
 pub enum Ack {
@@ -31,20 +35,36 @@ pub enum Ack {
 }
 
 impl Ack {
+    pub fn last_ackd_packet_sequence_number(&self) -> u32 {
+        match self {
+            Self::Full {
+                last_ackd_packet_sequence_number,
+                ..
+            }
+            | Self::Light {
+                last_ackd_packet_sequence_number,
+            }
+            | Self::Small {
+                last_ackd_packet_sequence_number,
+                ..
+            } => *last_ackd_packet_sequence_number,
+        }
+    }
+
     /// 44 BYTES
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
         match raw.len() {
             // Full
             44 => {
-                let ack_number = u32::from_be_bytes(raw[4..8].try_into()?);
+                let ack_number = read_u32(raw, 4, "ACK")?;
 
-                let last_ackd_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
-                let rtt = u32::from_be_bytes(raw[20..24].try_into()?);
-                let rtt_variance = u32::from_be_bytes(raw[24..28].try_into()?);
-                let available_buffer_size = u32::from_be_bytes(raw[28..32].try_into()?);
-                let packets_receiving_rate = u32::from_be_bytes(raw[32..36].try_into()?);
-                let estimated_link_capacity = u32::from_be_bytes(raw[36..40].try_into()?);
-                let receiving_rate = u32::from_be_bytes(raw[40..44].try_into()?);
+                let last_ackd_packet_sequence_number = read_u32(raw, 16, "ACK")?;
+                let rtt = read_u32(raw, 20, "ACK")?;
+                let rtt_variance = read_u32(raw, 24, "ACK")?;
+                let available_buffer_size = read_u32(raw, 28, "ACK")?;
+                let packets_receiving_rate = read_u32(raw, 32, "ACK")?;
+                let estimated_link_capacity = read_u32(raw, 36, "ACK")?;
+                let receiving_rate = read_u32(raw, 40, "ACK")?;
 
                 Ok(Self::Full {
                     ack_number,
@@ -59,7 +79,7 @@ impl Ack {
             }
             // Light
             20 => {
-                let last_ackd_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
+                let last_ackd_packet_sequence_number = read_u32(raw, 16, "ACK")?;
 
                 Ok(Self::Light {
                     last_ackd_packet_sequence_number,
@@ -67,10 +87,10 @@ impl Ack {
             }
             // Small
             32 => {
-                let last_ackd_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
-                let rtt = u32::from_be_bytes(raw[20..24].try_into()?);
-                let rtt_variance = u32::from_be_bytes(raw[24..28].try_into()?);
-                let available_buffer_size = u32::from_be_bytes(raw[28..32].try_into()?);
+                let last_ackd_packet_sequence_number = read_u32(raw, 16, "ACK")?;
+                let rtt = read_u32(raw, 20, "ACK")?;
+                let rtt_variance = read_u32(raw, 24, "ACK")?;
+                let available_buffer_size = read_u32(raw, 28, "ACK")?;
 
                 Ok(Self::Small {
                     last_ackd_packet_sequence_number,
@@ -79,7 +99,7 @@ impl Ack {
                     available_buffer_size,
                 })
             }
-            _ => Err(anyhow::anyhow!("Wrong package size")),
+            len => Err(Error::BadValue("ACK size", len as u32)),
         }
     }
 
@@ -1,4 +1,5 @@
 use super::control_types;
+use crate::{error::Result, protocol::packet::read_u32};
 
 #[derive(Clone, Debug)]
 pub struct AckAck {
@@ -6,8 +7,8 @@ pub struct AckAck {
 }
 
 impl AckAck {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let ack_number = u32::from_be_bytes(raw[4..8].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let ack_number = read_u32(raw, 4, "ACKACK")?;
 
         Ok(Self { ack_number })
     }
@@ -1,18 +1,36 @@
-use anyhow::Result;
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.9>
+
+use super::control_types;
+use crate::{error::Result, protocol::packet::read_u32};
 
 #[derive(Clone, Debug)]
 pub struct DropReq {
+    // Header
     pub message_number: u32,
+
+    // CIF
     pub first_packet_sequence_number: u32,
     pub last_packet_sequence_number: u32,
 }
 
 impl DropReq {
+    pub fn new(
+        message_number: u32,
+        first_packet_sequence_number: u32,
+        last_packet_sequence_number: u32,
+    ) -> Self {
+        Self {
+            message_number,
+            first_packet_sequence_number,
+            last_packet_sequence_number,
+        }
+    }
+
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let message_number = u32::from_be_bytes(raw[4..8].try_into()?);
+        let message_number = read_u32(raw, 4, "DROPREQ")?;
 
-        let first_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
-        let last_packet_sequence_number = u32::from_be_bytes(raw[20..24].try_into()?);
+        let first_packet_sequence_number = read_u32(raw, 16, "DROPREQ")?;
+        let last_packet_sequence_number = read_u32(raw, 20, "DROPREQ")?;
 
         Ok(Self {
             message_number,
@@ -21,7 +39,23 @@ impl DropReq {
         })
     }
 
+    /// 8 BYTES
     pub fn raw_header(&self) -> Vec<u8> {
-        todo!()
+        let mut res = Vec::new();
+
+        res.extend((control_types::DROPREQ | (1 << 15)).to_be_bytes()); // Control Flag + Control Type
+        res.extend(0u16.to_be_bytes()); // Reserved
+        res.extend(self.message_number.to_be_bytes()); // Message Number
+
+        res
+    }
+
+    /// 8 BYTES
+    pub fn raw_content(&self) -> Vec<u8> {
+        [
+            self.first_packet_sequence_number.to_be_bytes(),
+            self.last_packet_sequence_number.to_be_bytes(),
+        ]
+        .concat()
     }
 }
@@ -1,20 +1,31 @@
 //! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1>
 
-use crate::macros::auto_try_from;
+use crate::{
+    error::{Error, Result},
+    macros::auto_try_from,
+    protocol::packet::{read_u16, read_u32},
+};
 
 pub mod extension;
+pub mod reject_reason;
 
-use self::extension::{
-    extension_flags,
-    handshake::HandshakeExtension,
-    key_material::KeyMaterialExtension,
-    stream_id::StreamIdExtension,
+use self::{
+    extension::{
+        Extension,
+        congestion::CongestionExtension,
+        filter::FilterExtension,
+        group_membership::GroupMembershipExtension,
+        handshake::HandshakeExtension,
+        key_material::KeyMaterialExtension,
+        stream_id::StreamIdExtension,
+    },
+    reject_reason::{REJECTION_BASE, RejectReason},
 };
 
 auto_try_from! {
     #[repr(u16)]
     /// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Table 2)
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
     pub enum HandshakeEncryption {
         NoEncryption = 0,
         AES128 = 2,
@@ -23,16 +34,46 @@ auto_try_from! {
     }
 }
 
-auto_try_from! {
-    #[repr(u32)]
-    /// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Table 4)
-    #[derive(Clone, Copy, Debug, PartialEq)]
-    pub enum HandshakeType {
-        Done = 0xFF_FF_FF_FD,
-        Agreement = 0xFF_FF_FF_FE,
-        Conclusion = 0xFF_FF_FF_FF,
-        WaveHand = 0x00_00_00_00,
-        Induction = 0x00_00_00_01,
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Table 4)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandshakeType {
+    Done,
+    Agreement,
+    Conclusion,
+    WaveHand,
+    Induction,
+    /// Listener's refusal (`1000 + code`)
+    Rejected(RejectReason),
+}
+
+impl TryFrom<u32> for HandshakeType {
+    type Error = Error;
+
+    fn try_from(v: u32) -> Result<Self> {
+        match v {
+            0xFF_FF_FF_FD => Ok(Self::Done),
+            0xFF_FF_FF_FE => Ok(Self::Agreement),
+            0xFF_FF_FF_FF => Ok(Self::Conclusion),
+            0x00_00_00_00 => Ok(Self::WaveHand),
+            0x00_00_00_01 => Ok(Self::Induction),
+            v if v >= REJECTION_BASE => {
+                Ok(Self::Rejected(RejectReason::from_code(v - REJECTION_BASE)))
+            }
+            _ => Err(Error::BadValue("HandshakeType", v)),
+        }
+    }
+}
+
+impl From<HandshakeType> for u32 {
+    fn from(handshake_type: HandshakeType) -> Self {
+        match handshake_type {
+            HandshakeType::Done => 0xFF_FF_FF_FD,
+            HandshakeType::Agreement => 0xFF_FF_FF_FE,
+            HandshakeType::Conclusion => 0xFF_FF_FF_FF,
+            HandshakeType::WaveHand => 0x00_00_00_00,
+            HandshakeType::Induction => 0x00_00_00_01,
+            HandshakeType::Rejected(reason) => REJECTION_BASE.saturating_add(reason.code()),
+        }
     }
 }
 
@@ -55,54 +96,76 @@ pub struct Handshake {
     pub srt_socket_id: u32,
     pub syn_cookie: u32,
     pub peer_ip_address: (u32, u32, u32, u32),
-    pub handshake_extension: Option<HandshakeExtension>,
-    pub key_material_extension: Option<KeyMaterialExtension>,
-    pub stream_id_extension: Option<StreamIdExtension>,
+    /// Extension blocks of a Conclusion, in the order they are carried
+    pub extensions: Vec<Extension>,
+}
+
+/// Accessor of the first extension of a variant
+macro_rules! extension_getter {
+    ($(#[$meta:meta])* $name:ident, $variant:ident, $type:ty) => {
+        $(#[$meta])*
+        pub fn $name(&self) -> Option<&$type> {
+            self.extensions.iter().find_map(|extension| match extension {
+                Extension::$variant(extension) => Some(extension),
+                _ => None,
+            })
+        }
+    };
 }
 
 impl Handshake {
-    pub fn from_raw_cif(raw: &[u8]) -> anyhow::Result<Self> {
-        let version = u32::from_be_bytes(raw[0..4].try_into()?);
+    extension_getter!(
+        /// `HSREQ` or `HSRSP`
+        handshake_extension,
+        Handshake,
+        HandshakeExtension
+    );
 
-        let encryption = u16::from_be_bytes(raw[4..6].try_into()?).try_into()?;
-        let extension_field = u16::from_be_bytes(raw[6..8].try_into()?);
+    extension_getter!(
+        /// `KMREQ` or `KMRSP`
+        key_material_extension,
+        KeyMaterial,
+        KeyMaterialExtension
+    );
 
-        let initial_packet_sequence_number = u32::from_be_bytes(raw[8..12].try_into()?);
-        let maximum_transmission_unit_size = u32::from_be_bytes(raw[12..16].try_into()?);
-        let maximum_flow_window_size = u32::from_be_bytes(raw[16..20].try_into()?);
-        let handshake_type = u32::from_be_bytes(raw[20..24].try_into()?).try_into()?;
-        let srt_socket_id = u32::from_be_bytes(raw[24..28].try_into()?);
-        let syn_cookie = u32::from_be_bytes(raw[28..32].try_into()?);
+    extension_getter!(stream_id_extension, StreamId, StreamIdExtension);
 
-        let peer_ip_address = (
-            u32::from_be_bytes(raw[32..36].try_into()?),
-            u32::from_be_bytes(raw[36..40].try_into()?),
-            u32::from_be_bytes(raw[40..44].try_into()?),
-            u32::from_be_bytes(raw[44..48].try_into()?),
-        );
+    extension_getter!(congestion_extension, Congestion, CongestionExtension);
 
-        // Extensions
-        let mut ext_pad = 0;
+    extension_getter!(filter_extension, Filter, FilterExtension);
 
-        let handshake_extension = if extension_field & extension_flags::HSREQ != 0 {
-            ext_pad += 4 * 4;
-            Some(HandshakeExtension::from_raw(&raw[48..])?)
-        } else {
-            None
-        };
+    extension_getter!(
+        group_membership_extension,
+        GroupMembership,
+        GroupMembershipExtension
+    );
 
-        let key_material_extension = if (extension_field & extension_flags::KMREQ != 0)
-            && handshake_type != HandshakeType::Induction
-        {
-            Some(KeyMaterialExtension::from_raw(&raw[(48 + ext_pad)..])?)
-        } else {
-            None
-        };
+    pub fn from_raw_cif(raw: &[u8]) -> Result<Self> {
+        let version = read_u32(raw, 0, "handshake")?;
 
-        let stream_id_extension = if extension_field & extension_flags::CONFIG != 0 {
-            Some(StreamIdExtension::from_raw(&raw[(48 + ext_pad)..])?)
+        let encryption = read_u16(raw, 4, "handshake")?.try_into()?;
+        let extension_field = read_u16(raw, 6, "handshake")?;
+
+        let initial_packet_sequence_number = read_u32(raw, 8, "handshake")?;
+        let maximum_transmission_unit_size = read_u32(raw, 12, "handshake")?;
+        let maximum_flow_window_size = read_u32(raw, 16, "handshake")?;
+        let handshake_type = read_u32(raw, 20, "handshake")?.try_into()?;
+        let srt_socket_id = read_u32(raw, 24, "handshake")?;
+        let syn_cookie = read_u32(raw, 28, "handshake")?;
+
+        let peer_ip_address = (
+            read_u32(raw, 32, "handshake")?,
+            read_u32(raw, 36, "handshake")?,
+            read_u32(raw, 40, "handshake")?,
+            read_u32(raw, 44, "handshake")?,
+        );
+
+        // Extensions
+        // (in other phases `Extension Field` holds a version-specific value, e.g. the magic code)
+        let extensions = if handshake_type == HandshakeType::Conclusion && extension_field != 0 {
+            Extension::from_raw_list(raw.get(48..).unwrap_or_default())?
         } else {
-            None
+            Vec::new()
         };
 
         Ok(Self {
@@ -116,9 +179,7 @@ impl Handshake {
             srt_socket_id,
             syn_cookie,
             peer_ip_address,
-            handshake_extension,
-            key_material_extension,
-            stream_id_extension,
+            extensions,
         })
     }
 
@@ -131,7 +192,7 @@ impl Handshake {
         res.extend(self.initial_packet_sequence_number.to_be_bytes());
         res.extend(self.maximum_transmission_unit_size.to_be_bytes());
         res.extend(self.maximum_flow_window_size.to_be_bytes());
-        res.extend((self.handshake_type as u32).to_be_bytes());
+        res.extend(u32::from(self.handshake_type).to_be_bytes());
         res.extend(self.srt_socket_id.to_be_bytes());
         res.extend(self.syn_cookie.to_be_bytes());
 
@@ -140,16 +201,130 @@ impl Handshake {
         res.extend(self.peer_ip_address.2.to_be_bytes());
         res.extend(self.peer_ip_address.3.to_be_bytes());
 
-        if let Some(ext) = &self.handshake_extension {
-            res.extend(ext.to_raw());
+        for extension in &self.extensions {
+            res.extend(extension.to_raw());
         }
-        if let Some(ext) = &self.key_material_extension {
-            res.extend(ext.to_raw());
-        }
-        // if let Some(ext) = &self.stream_id_extension {
-        //     res.extend(ext.to_raw());
-        // }
 
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use anyhow::Context;
+
+    use super::*;
+    use crate::{
+        options::Options,
+        protocol::{
+            ops,
+            packet::control::handshake::extension::{extension_flags, extension_types},
+        },
+    };
+
+    #[test]
+    fn test_extensions_round_trip() -> anyhow::Result<()> {
+        let options = Options {
+            passphrase: Some("passphrase".to_owned()),
+            ..Options::new()
+        };
+        let (_, key_material) = ops::key_material_request(&options)?.context("No KMREQ")?;
+
+        // Encrypted publisher ordered like libsrt: `KMREQ` after the stream ID
+        let handshake = Handshake {
+            extension_field: extension_flags::HSREQ
+                | extension_flags::KMREQ
+                | extension_flags::CONFIG,
+            handshake_type: HandshakeType::Conclusion,
+            extensions: vec![
+                Extension::Handshake(HandshakeExtension {
+                    r#type: extension_types::HSREQ,
+                    length: 3,
+                    srt_version: 0x01_05_03,
+                    srt_flags: 0xBF,
+                    receiver_delay: 120,
+                    sender_delay: 120,
+                }),
+                Extension::StreamId(StreamIdExtension::new(
+                    "#!::r=live/cam1,m=publish".to_owned(),
+                )?),
+                Extension::KeyMaterial(key_material.clone()),
+                Extension::Unknown(0x10, vec![1, 2, 3, 4]),
+            ],
+            ..ops::induction_request(1, 2, Ipv4Addr::LOCALHOST.into())
+        };
+        let raw = handshake.raw_content();
+
+        let parsed = Handshake::from_raw_cif(&raw)?;
+        assert_eq!(
+            parsed
+                .stream_id_extension()
+                .map(|extension| extension.stream_id.as_str()),
+            Some("#!::r=live/cam1,m=publish")
+        );
+        assert_eq!(
+            parsed
+                .key_material_extension()
+                .map(|extension| &extension.wrapped_key),
+            Some(&key_material.wrapped_key)
+        );
+        assert!(matches!(
+            &parsed.extensions[3],
+            Extension::Unknown(0x10, contents) if contents == &[1, 2, 3, 4]
+        ));
+        assert_eq!(parsed.raw_content(), raw);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_extensions() {
+        let handshake = Handshake {
+            extension_field: extension_flags::HSREQ | extension_flags::CONFIG,
+            handshake_type: HandshakeType::Conclusion,
+            extensions: vec![
+                Extension::Handshake(HandshakeExtension {
+                    r#type: extension_types::HSREQ,
+                    length: 3,
+                    srt_version: 0x01_05_03,
+                    srt_flags: 0xBF,
+                    receiver_delay: 120,
+                    sender_delay: 120,
+                }),
+                Extension::GroupMembership(GroupMembershipExtension {
+                    group_id: 0x4000_0001,
+                    r#type: 1,
+                    flags: 0,
+                    weight: 0,
+                }),
+            ],
+            ..ops::induction_request(1, 2, Ipv4Addr::LOCALHOST.into())
+        };
+        let mut raw = handshake.raw_content();
+
+        for length in 0..raw.len() {
+            let parsed = Handshake::from_raw_cif(&raw[..length]);
+            match length {
+                ..48 => assert_eq!(parsed.err(), Some(Error::Truncated("handshake"))),
+                52..64 => assert_eq!(
+                    parsed.err(),
+                    Some(Error::BadExtension(extension_types::HSREQ))
+                ),
+                68..76 => assert_eq!(
+                    parsed.err(),
+                    Some(Error::BadExtension(extension_types::GROUP))
+                ),
+                _ => assert!(parsed.is_ok()),
+            }
+        }
+
+        // Block claiming fewer words than its type needs
+        raw[50..52].copy_from_slice(&1u16.to_be_bytes());
+        assert_eq!(
+            Handshake::from_raw_cif(&raw[..56]).err(),
+            Some(Error::BadExtension(extension_types::HSREQ))
+        );
+    }
+}
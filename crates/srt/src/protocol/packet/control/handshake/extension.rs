@@ -1,10 +1,154 @@
+pub mod congestion;
+pub mod filter;
 pub mod group_membership;
 pub mod handshake;
 pub mod key_material;
 pub mod stream_id;
 
+use self::{
+    congestion::CongestionExtension,
+    filter::FilterExtension,
+    group_membership::GroupMembershipExtension,
+    handshake::HandshakeExtension,
+    key_material::KeyMaterialExtension,
+    stream_id::StreamIdExtension,
+};
+use crate::{
+    error::{Error, Result},
+    protocol::packet::read_u16,
+};
+
 pub mod extension_flags {
     pub const HSREQ: u16 = 0x00_01;
     pub const KMREQ: u16 = 0x00_02;
     pub const CONFIG: u16 = 0x00_04;
 }
+
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Table 5)
+pub mod extension_types {
+    pub const HSREQ: u16 = 1;
+    pub const HSRSP: u16 = 2;
+    pub const KMREQ: u16 = 3;
+    pub const KMRSP: u16 = 4;
+    pub const SID: u16 = 5;
+    pub const CONGESTION: u16 = 6;
+    pub const FILTER: u16 = 7;
+    pub const GROUP: u16 = 8;
+}
+
+/// Block of a Conclusion handshake: `Extension Type`, `Extension Length` (words)
+/// and `Extension Contents`
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Figure 6)
+#[derive(Clone, Debug)]
+pub enum Extension {
+    /// `HSREQ` or `HSRSP`
+    Handshake(HandshakeExtension),
+    /// `KMREQ` or `KMRSP`
+    KeyMaterial(KeyMaterialExtension),
+    StreamId(StreamIdExtension),
+    Congestion(CongestionExtension),
+    Filter(FilterExtension),
+    GroupMembership(GroupMembershipExtension),
+    /// Type not known to this implementation, kept as is: `(type, contents)`
+    Unknown(u16, Vec<u8>),
+}
+
+impl Extension {
+    /// Parse the blocks filling `raw`
+    pub fn from_raw_list(mut raw: &[u8]) -> Result<Vec<Self>> {
+        let mut extensions = Vec::new();
+        while raw.len() >= 4 {
+            let r#type = read_u16(raw, 0, "handshake extension")?;
+            let length = read_u16(raw, 2, "handshake extension")?;
+            let size = 4 + length as usize * 4;
+            let block = raw.get(..size).ok_or(Error::BadExtension(r#type))?;
+
+            extensions.push(Self::from_raw(block)?);
+            raw = &raw[size..];
+        }
+
+        Ok(extensions)
+    }
+
+    /// Parse a single block (`raw` holds exactly its header and contents)
+    ///
+    /// Malformed contents are reported as [`Error::BadExtension`], except for a `KMRSP`
+    /// reporting a failure ([`Error::KeyMaterialState`]).
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = read_u16(raw, 0, "handshake extension")?;
+
+        Self::from_raw_typed(r#type, raw).map_err(|e| match e {
+            Error::KeyMaterialState(_) => e,
+            _ => Error::BadExtension(r#type),
+        })
+    }
+
+    fn from_raw_typed(r#type: u16, raw: &[u8]) -> Result<Self> {
+        Ok(match r#type {
+            extension_types::HSREQ | extension_types::HSRSP => {
+                Self::Handshake(HandshakeExtension::from_raw(raw)?)
+            }
+            extension_types::KMREQ | extension_types::KMRSP => {
+                Self::KeyMaterial(KeyMaterialExtension::from_raw(raw)?)
+            }
+            extension_types::SID => Self::StreamId(StreamIdExtension::from_raw(raw)?),
+            extension_types::CONGESTION => Self::Congestion(CongestionExtension::from_raw(raw)?),
+            extension_types::FILTER => Self::Filter(FilterExtension::from_raw(raw)?),
+            extension_types::GROUP => {
+                Self::GroupMembership(GroupMembershipExtension::from_raw(raw)?)
+            }
+            _ => {
+                tracing::debug!("Keeping unknown handshake extension {}", r#type);
+                Self::Unknown(r#type, raw.get(4..).unwrap_or_default().to_vec())
+            }
+        })
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            Self::Handshake(extension) => extension.to_raw(),
+            Self::KeyMaterial(extension) => extension.to_raw(),
+            Self::StreamId(extension) => extension.to_raw(),
+            Self::Congestion(extension) => extension.to_raw(),
+            Self::Filter(extension) => extension.to_raw(),
+            Self::GroupMembership(extension) => extension.to_raw(),
+            Self::Unknown(r#type, contents) => {
+                let mut raw = Vec::new();
+                raw.extend(r#type.to_be_bytes());
+                raw.extend(((contents.len() / 4) as u16).to_be_bytes());
+                raw.extend(contents);
+
+                raw
+            }
+        }
+    }
+}
+
+/// Decode a string zero-padded to whole words with the bytes of each word reversed
+/// (`SID`, `CONGESTION` and `FILTER`)
+pub(crate) fn string_from_words(words: &[u8]) -> String {
+    let mut bytes: Vec<u8> = words
+        .chunks_exact(4)
+        .flat_map(|word| word.iter().rev())
+        .copied()
+        .collect();
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Encode `string` into `length` words, like [`string_from_words`] decodes them
+pub(crate) fn string_to_words(string: &str, length: u16) -> Vec<u8> {
+    let mut bytes = string.as_bytes().to_vec();
+    bytes.resize(length as usize * 4, 0);
+
+    bytes
+        .chunks_exact(4)
+        .flat_map(|word| word.iter().rev())
+        .copied()
+        .collect()
+}
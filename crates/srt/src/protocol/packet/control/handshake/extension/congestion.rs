@@ -0,0 +1,55 @@
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Table 5)
+
+use anyhow::bail;
+
+use super::{extension_types, string_from_words, string_to_words};
+use crate::{
+    error::{Error, Result},
+    protocol::{constants::MAX_CONGESTION_LENGTH, packet::read_u16},
+};
+
+/// Congestion control type (`"live"` or `"file"`), encoded like the stream ID
+#[derive(Clone, Debug)]
+pub struct CongestionExtension {
+    pub r#type: u16,
+    pub length: u16,
+    pub congestion: String,
+}
+
+impl CongestionExtension {
+    pub fn new(congestion: &str) -> anyhow::Result<Self> {
+        if congestion.len() > MAX_CONGESTION_LENGTH {
+            bail!("Congestion control type exceeds {MAX_CONGESTION_LENGTH} bytes");
+        }
+
+        Ok(Self {
+            r#type: extension_types::CONGESTION,
+            length: congestion.len().div_ceil(4).try_into()?,
+            congestion: congestion.to_owned(),
+        })
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = read_u16(raw, 0, "congestion type")?;
+        let length = read_u16(raw, 2, "congestion type")?;
+
+        let words = raw
+            .get(4..4 + length as usize * 4)
+            .ok_or(Error::Truncated("congestion type"))?;
+
+        Ok(Self {
+            r#type,
+            length,
+            congestion: string_from_words(words),
+        })
+    }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend(self.r#type.to_be_bytes());
+        raw.extend(self.length.to_be_bytes());
+        raw.extend(string_to_words(&self.congestion, self.length));
+
+        raw
+    }
+}
@@ -0,0 +1,55 @@
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1> (Table 5)
+
+use anyhow::bail;
+
+use super::{extension_types, string_from_words, string_to_words};
+use crate::{
+    error::{Error, Result},
+    protocol::{constants::MAX_FILTER_LENGTH, packet::read_u16},
+};
+
+/// Packet filter configuration (e.g. `"fec,cols:10,rows:5"`), encoded like the stream ID
+#[derive(Clone, Debug)]
+pub struct FilterExtension {
+    pub r#type: u16,
+    pub length: u16,
+    pub filter: String,
+}
+
+impl FilterExtension {
+    pub fn new(filter: &str) -> anyhow::Result<Self> {
+        if filter.len() > MAX_FILTER_LENGTH {
+            bail!("Packet filter configuration exceeds {MAX_FILTER_LENGTH} bytes");
+        }
+
+        Ok(Self {
+            r#type: extension_types::FILTER,
+            length: filter.len().div_ceil(4).try_into()?,
+            filter: filter.to_owned(),
+        })
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = read_u16(raw, 0, "packet filter")?;
+        let length = read_u16(raw, 2, "packet filter")?;
+
+        let words = raw
+            .get(4..4 + length as usize * 4)
+            .ok_or(Error::Truncated("packet filter"))?;
+
+        Ok(Self {
+            r#type,
+            length,
+            filter: string_from_words(words),
+        })
+    }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend(self.r#type.to_be_bytes());
+        raw.extend(self.length.to_be_bytes());
+        raw.extend(string_to_words(&self.filter, self.length));
+
+        raw
+    }
+}
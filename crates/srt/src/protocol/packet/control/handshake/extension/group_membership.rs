@@ -1,13 +1,23 @@
 //! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1.4>
 
+use super::extension_types;
+use crate::{
+    error::{Error, Result},
+    protocol::packet::{read_u16, read_u32},
+};
+
+/// `Type` of a group
 pub mod group_type {
-    pub const UNDEFINED: u32 = 0;
-    pub const BROADCAST: u32 = 1;
-    pub const MAIN_BACKUP: u32 = 2;
-    pub const BALANCING: u32 = 3;
-    pub const MULTICAST: u32 = 4;
+    pub const UNDEFINED: u8 = 0;
+    pub const BROADCAST: u8 = 1;
+    pub const MAIN_BACKUP: u8 = 2;
+    pub const BALANCING: u8 = 3;
+    pub const MULTICAST: u8 = 4;
 }
 
+/// Group the connection is a member of: `Group ID` (socket ID of the sender's group),
+/// `Type`, `Flags` and `Weight` (priority of a main/backup link)
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct GroupMembershipExtension {
     pub group_id: u32,
     pub r#type: u8,
@@ -16,11 +26,18 @@ pub struct GroupMembershipExtension {
 }
 
 impl GroupMembershipExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let group_id = u32::from_be_bytes(raw[4..8].try_into()?);
+    /// (words) `Extension Length`
+    const LENGTH: u16 = 2;
+
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let raw = raw
+            .get(..4 + Self::LENGTH as usize * 4)
+            .ok_or(Error::Truncated("group membership"))?;
+
+        let group_id = read_u32(raw, 4, "group membership")?;
         let r#type = raw[8];
         let flags = raw[9];
-        let weight = u16::from_be_bytes(raw[10..12].try_into()?);
+        let weight = read_u16(raw, 10, "group membership")?;
 
         Ok(Self {
             group_id,
@@ -29,4 +46,15 @@ impl GroupMembershipExtension {
             weight,
         })
     }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend(extension_types::GROUP.to_be_bytes());
+        raw.extend(Self::LENGTH.to_be_bytes());
+        raw.extend(self.group_id.to_be_bytes());
+        raw.extend([self.r#type, self.flags]);
+        raw.extend(self.weight.to_be_bytes());
+
+        raw
+    }
 }
@@ -1,4 +1,8 @@
-use crate::macros::simple_raw;
+use crate::{
+    error::{Error, Result},
+    macros::simple_raw,
+    protocol::packet::{read_u16, read_u32},
+};
 
 pub mod handshake_extension_message_flags {
     pub const TSBPDSND: u32 = 0x00_00_00_01;
@@ -25,14 +29,14 @@ simple_raw! {
 }
 
 impl HandshakeExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let r#type = u16::from_be_bytes(raw[0..2].try_into()?);
-        let length = u16::from_be_bytes(raw[2..4].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = read_u16(raw, 0, "handshake extension")?;
+        let length = read_u16(raw, 2, "handshake extension")?;
 
-        let srt_version = u32::from_be_bytes(raw[4..8].try_into()?);
-        let srt_flags = u32::from_be_bytes(raw[8..12].try_into()?);
-        let receiver_delay = u16::from_be_bytes(raw[12..14].try_into()?);
-        let sender_delay = u16::from_be_bytes(raw[14..16].try_into()?);
+        let srt_version = read_u32(raw, 4, "handshake extension")?;
+        let srt_flags = read_u32(raw, 8, "handshake extension")?;
+        let receiver_delay = read_u16(raw, 12, "handshake extension")?;
+        let sender_delay = read_u16(raw, 14, "handshake extension")?;
 
         Ok(Self {
             r#type,
@@ -43,4 +47,20 @@ impl HandshakeExtension {
             sender_delay,
         })
     }
+
+    /// Parse the contents without the extension header
+    /// (as carried by `HSREQ`/`HSRSP` control packets of HSv4 connections)
+    pub fn from_raw_message(r#type: u16, raw: &[u8]) -> Result<Self> {
+        let raw = raw
+            .get(..12)
+            .ok_or(Error::Truncated("handshake extension"))?;
+        let header = [r#type.to_be_bytes(), 3u16.to_be_bytes()].concat();
+
+        Self::from_raw(&[header.as_slice(), raw].concat())
+    }
+
+    /// Contents without the extension header
+    pub fn raw_message(&self) -> Vec<u8> {
+        self.to_raw().split_off(4)
+    }
 }
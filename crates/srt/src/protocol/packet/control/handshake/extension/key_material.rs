@@ -1,6 +1,15 @@
-use anyhow::bail;
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.2>
 
-#[derive(Clone, Debug)]
+use crate::{
+    error::{Error, Result},
+    macros::auto_try_from,
+    protocol::packet::{read_u16, read_u32},
+};
+
+/// `Sign` field (`"HAI"` in PnP Vendor ID format)
+const SIGN: u16 = 0x2029;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum KeyBasedEncryption {
     // None,
     EvenKey,
@@ -8,42 +17,142 @@ pub enum KeyBasedEncryption {
     Both,
 }
 
+auto_try_from! {
+    #[repr(u8)]
+    /// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.2> (`Cipher`)
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Cipher {
+        None = 0,
+        AesEcb = 1,
+        AesCtr = 2,
+        AesCbc = 3,
+        AesGcm = 4,
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct KeyMaterialExtension {
     pub r#type: u16,
     pub length: u16,
     pub packet_type: u8,
     pub key_based_encryption: KeyBasedEncryption,
+    pub keki: u32,
+    pub cipher: Cipher,
+    pub auth: u8,
+    pub stream_encapsulation: u8,
+    pub salt: Vec<u8>,
+    /// Length of a single Stream Encrypting Key (bytes)
+    pub key_length: usize,
+    /// Stream Encrypting Key(s), wrapped with the passphrase-derived key
+    pub wrapped_key: Vec<u8>,
 }
 
 impl KeyMaterialExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let r#type = u16::from_be_bytes(raw[0..2].try_into()?);
-        let length = u16::from_be_bytes(raw[2..4].try_into()?);
-
-        let packet_type = raw[4] & 0b0000_1111;
-        // let sign = u16::from_be_bytes(raw[5..7].try_into()?); // = 0x2029
-        let key_based_encryption = match raw[7] & 0x0000_0011 {
-            0b00 => bail!("Invalid extension format"),
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = read_u16(raw, 0, "key material")?;
+        let length = read_u16(raw, 2, "key material")?;
+
+        if length == 1 {
+            // `KMRSP` reporting a failure (`KM State`)
+            let state = read_u32(raw, 4, "key material")?;
+            return Err(Error::KeyMaterialState(state));
+        }
+
+        Self::from_raw_message(r#type, &raw[4..])
+    }
+
+    /// Parse a Key Material message without the extension header
+    /// (as carried in-band by `KMREQ`/`KMRSP` control packets)
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_raw_message(r#type: u16, raw: &[u8]) -> Result<Self> {
+        if raw.len() < 16 {
+            return Err(Error::Truncated("key material"));
+        }
+
+        let packet_type = raw[0] & 0b0000_1111;
+        // let sign = u16::from_be_bytes(raw[1..3].try_into()?); // = 0x2029
+        let key_based_encryption = match raw[3] & 0b0000_0011 {
             0b01 => KeyBasedEncryption::EvenKey,
             0b10 => KeyBasedEncryption::OddKey,
             0b11 => KeyBasedEncryption::Both,
-            _ => unreachable!(),
+            flags => return Err(Error::BadValue("key-based encryption", flags.into())),
         };
-        // let keki = u32::from_be_bytes(raw[8..12].try_into()?); // = 0
-        let _cipher = u8::from_be_bytes(raw[12..13].try_into()?);
-        // let auth = u8::from_be_bytes(raw[13..14].try_into()?); // = 0
-        let _stream_encapsulation = u8::from_be_bytes(raw[14..15].try_into()?);
+        let keki = read_u32(raw, 4, "key material")?;
+        let cipher = raw[8].try_into()?;
+        let auth = raw[9];
+        let stream_encapsulation = raw[10];
+
+        let salt_length = raw[14] as usize * 4;
+        let key_length = raw[15] as usize * 4;
+        let keys = if key_based_encryption == KeyBasedEncryption::Both {
+            2
+        } else {
+            1
+        };
+
+        let wrapped_start = 16 + salt_length;
+        let wrapped_end = wrapped_start + key_length * keys + 8;
+        let salt = raw
+            .get(16..wrapped_start)
+            .ok_or(Error::Truncated("key material salt"))?
+            .to_vec();
+        let wrapped_key = raw
+            .get(wrapped_start..wrapped_end)
+            .ok_or(Error::Truncated("key material wrapped key"))?
+            .to_vec();
 
         Ok(Self {
             r#type,
-            length,
+            length: (wrapped_end / 4) as u16,
             packet_type,
             key_based_encryption,
+            keki,
+            cipher,
+            auth,
+            stream_encapsulation,
+            salt,
+            key_length,
+            wrapped_key,
         })
     }
 
     pub fn to_raw(&self) -> Vec<u8> {
-        todo!()
+        let mut res = Vec::new();
+
+        res.extend(self.r#type.to_be_bytes());
+        res.extend(self.length.to_be_bytes());
+        res.extend(self.raw_message());
+
+        res
+    }
+
+    /// Key Material message without the extension header
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn raw_message(&self) -> Vec<u8> {
+        let kk = match self.key_based_encryption {
+            KeyBasedEncryption::EvenKey => 0b01,
+            KeyBasedEncryption::OddKey => 0b10,
+            KeyBasedEncryption::Both => 0b11,
+        };
+
+        let mut res = Vec::new();
+
+        res.push((1 << 4) | self.packet_type); // S = 0, V = 1, PT
+        res.extend(SIGN.to_be_bytes());
+        res.push(kk); // Resv1 + KK
+        res.extend(self.keki.to_be_bytes());
+        res.extend([
+            self.cipher as u8,
+            self.auth,
+            self.stream_encapsulation,
+            0, // Resv2
+        ]);
+        res.extend(0u16.to_be_bytes()); // Resv3
+        res.push((self.salt.len() / 4) as u8);
+        res.push((self.key_length / 4) as u8);
+        res.extend(&self.salt);
+        res.extend(&self.wrapped_key);
+
+        res
     }
 }
@@ -1,3 +1,12 @@
+use anyhow::bail;
+
+use super::{extension_types, string_from_words, string_to_words};
+use crate::{
+    error::{Error, Result},
+    protocol::{constants::MAX_STREAM_ID_LENGTH, packet::read_u16},
+};
+
+/// Stream ID as UTF-8, zero-padded to whole words with the bytes of each word reversed
 #[derive(Clone, Debug)]
 pub struct StreamIdExtension {
     pub r#type: u16,
@@ -6,18 +15,26 @@ pub struct StreamIdExtension {
 }
 
 impl StreamIdExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let r#type = u16::from_be_bytes(raw[0..2].try_into()?);
-        let length = u16::from_be_bytes(raw[2..4].try_into()?);
+    pub fn new(stream_id: String) -> anyhow::Result<Self> {
+        if stream_id.len() > MAX_STREAM_ID_LENGTH {
+            bail!("Stream ID exceeds {MAX_STREAM_ID_LENGTH} bytes");
+        }
 
-        let mut stream_id = String::new();
+        Ok(Self {
+            r#type: extension_types::SID,
+            length: stream_id.len().div_ceil(4).try_into()?,
+            stream_id,
+        })
+    }
 
-        for i in 0..length {
-            let pos = 4 + i as usize * 4;
-            let mut bytes = Vec::from(&raw[pos..(pos + 4)]);
-            bytes.reverse();
-            stream_id += String::from_utf8_lossy(&bytes).trim_matches(char::from(0));
-        }
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = read_u16(raw, 0, "stream ID")?;
+        let length = read_u16(raw, 2, "stream ID")?;
+
+        let words = raw
+            .get(4..4 + length as usize * 4)
+            .ok_or(Error::Truncated("stream ID"))?;
+        let stream_id = string_from_words(words);
 
         Ok(Self {
             r#type,
@@ -27,6 +44,30 @@ impl StreamIdExtension {
     }
 
     pub fn to_raw(&self) -> Vec<u8> {
-        todo!()
+        let mut raw = Vec::new();
+        raw.extend(self.r#type.to_be_bytes());
+        raw.extend(self.length.to_be_bytes());
+        raw.extend(string_to_words(&self.stream_id, self.length));
+
+        raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let extension = StreamIdExtension::new("#!::r=live/cam1,u=zoë".to_owned())?;
+        let raw = extension.to_raw();
+
+        assert_eq!(raw.len(), 4 + 4 * extension.length as usize);
+        assert_eq!(&raw[4..8], b"::!#".as_slice());
+
+        let parsed = StreamIdExtension::from_raw(&raw)?;
+        assert_eq!(parsed.stream_id, extension.stream_id);
+
+        Ok(())
     }
 }
@@ -0,0 +1,151 @@
+//! Reasons for refusing a connection, sent as `1000 + code` in `Handshake Type`
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/API/rejection-codes.md>
+
+use std::fmt;
+
+use crate::macros::auto_try_from;
+
+/// `Handshake Type` values from here on are rejections
+pub const REJECTION_BASE: u32 = 1000;
+
+/// Codes from here on are [`RejectReason::Predefined`]
+const PREDEFINED_BASE: u32 = 1000;
+/// Codes from here on are [`RejectReason::User`]
+const USER_BASE: u32 = 2000;
+
+auto_try_from! {
+    #[repr(u32)]
+    /// `SRT_REJ_*`: refusals by the protocol itself
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum CoreReason {
+        Unknown = 0,
+        /// System function error
+        System = 1,
+        /// Rejected by the peer
+        Peer = 2,
+        /// Resource allocation failure
+        Resource = 3,
+        /// Malformed handshake
+        Rogue = 4,
+        /// Listener's backlog exceeded
+        Backlog = 5,
+        /// Internal program error
+        Ipe = 6,
+        /// Socket is closing
+        Close = 7,
+        /// Peer is too old
+        Version = 8,
+        /// Rendezvous cookie collision
+        RdvCookie = 9,
+        /// Wrong passphrase
+        BadSecret = 10,
+        /// Passphrase set on one side only
+        Unsecure = 11,
+        /// Message API mismatch
+        MessageApi = 12,
+        /// Congestion control mismatch
+        Congestion = 13,
+        /// Packet filter mismatch
+        Filter = 14,
+        /// Group settings mismatch
+        Group = 15,
+        /// Connection timed out
+        Timeout = 16,
+        /// Cipher mismatch
+        Crypto = 17,
+    }
+}
+
+/// Why a listener refused a connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    /// `SRT_REJ_*` (codes below 1000)
+    Core(CoreReason),
+    /// `SRT_REJX_*`: HTTP-like status, e.g. `403` for forbidden (code `1000 + status`)
+    ///
+    /// Statuses above 999 would reach into the [`Self::User`] codes: they are sent as 999.
+    Predefined(u16),
+    /// Application-specific (code `2000 + value`)
+    User(u32),
+}
+
+impl RejectReason {
+    /// `SRT_REJX_BAD_MODE`
+    pub const BAD_MODE: Self = Self::Predefined(405);
+    /// `SRT_REJX_BAD_REQUEST`
+    pub const BAD_REQUEST: Self = Self::Predefined(400);
+    /// `SRT_REJX_CONFLICT`
+    pub const CONFLICT: Self = Self::Predefined(409);
+    /// `SRT_REJX_FORBIDDEN`
+    pub const FORBIDDEN: Self = Self::Predefined(403);
+    /// `SRT_REJX_NOTFOUND`
+    pub const NOT_FOUND: Self = Self::Predefined(404);
+    /// `SRT_REJX_UNAUTHORIZED`
+    pub const UNAUTHORIZED: Self = Self::Predefined(401);
+
+    pub fn from_code(code: u32) -> Self {
+        if code >= USER_BASE {
+            Self::User(code - USER_BASE)
+        } else if code >= PREDEFINED_BASE {
+            #[allow(clippy::cast_possible_truncation)]
+            Self::Predefined((code - PREDEFINED_BASE) as u16)
+        } else {
+            Self::Core(code.try_into().unwrap_or(CoreReason::Unknown))
+        }
+    }
+
+    pub fn code(self) -> u32 {
+        match self {
+            Self::Core(reason) => reason as u32,
+            Self::Predefined(status) => {
+                PREDEFINED_BASE + u32::from(status).min(USER_BASE - PREDEFINED_BASE - 1)
+            }
+            Self::User(value) => USER_BASE.saturating_add(value),
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Core(reason) => write!(f, "Connection rejected ({reason:?})"),
+            Self::Predefined(status) => write!(f, "Connection rejected (status {status})"),
+            Self::User(value) => write!(f, "Connection rejected (user code {value})"),
+        }
+    }
+}
+
+impl std::error::Error for RejectReason {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        for reason in [
+            RejectReason::Core(CoreReason::BadSecret),
+            RejectReason::FORBIDDEN,
+            RejectReason::Predefined(999),
+            RejectReason::User(0),
+            RejectReason::User(7),
+        ] {
+            assert_eq!(RejectReason::from_code(reason.code()), reason);
+        }
+
+        assert_eq!(RejectReason::FORBIDDEN.code(), 1403);
+        assert_eq!(RejectReason::User(7).code(), 2007);
+
+        // Never mistaken for a user code
+        assert_eq!(RejectReason::Predefined(1000).code(), 1999);
+        assert_eq!(
+            RejectReason::from_code(RejectReason::Predefined(u16::MAX).code()),
+            RejectReason::Predefined(999)
+        );
+        assert_eq!(
+            RejectReason::from_code(999),
+            RejectReason::Core(CoreReason::Unknown)
+        );
+    }
+}
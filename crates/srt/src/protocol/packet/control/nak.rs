@@ -1,7 +1,13 @@
-use anyhow::Result;
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.5>
 
-#[derive(Clone, Debug)]
-pub enum Nak {
+use crate::{
+    error::{Error, Result},
+    protocol::{constants::MAX_PAYLOAD_SIZE, packet::HEADER_SIZE},
+};
+
+/// Entry of a compressed loss list
+#[derive(Clone, Debug, PartialEq)]
+pub enum LossEntry {
     Single {
         lost_packet: u32,
     },
@@ -11,39 +17,142 @@ pub enum Nak {
     },
 }
 
+#[derive(Clone, Debug)]
+pub struct Nak {
+    pub entries: Vec<LossEntry>,
+}
+
 impl Nak {
+    /// Report lost packets `from..=to`
+    pub fn new(from: u32, to: u32) -> Self {
+        Self::from_ranges([(from, to)])
+    }
+
+    /// Report lost packets as inclusive `(from, to)` ranges
+    ///
+    /// Ranges beyond what fits a single packet are left out (reported by the next NAK)
+    pub fn from_ranges(ranges: impl IntoIterator<Item = (u32, u32)>) -> Self {
+        let mut entries = Vec::new();
+        let mut size = 0;
+
+        for (from, to) in ranges {
+            let entry = if from == to {
+                LossEntry::Single { lost_packet: from }
+            } else {
+                LossEntry::Range {
+                    lost_packets_from: from,
+                    lost_packets_to: to,
+                }
+            };
+
+            size += entry.size();
+            if size > MAX_PAYLOAD_SIZE {
+                break;
+            }
+            entries.push(entry);
+        }
+
+        Self { entries }
+    }
+
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let is_range = raw[16] >> 7 == 1;
-
-        if is_range {
-            let lost_packets_from = u32::from_be_bytes(raw[16..20].try_into()?) & !(1 << 31);
-            let lost_packets_to = u32::from_be_bytes(raw[20..24].try_into()?);
-            Ok(Self::Range {
-                lost_packets_from,
-                lost_packets_to,
-            })
-        } else {
-            let lost_packet = u32::from_be_bytes(raw[16..20].try_into()?);
-            Ok(Self::Single { lost_packet })
+        let mut words = raw
+            .get(HEADER_SIZE..)
+            .ok_or(Error::Truncated("NAK"))?
+            .chunks_exact(4)
+            .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]));
+        let mut entries = Vec::new();
+
+        while let Some(word) = words.next() {
+            let is_range = word >> 31 == 1;
+
+            if is_range {
+                let Some(lost_packets_to) = words.next() else {
+                    return Err(Error::Truncated("NAK range"));
+                };
+                entries.push(LossEntry::Range {
+                    lost_packets_from: word & !(1 << 31),
+                    lost_packets_to,
+                });
+            } else {
+                entries.push(LossEntry::Single { lost_packet: word });
+            }
         }
+
+        Ok(Self { entries })
+    }
+
+    /// Lost packets as inclusive `(from, to)` ranges
+    pub fn ranges(&self) -> Vec<(u32, u32)> {
+        self.entries
+            .iter()
+            .map(|entry| match entry {
+                LossEntry::Single { lost_packet } => (*lost_packet, *lost_packet),
+                LossEntry::Range {
+                    lost_packets_from,
+                    lost_packets_to,
+                } => (*lost_packets_from, *lost_packets_to),
+            })
+            .collect()
     }
 
     pub fn raw_content(&self) -> Vec<u8> {
         let mut res = Vec::new();
 
-        match self {
-            Self::Single { lost_packet } => {
-                res.extend(lost_packet.to_be_bytes());
-            }
-            Self::Range {
-                lost_packets_from,
-                lost_packets_to,
-            } => {
-                res.extend((lost_packets_from | (1 << 31)).to_be_bytes());
-                res.extend(lost_packets_to.to_be_bytes());
+        for entry in &self.entries {
+            match entry {
+                LossEntry::Single { lost_packet } => {
+                    res.extend(lost_packet.to_be_bytes());
+                }
+                LossEntry::Range {
+                    lost_packets_from,
+                    lost_packets_to,
+                } => {
+                    res.extend((lost_packets_from | (1 << 31)).to_be_bytes());
+                    res.extend(lost_packets_to.to_be_bytes());
+                }
             }
         }
 
         res
     }
 }
+
+impl LossEntry {
+    /// (bytes)
+    fn size(&self) -> usize {
+        match self {
+            Self::Single { .. } => 4,
+            Self::Range { .. } => 8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_loss_list() {
+        let nak = Nak::from_ranges([(3, 3), (5, 9), (0x7FFF_FFFE, 0x7FFF_FFFF)]);
+
+        let mut raw = vec![0; 16];
+        raw.extend(nak.raw_content());
+        assert_eq!(raw.len(), 16 + 4 + 8 + 8);
+        assert_eq!(raw[20], 0x80);
+
+        let decoded = Nak::from_raw(&raw).map(|nak| nak.ranges()).ok();
+        assert_eq!(
+            decoded,
+            Some(vec![(3, 3), (5, 9), (0x7FFF_FFFE, 0x7FFF_FFFF)])
+        );
+        assert!(Nak::from_raw(&raw[..32]).is_err());
+    }
+
+    #[test]
+    fn test_fits_packet() {
+        let nak = Nak::from_ranges((0..1_000).map(|i| (i * 4, i * 4 + 1)));
+
+        assert_eq!(nak.entries.len(), MAX_PAYLOAD_SIZE / 8);
+    }
+}
@@ -1,6 +1,5 @@
-use anyhow::Result;
-
 use super::control_types;
+use crate::{error::Result, protocol::packet::read_u32};
 
 #[derive(Clone, Debug)]
 pub struct PeerError {
@@ -9,7 +8,7 @@ pub struct PeerError {
 
 impl PeerError {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let error_code = u32::from_be_bytes(raw[4..8].try_into()?);
+        let error_code = read_u32(raw, 4, "PEERERROR")?;
 
         Ok(Self { error_code })
     }
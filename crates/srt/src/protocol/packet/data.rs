@@ -1,4 +1,7 @@
-#[derive(Debug)]
+use super::{HEADER_SIZE, read_u32};
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PacketPosition {
     Middle,
     First,
@@ -6,14 +9,26 @@ pub enum PacketPosition {
     Single,
 }
 
-#[derive(Debug)]
+impl PacketPosition {
+    /// Position of part `index` of a message split into `count` packets
+    pub fn of(index: usize, count: usize) -> Self {
+        match (index == 0, index + 1 >= count) {
+            (true, true) => Self::Single,
+            (true, false) => Self::First,
+            (false, true) => Self::Last,
+            (false, false) => Self::Middle,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum EncryptionFlag {
     NoEncryption,
     EvenKey,
     OddKey,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DataPacketInfo {
     pub packet_sequence_number: u32,
     pub position: PacketPosition,
@@ -25,10 +40,15 @@ pub struct DataPacketInfo {
 }
 
 impl DataPacketInfo {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let packet_sequence_number = u32::from_be_bytes(raw[0..4].try_into()?) & !(1 << 31);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let packet_sequence_number = read_u32(raw, 0, "data packet header")? & !(1 << 31);
+        let second_word = read_u32(raw, 4, "data packet header")?;
+        let content = raw
+            .get(HEADER_SIZE..)
+            .ok_or(Error::Truncated("data packet header"))?
+            .to_vec();
 
-        let fb = raw[4];
+        let fb = (second_word >> 24) as u8;
         let position = match (fb & 0b1100_0000) >> 6 {
             0b00 => PacketPosition::Middle,
             0b01 => PacketPosition::Last,
@@ -36,26 +56,16 @@ impl DataPacketInfo {
             0b11 => PacketPosition::Single,
             _ => unreachable!(),
         };
-        let order = match (fb & 0b0010_0000) >> 5 {
-            0 => false,
-            1 => true,
-            _ => unreachable!(),
-        };
+        let order = fb & 0b0010_0000 != 0;
         let encryption = match (fb & 0b0001_1000) >> 3 {
             0b00 => EncryptionFlag::NoEncryption,
             0b01 => EncryptionFlag::EvenKey,
             0b10 => EncryptionFlag::OddKey,
-            _ => unreachable!(),
-        };
-        let retransmitted = match (fb & 0b0000_0100) >> 2 {
-            0 => false,
-            1 => true,
-            _ => unreachable!(),
+            flag => return Err(Error::BadValue("encryption flag", flag.into())),
         };
+        let retransmitted = fb & 0b0000_0100 != 0;
 
-        let message_number = u32::from_be_bytes(raw[4..8].try_into()?) & !(0b11_11_11 << 26);
-
-        let content = Vec::from(&raw[16..]);
+        let message_number = second_word & !(0b11_11_11 << 26);
 
         Ok(Self {
             packet_sequence_number,
@@ -68,11 +78,35 @@ impl DataPacketInfo {
         })
     }
 
+    /// Get `Packet Sequence Number` and `Message Number` words (with flags)
     pub fn raw_header(&self) -> Vec<u8> {
-        todo!()
+        let position: u32 = match self.position {
+            PacketPosition::Middle => 0b00,
+            PacketPosition::Last => 0b01,
+            PacketPosition::First => 0b10,
+            PacketPosition::Single => 0b11,
+        };
+        let encryption: u32 = match self.encryption {
+            EncryptionFlag::NoEncryption => 0b00,
+            EncryptionFlag::EvenKey => 0b01,
+            EncryptionFlag::OddKey => 0b10,
+        };
+
+        let flags = (position << 30)
+            | (u32::from(self.order) << 29)
+            | (encryption << 27)
+            | (u32::from(self.retransmitted) << 26);
+
+        let mut res = Vec::new();
+
+        res.extend((self.packet_sequence_number & !(1 << 31)).to_be_bytes());
+        res.extend((flags | (self.message_number & !(0b11_11_11 << 26))).to_be_bytes());
+
+        res
     }
 
+    /// Get payload
     pub fn raw_content(&self) -> Vec<u8> {
-        todo!()
+        self.content.clone()
     }
 }
@@ -0,0 +1,558 @@
+//! Receiver-side buffer restoring packet order and reassembling messages
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.8.2>
+
+use std::{collections::VecDeque, time::Instant};
+
+use crate::protocol::{
+    loss_list::LossList,
+    packet::data::{DataPacketInfo, PacketPosition},
+    sequence,
+    tsbpd::Tsbpd,
+};
+
+/// Outcome of [`ReceiveBuffer::push`]
+#[derive(Debug, PartialEq)]
+pub enum Arrival {
+    Stored {
+        /// Gap (`from`, `to`) detected in front of this packet
+        lost: Option<(u32, u32)>,
+        /// Packet was in the loss list
+        recovered: bool,
+    },
+    Duplicate,
+    /// Precedes already delivered data
+    Belated,
+    /// Too far ahead of delivered data to fit the buffer
+    Overflow,
+}
+
+/// State of a sequence number within the receive window
+#[derive(Clone)]
+enum Slot {
+    Missing,
+    /// Given up on (too late for delivery or `DROPREQ` by the sender)
+    Dropped,
+    /// Taken ahead of missing packets before it (out-of-order message)
+    Delivered,
+    /// Packet with its delivery time
+    Received(Instant, DataPacketInfo),
+}
+
+pub struct ReceiveBuffer {
+    /// Sequence number of the next packet to deliver
+    next_sequence_number: u32,
+
+    /// Slots starting at `next_sequence_number`
+    packets: VecDeque<Slot>,
+    loss_list: LossList,
+    capacity: usize,
+
+    /// Without TSBPD packets are delivered as soon as they are in order
+    tsbpd: Option<Tsbpd>,
+    /// Skip missing packets once a following packet is due (`TLPKTDROP`, requires TSBPD)
+    too_late_drop: bool,
+
+    /// Delivered packets of the message being reassembled
+    partial: Vec<DataPacketInfo>,
+
+    /// Number of packets never delivered
+    dropped: u32,
+}
+
+impl ReceiveBuffer {
+    pub fn new(
+        initial_sequence_number: u32,
+        capacity: usize,
+        tsbpd: Option<Tsbpd>,
+        too_late_drop: bool,
+    ) -> Self {
+        Self {
+            next_sequence_number: initial_sequence_number,
+            packets: VecDeque::new(),
+            loss_list: LossList::new(),
+            capacity,
+            too_late_drop: too_late_drop && tsbpd.is_some(),
+            tsbpd,
+            partial: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn loss_list(&self) -> &LossList {
+        &self.loss_list
+    }
+
+    /// Stored packets and gaps awaiting delivery
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Free space (packets)
+    pub fn available(&self) -> usize {
+        self.capacity - self.packets.len()
+    }
+
+    /// Number of packets skipped as too late or dropped by the sender
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Sequence number following contiguously received packets
+    /// (`Last Acknowledged Packet Sequence Number` of an ACK)
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn ack_sequence_number(&self) -> u32 {
+        let received = self
+            .packets
+            .iter()
+            .position(|slot| matches!(slot, Slot::Missing))
+            .unwrap_or(self.packets.len());
+
+        sequence::add(self.next_sequence_number, received as u32)
+    }
+
+    /// Store a packet with `Timestamp` of `timestamp`
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn push(&mut self, timestamp: u32, data: DataPacketInfo) -> Arrival {
+        let seq = data.packet_sequence_number;
+
+        let offset = sequence::offset(self.next_sequence_number, seq);
+        if offset < 0 {
+            return Arrival::Belated;
+        }
+        let offset = offset as usize;
+        if offset >= self.capacity {
+            return Arrival::Overflow;
+        }
+
+        let mut lost = None;
+        if offset > self.packets.len() {
+            let from = sequence::add(self.next_sequence_number, self.packets.len() as u32);
+            let to = sequence::prev(seq);
+            self.loss_list.push(from, to);
+            lost = Some((from, to));
+        }
+        if offset >= self.packets.len() {
+            self.packets.resize(offset + 1, Slot::Missing);
+        }
+
+        let slot = &mut self.packets[offset];
+        match slot {
+            Slot::Missing => (),
+            Slot::Dropped => return Arrival::Belated,
+            Slot::Received(..) | Slot::Delivered => return Arrival::Duplicate,
+        }
+        let delivery_time = match &mut self.tsbpd {
+            Some(tsbpd) => tsbpd.delivery_time(timestamp),
+            None => Instant::now(),
+        };
+        *slot = Slot::Received(delivery_time, data);
+
+        let recovered = lost.is_none() && self.loss_list.contains(seq);
+        if recovered {
+            self.loss_list.remove(seq);
+        }
+
+        Arrival::Stored { lost, recovered }
+    }
+
+    /// Give up on `from..=to` (`DROPREQ`), returns the number of packets not received
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn drop_range(&mut self, from: u32, to: u32) -> u32 {
+        self.loss_list.remove_range(from, to);
+
+        let start = sequence::offset(self.next_sequence_number, from).max(0);
+        let end = sequence::offset(self.next_sequence_number, to);
+        if end < start {
+            return 0;
+        }
+
+        // Range reaching past the window (e.g. a group member skipped ahead):
+        // jump over it, unless received packets are in the way
+        if start == 0
+            && end as usize >= self.capacity
+            && !self
+                .packets
+                .iter()
+                .any(|slot| matches!(slot, Slot::Received(..)))
+        {
+            let skipped = self
+                .packets
+                .drain(..)
+                .filter(|slot| !matches!(slot, Slot::Missing))
+                .count() as u32;
+            let count = end as u32 + 1 - skipped;
+            self.next_sequence_number = sequence::next(to);
+            self.loss_list.remove_before(self.next_sequence_number);
+            self.dropped += count;
+
+            return count;
+        }
+        let (start, end) = (start as usize, (end as usize).min(self.capacity - 1));
+        if start > end {
+            return 0;
+        }
+        if end >= self.packets.len() {
+            self.packets.resize(end + 1, Slot::Missing);
+        }
+
+        let mut count = 0;
+        for slot in self.packets.range_mut(start..=end) {
+            if matches!(slot, Slot::Missing) {
+                *slot = Slot::Dropped;
+                count += 1;
+            }
+        }
+        self.dropped += count;
+
+        count
+    }
+
+    /// Delivery time of the next packet, if it has arrived
+    /// (or of the first packet after missing ones, with too-late drop)
+    pub fn next_delivery(&self) -> Option<Instant> {
+        for slot in &self.packets {
+            match slot {
+                Slot::Dropped | Slot::Delivered => (),
+                Slot::Missing if self.too_late_drop => (),
+                Slot::Missing => return None,
+                Slot::Received(time, _) => return Some(*time),
+            }
+        }
+
+        None
+    }
+
+    /// Take the next packet, if it has arrived and is due at `now`
+    ///
+    /// With too-late drop, missing packets before a due packet are skipped
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn pop(&mut self, now: Instant) -> Option<DataPacketInfo> {
+        if self.next_delivery()? > now {
+            return None;
+        }
+
+        let skipped = self
+            .packets
+            .iter()
+            .position(|slot| matches!(slot, Slot::Received(..)))?;
+        if skipped > 0 {
+            let missing = self
+                .packets
+                .drain(..skipped)
+                .filter(|slot| matches!(slot, Slot::Missing))
+                .count() as u32;
+            let from = self.next_sequence_number;
+            self.next_sequence_number = sequence::add(from, skipped as u32);
+            self.loss_list.remove_before(self.next_sequence_number);
+
+            if missing > 0 {
+                tracing::warn!(
+                    "Dropped {missing} too late packets before {}",
+                    self.next_sequence_number
+                );
+                self.dropped += missing;
+            }
+        }
+
+        let Some(Slot::Received(_, data)) = self.packets.pop_front() else {
+            return None;
+        };
+        self.next_sequence_number = sequence::next(self.next_sequence_number);
+
+        Some(data)
+    }
+
+    /// Take the next whole message, once its packets are due at `now`,
+    /// with the sequence number of its first packet
+    ///
+    /// Parts of messages that can't be completed (packets dropped) are discarded.
+    /// Without TSBPD, a complete message sent out of order (`order` unset) is taken
+    /// ahead of missing packets.
+    pub fn pop_message(&mut self, now: Instant) -> Option<(u32, Vec<u8>)> {
+        while let Some(data) = self.pop(now) {
+            if let Some(message) = self.assemble(data) {
+                return Some(message);
+            }
+        }
+
+        if self.tsbpd.is_none() {
+            return self.pop_unordered();
+        }
+
+        None
+    }
+
+    /// Add the next in-order packet to [`Self::partial`], returning the message once complete
+    fn assemble(&mut self, data: DataPacketInfo) -> Option<(u32, Vec<u8>)> {
+        let continues = self.partial.last().is_some_and(|last| {
+            last.message_number == data.message_number
+                && sequence::next(last.packet_sequence_number) == data.packet_sequence_number
+        });
+        let starts = matches!(
+            data.position,
+            PacketPosition::First | PacketPosition::Single
+        );
+
+        if (starts || !continues)
+            && let Some(first) = self.partial.first()
+        {
+            tracing::warn!("Discarded incomplete message {}", first.message_number);
+            self.partial.clear();
+        }
+        if !starts && !continues {
+            tracing::warn!("Discarded incomplete message {}", data.message_number);
+            return None;
+        }
+
+        let complete = matches!(data.position, PacketPosition::Last | PacketPosition::Single);
+        self.partial.push(data);
+
+        complete.then(|| {
+            let first = self.partial[0].packet_sequence_number;
+            let message = self
+                .partial
+                .drain(..)
+                .flat_map(|data| data.content)
+                .collect();
+
+            (first, message)
+        })
+    }
+
+    /// Take a complete out-of-order message behind missing packets
+    fn pop_unordered(&mut self) -> Option<(u32, Vec<u8>)> {
+        let mut start = None;
+        let mut found = None;
+
+        for (i, slot) in self.packets.iter().enumerate() {
+            let Slot::Received(_, data) = slot else {
+                start = None;
+                continue;
+            };
+
+            match (data.position, start) {
+                _ if data.order => start = None,
+                (PacketPosition::Single, _) => {
+                    found = Some((i, i));
+                    break;
+                }
+                (PacketPosition::First, _) => start = Some((i, data.message_number)),
+                (PacketPosition::Middle, Some((_, message_number)))
+                    if message_number == data.message_number => {}
+                (PacketPosition::Last, Some((first, message_number)))
+                    if message_number == data.message_number =>
+                {
+                    found = Some((first, i));
+                    break;
+                }
+                _ => start = None,
+            }
+        }
+
+        let (first, last) = found?;
+        #[allow(clippy::cast_possible_truncation)]
+        let sequence_number = sequence::add(self.next_sequence_number, first as u32);
+        let message = self
+            .packets
+            .range_mut(first..=last)
+            .filter_map(|slot| match std::mem::replace(slot, Slot::Delivered) {
+                Slot::Received(_, data) => Some(data.content),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
+        Some((sequence_number, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::protocol::packet::data::{EncryptionFlag, PacketPosition};
+
+    fn packet(packet_sequence_number: u32) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number,
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: 1,
+            content: Vec::new(),
+        }
+    }
+
+    fn part(
+        packet_sequence_number: u32,
+        message_number: u32,
+        position: PacketPosition,
+        order: bool,
+    ) -> DataPacketInfo {
+        DataPacketInfo {
+            position,
+            order,
+            message_number,
+            content: vec![u8::try_from(packet_sequence_number).unwrap_or_default()],
+            ..packet(packet_sequence_number)
+        }
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut buffer = ReceiveBuffer::new(10, 64, None, false);
+
+        assert!(matches!(
+            buffer.push(0, packet(10)),
+            Arrival::Stored { lost: None, .. }
+        ));
+        assert_eq!(
+            buffer.push(0, packet(13)),
+            Arrival::Stored {
+                lost: Some((11, 12)),
+                recovered: false
+            }
+        );
+        assert_eq!(buffer.ack_sequence_number(), 11);
+
+        assert_eq!(
+            buffer.pop(Instant::now()).map(|p| p.packet_sequence_number),
+            Some(10)
+        );
+        assert!(buffer.pop(Instant::now()).is_none());
+
+        assert_eq!(
+            buffer.push(0, packet(12)),
+            Arrival::Stored {
+                lost: None,
+                recovered: true
+            }
+        );
+        assert_eq!(buffer.push(0, packet(12)), Arrival::Duplicate);
+        assert_eq!(
+            buffer.push(0, packet(11)),
+            Arrival::Stored {
+                lost: None,
+                recovered: true
+            }
+        );
+        assert!(buffer.loss_list().is_empty());
+        assert_eq!(buffer.ack_sequence_number(), 14);
+
+        let order: Vec<_> = std::iter::from_fn(|| buffer.pop(Instant::now()))
+            .map(|p| p.packet_sequence_number)
+            .collect();
+        assert_eq!(order, [11, 12, 13]);
+        assert_eq!(buffer.push(0, packet(12)), Arrival::Belated);
+    }
+
+    #[test]
+    fn test_tsbpd() {
+        let now = Instant::now();
+        let latency = Duration::from_millis(120);
+        let mut buffer = ReceiveBuffer::new(0, 64, Some(Tsbpd::new(now, 0, latency)), false);
+
+        buffer.push(1_000, packet(0));
+        assert_eq!(
+            buffer.next_delivery(),
+            Some(now + latency + Duration::from_millis(1))
+        );
+
+        assert!(buffer.pop(now + latency).is_none());
+        assert!(
+            buffer
+                .pop(now + latency + Duration::from_millis(1))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_too_late_drop() {
+        let now = Instant::now();
+        let latency = Duration::from_millis(120);
+        let mut buffer = ReceiveBuffer::new(0, 64, Some(Tsbpd::new(now, 0, latency)), true);
+
+        buffer.push(0, packet(0));
+        buffer.push(3_000, packet(3));
+        assert_eq!(buffer.loss_list().len(), 2);
+
+        let due = now + latency + Duration::from_millis(3);
+        assert_eq!(buffer.next_delivery(), Some(now + latency));
+        assert_eq!(buffer.pop(due).map(|p| p.packet_sequence_number), Some(0));
+        assert_eq!(buffer.next_delivery(), Some(due));
+
+        assert!(buffer.pop(due - Duration::from_millis(1)).is_none());
+        assert_eq!(buffer.pop(due).map(|p| p.packet_sequence_number), Some(3));
+        assert_eq!(buffer.dropped(), 2);
+        assert!(buffer.loss_list().is_empty());
+        assert_eq!(buffer.push(0, packet(1)), Arrival::Belated);
+    }
+
+    #[test]
+    fn test_drop_range() {
+        let mut buffer = ReceiveBuffer::new(0, 64, None, false);
+
+        buffer.push(0, packet(0));
+        buffer.push(0, packet(4));
+        assert_eq!(buffer.ack_sequence_number(), 1);
+
+        assert_eq!(buffer.drop_range(1, 3), 3);
+        assert!(buffer.loss_list().is_empty());
+        assert_eq!(buffer.ack_sequence_number(), 5);
+        assert_eq!(buffer.push(0, packet(2)), Arrival::Belated);
+
+        let order: Vec<_> = std::iter::from_fn(|| buffer.pop(Instant::now()))
+            .map(|p| p.packet_sequence_number)
+            .collect();
+        assert_eq!(order, [0, 4]);
+        assert_eq!(buffer.dropped(), 3);
+
+        // Far beyond the window
+        assert_eq!(buffer.drop_range(5, 1_004), 1_000);
+        assert_eq!(buffer.ack_sequence_number(), 1_005);
+        assert!(matches!(
+            buffer.push(0, packet(1_005)),
+            Arrival::Stored { lost: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut buffer = ReceiveBuffer::new(0, 64, None, false);
+
+        // Out of order message 4..=5 overtakes incomplete 0..=2 and missing 3
+        buffer.push(0, part(0, 1, PacketPosition::First, true));
+        buffer.push(0, part(1, 1, PacketPosition::Middle, true));
+        buffer.push(0, part(4, 3, PacketPosition::First, false));
+        buffer.push(0, part(5, 3, PacketPosition::Last, false));
+        assert_eq!(buffer.pop_message(Instant::now()), Some((4, vec![4, 5])));
+        assert!(buffer.pop_message(Instant::now()).is_none());
+
+        buffer.push(0, part(2, 1, PacketPosition::Last, true));
+        assert_eq!(buffer.pop_message(Instant::now()), Some((0, vec![0, 1, 2])));
+        assert!(buffer.pop_message(Instant::now()).is_none());
+        assert_eq!(
+            buffer.push(0, part(5, 3, PacketPosition::Last, false)),
+            Arrival::Duplicate
+        );
+
+        // Delivered message is skipped
+        buffer.push(0, part(3, 2, PacketPosition::Single, true));
+        assert_eq!(buffer.pop_message(Instant::now()), Some((3, vec![3])));
+        assert!(buffer.pop_message(Instant::now()).is_none());
+        assert_eq!(buffer.ack_sequence_number(), 6);
+
+        // Message missing its first packet is discarded
+        buffer.drop_range(6, 6);
+        buffer.push(0, part(7, 4, PacketPosition::Last, true));
+        buffer.push(0, part(8, 5, PacketPosition::Single, true));
+        assert_eq!(buffer.pop_message(Instant::now()), Some((8, vec![8])));
+    }
+}
@@ -0,0 +1,379 @@
+//! Sender-side buffer of packets awaiting acknowledgement
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.8.2>
+
+use std::collections::VecDeque;
+
+use crate::protocol::{
+    constants::MAX_MESSAGE_NUMBER,
+    packet::{
+        control::drop_req::DropReq,
+        data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+    },
+    sequence,
+};
+
+/// Stored packet
+struct Entry {
+    timestamp: u32,
+    /// `Timestamp` after which the packet's message is given up on (`None`: never)
+    expiry: Option<u32>,
+    /// Message was given up on, only its acknowledgement is awaited
+    dropped: bool,
+    packet: DataPacketInfo,
+}
+
+pub struct SendBuffer {
+    /// Sequence number of the oldest stored packet
+    /// (or of the next packet, when empty)
+    first_sequence_number: u32,
+    next_message_number: u32,
+
+    /// Oldest first
+    packets: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl SendBuffer {
+    pub fn new(initial_sequence_number: u32, capacity: usize) -> Self {
+        Self {
+            first_sequence_number: initial_sequence_number,
+            next_message_number: 1,
+            packets: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn next_sequence_number(&self) -> u32 {
+        sequence::add(self.first_sequence_number, self.packets.len() as u32)
+    }
+
+    /// Wrap `content` into a new data packet, keeping a copy until it is acknowledged
+    ///
+    /// When full, the oldest packet is given up on to make room:
+    /// the `DROPREQ` returned along informs the receiver
+    pub fn push(&mut self, timestamp: u32, content: Vec<u8>) -> (DataPacketInfo, Option<DropReq>) {
+        self.push_part(timestamp, content, PacketPosition::Single, false, None)
+    }
+
+    /// Wrap `content` into the packet at `position` of the current message
+    ///
+    /// The message is delivered `in_order` with earlier ones, unless given up on
+    /// after the `expiry` timestamp (TTL). Gives up on the oldest packet if full, see [`Self::push`].
+    pub fn push_part(
+        &mut self,
+        timestamp: u32,
+        content: Vec<u8>,
+        position: PacketPosition,
+        in_order: bool,
+        expiry: Option<u32>,
+    ) -> (DataPacketInfo, Option<DropReq>) {
+        let evicted = if self.packets.len() >= self.capacity {
+            let oldest = self.packets.pop_front();
+            let drop_req = oldest.map(|entry| {
+                DropReq::new(
+                    entry.packet.message_number,
+                    self.first_sequence_number,
+                    self.first_sequence_number,
+                )
+            });
+            self.first_sequence_number = sequence::next(self.first_sequence_number);
+            drop_req
+        } else {
+            None
+        };
+
+        let packet = DataPacketInfo {
+            packet_sequence_number: self.next_sequence_number(),
+            position,
+            order: in_order,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: self.next_message_number,
+            content,
+        };
+
+        if matches!(position, PacketPosition::Last | PacketPosition::Single) {
+            self.next_message_number = if self.next_message_number == MAX_MESSAGE_NUMBER {
+                1
+            } else {
+                self.next_message_number + 1
+            };
+        }
+
+        self.packets.push_back(Entry {
+            timestamp,
+            expiry,
+            dropped: false,
+            packet: packet.clone(),
+        });
+
+        (packet, evicted)
+    }
+
+    /// Release packets preceding `sequence_number`
+    /// (`Last Acknowledged Packet Sequence Number` of an ACK)
+    ///
+    /// Returns whether `sequence_number` lies within the packets awaiting acknowledgement
+    /// (or right after them), as in an ACK of the peer; others are ignored
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn acknowledge(&mut self, sequence_number: u32) -> bool {
+        let count = sequence::offset(self.first_sequence_number, sequence_number);
+        if count < 0 || count as usize > self.packets.len() {
+            return false;
+        }
+
+        self.packets.drain(..count as usize);
+        self.first_sequence_number = sequence::add(self.first_sequence_number, count as u32);
+
+        true
+    }
+
+    /// Give up on packets older than `threshold` at `timestamp` (too-late drop)
+    ///
+    /// Returns the `DROPREQ` to inform the receiver
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn drop_expired(&mut self, timestamp: u32, threshold: u32) -> Option<DropReq> {
+        let count = self
+            .packets
+            .iter()
+            .take_while(|entry| timestamp.wrapping_sub(entry.timestamp) > threshold)
+            .count();
+        let first = self.packets.front().filter(|_| count > 0)?;
+
+        let drop_req = DropReq::new(
+            first.packet.message_number,
+            self.first_sequence_number,
+            sequence::add(self.first_sequence_number, count as u32 - 1),
+        );
+        self.packets.drain(..count);
+        self.first_sequence_number = sequence::add(self.first_sequence_number, count as u32);
+
+        Some(drop_req)
+    }
+
+    /// Give up on messages whose TTL has passed at `timestamp`
+    ///
+    /// Returns a `DROPREQ` for each of them to inform the receiver
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub fn drop_outdated(&mut self, timestamp: u32) -> Vec<DropReq> {
+        let mut drop_reqs: Vec<DropReq> = Vec::new();
+
+        for entry in &mut self.packets {
+            let outdated = entry
+                .expiry
+                .is_some_and(|expiry| timestamp.wrapping_sub(expiry) as i32 > 0);
+            if !outdated || entry.dropped {
+                continue;
+            }
+            entry.dropped = true;
+
+            let packet = &entry.packet;
+            match drop_reqs.last_mut() {
+                Some(drop_req)
+                    if drop_req.message_number == packet.message_number
+                        && sequence::next(drop_req.last_packet_sequence_number)
+                            == packet.packet_sequence_number =>
+                {
+                    drop_req.last_packet_sequence_number = packet.packet_sequence_number;
+                }
+                _ => drop_reqs.push(DropReq::new(
+                    packet.message_number,
+                    packet.packet_sequence_number,
+                    packet.packet_sequence_number,
+                )),
+            }
+        }
+
+        drop_reqs
+    }
+
+    /// Continue at `sequence_number`, giving up on all stored packets
+    /// (a group member catching up with the group)
+    ///
+    /// Returns the `DROPREQ` to inform the receiver (`None` if already there or beyond)
+    pub fn skip_to(&mut self, sequence_number: u32) -> Option<DropReq> {
+        if !sequence::lt(self.next_sequence_number(), sequence_number) {
+            return None;
+        }
+
+        let drop_req = DropReq::new(
+            0,
+            self.first_sequence_number,
+            sequence::prev(sequence_number),
+        );
+        self.packets.clear();
+        self.first_sequence_number = sequence_number;
+
+        Some(drop_req)
+    }
+
+    /// Part of `from..=to` that is no longer stored (acknowledged or given up on)
+    pub fn released(&self, from: u32, to: u32) -> Option<(u32, u32)> {
+        if !sequence::lt(from, self.first_sequence_number) {
+            return None;
+        }
+
+        let last = sequence::prev(self.first_sequence_number);
+        Some((from, if sequence::lt(to, last) { to } else { last }))
+    }
+
+    /// Stored packets within `from..=to` as `(timestamp, packet)`, flagged as retransmitted
+    /// (except those given up on)
+    #[allow(clippy::cast_sign_loss)]
+    pub fn retransmit(&self, from: u32, to: u32) -> Vec<(u32, DataPacketInfo)> {
+        let start = sequence::offset(self.first_sequence_number, from).max(0) as usize;
+        let end = sequence::offset(self.first_sequence_number, to);
+        if end < 0 {
+            return Vec::new();
+        }
+
+        self.packets
+            .iter()
+            .skip(start)
+            .take((end as usize + 1).saturating_sub(start))
+            .filter(|entry| !entry.dropped)
+            .map(|entry| {
+                (
+                    entry.timestamp,
+                    DataPacketInfo {
+                        retransmitted: true,
+                        ..entry.packet.clone()
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::constants::MAX_SEQUENCE_NUMBER;
+
+    #[test]
+    fn test_acknowledge_and_retransmit() {
+        let mut buffer = SendBuffer::new(MAX_SEQUENCE_NUMBER - 1, 16);
+
+        for i in 0..4 {
+            buffer.push(i, vec![u8::try_from(i).unwrap_or_default()]);
+        }
+        assert_eq!(buffer.next_sequence_number(), 2);
+
+        let lost = buffer.retransmit(MAX_SEQUENCE_NUMBER, 0);
+        assert_eq!(lost.len(), 2);
+        assert_eq!(lost[0].0, 1);
+        assert!(lost[0].1.retransmitted);
+        assert_eq!(lost[1].1.packet_sequence_number, 0);
+
+        assert!(!buffer.acknowledge(3));
+        assert_eq!(buffer.len(), 4);
+        assert!(buffer.acknowledge(1));
+        assert_eq!(buffer.len(), 1);
+        assert!(!buffer.acknowledge(MAX_SEQUENCE_NUMBER));
+        assert!(buffer.retransmit(MAX_SEQUENCE_NUMBER - 1, 0).is_empty());
+    }
+
+    #[test]
+    fn test_drop_expired() {
+        let mut buffer = SendBuffer::new(10, 16);
+
+        for i in 0..4 {
+            buffer.push(i * 1_000, Vec::new());
+        }
+        assert!(buffer.drop_expired(2_500, 2_500).is_none());
+
+        let drop_req = buffer.drop_expired(4_500, 2_500);
+        assert!(matches!(
+            drop_req,
+            Some(DropReq {
+                message_number: 1,
+                first_packet_sequence_number: 10,
+                last_packet_sequence_number: 11,
+            })
+        ));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.released(9, 13), Some((9, 11)));
+        assert!(buffer.released(12, 13).is_none());
+    }
+
+    #[test]
+    fn test_message_ttl() {
+        let mut buffer = SendBuffer::new(0, 16);
+
+        buffer.push(0, Vec::new());
+        for i in 0..3 {
+            let (part, _) =
+                buffer.push_part(0, Vec::new(), PacketPosition::of(i, 3), true, Some(1_000));
+            assert_eq!(part.message_number, 2);
+            assert!(part.order);
+        }
+        assert_eq!(buffer.push(0, Vec::new()).0.message_number, 3);
+
+        assert!(buffer.drop_outdated(1_000).is_empty());
+        let drop_reqs = buffer.drop_outdated(1_001);
+        assert!(matches!(
+            drop_reqs[..],
+            [DropReq {
+                message_number: 2,
+                first_packet_sequence_number: 1,
+                last_packet_sequence_number: 3,
+            }]
+        ));
+        assert!(buffer.drop_outdated(2_000).is_empty());
+
+        let lost: Vec<_> = buffer
+            .retransmit(0, 4)
+            .into_iter()
+            .map(|(_, packet)| packet.packet_sequence_number)
+            .collect();
+        assert_eq!(lost, [0, 4]);
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let mut buffer = SendBuffer::new(0, 16);
+
+        buffer.push(0, Vec::new());
+        assert!(buffer.skip_to(1).is_none());
+        assert!(matches!(
+            buffer.skip_to(100),
+            Some(DropReq {
+                message_number: 0,
+                first_packet_sequence_number: 0,
+                last_packet_sequence_number: 99,
+            })
+        ));
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.push(0, Vec::new()).0.packet_sequence_number, 100);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut buffer = SendBuffer::new(10, 2);
+
+        assert!(buffer.push(0, Vec::new()).1.is_none());
+        assert!(buffer.push(0, Vec::new()).1.is_none());
+        let (packet, evicted) = buffer.push(0, Vec::new());
+        assert_eq!(packet.packet_sequence_number, 12);
+        assert!(matches!(
+            evicted,
+            Some(DropReq {
+                message_number: 1,
+                first_packet_sequence_number: 10,
+                last_packet_sequence_number: 10,
+            })
+        ));
+        assert_eq!(buffer.len(), 2);
+        assert!(buffer.retransmit(10, 10).is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+//! Arithmetic on 31-bit packet sequence numbers
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.1>
+
+use crate::protocol::constants::MAX_SEQUENCE_NUMBER;
+
+/// Half of the sequence number space
+/// (numbers further apart are treated as wrapped around)
+const THRESHOLD: u32 = MAX_SEQUENCE_NUMBER / 2;
+
+pub fn add(seq: u32, n: u32) -> u32 {
+    seq.wrapping_add(n) & MAX_SEQUENCE_NUMBER
+}
+
+pub fn next(seq: u32) -> u32 {
+    add(seq, 1)
+}
+
+pub fn prev(seq: u32) -> u32 {
+    seq.wrapping_sub(1) & MAX_SEQUENCE_NUMBER
+}
+
+/// Signed distance from `from` to `to`
+#[allow(clippy::cast_possible_wrap)]
+pub fn offset(from: u32, to: u32) -> i32 {
+    let diff = to.wrapping_sub(from) & MAX_SEQUENCE_NUMBER;
+
+    if diff > THRESHOLD {
+        diff as i32 - (MAX_SEQUENCE_NUMBER as i32) - 1
+    } else {
+        diff as i32
+    }
+}
+
+/// `a` precedes `b`
+pub fn lt(a: u32, b: u32) -> bool {
+    offset(a, b) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(next(MAX_SEQUENCE_NUMBER), 0);
+        assert_eq!(prev(0), MAX_SEQUENCE_NUMBER);
+        assert_eq!(add(MAX_SEQUENCE_NUMBER - 1, 3), 1);
+    }
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(offset(10, 15), 5);
+        assert_eq!(offset(15, 10), -5);
+        assert_eq!(offset(MAX_SEQUENCE_NUMBER - 1, 2), 4);
+        assert_eq!(offset(2, MAX_SEQUENCE_NUMBER - 1), -4);
+
+        assert!(lt(MAX_SEQUENCE_NUMBER, 0));
+        assert!(!lt(0, MAX_SEQUENCE_NUMBER));
+    }
+}
@@ -0,0 +1,301 @@
+//! Connection statistics, comparable to `SRT_TRACEBSTATS` of `libsrt`
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/API/statistics.md>
+
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    constants::MAX_PACKET_SIZE,
+    packet::{control::drop_req::DropReq, data::DataPacketInfo},
+    receive_buffer::Arrival,
+    sequence,
+};
+
+/// Packet and byte counters, over the whole connection or an interval
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Original data packets sent (without retransmissions)
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub packets_retransmitted: u64,
+    pub bytes_retransmitted: u64,
+    /// Unacknowledged packets the sender gave up on (too-late drop)
+    pub packets_send_dropped: u64,
+
+    /// Data packets received (including retransmissions and duplicates)
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    /// Packets found missing in the received sequence
+    pub packets_lost: u64,
+    /// Retransmitted packets received
+    pub packets_received_retransmitted: u64,
+    /// Packets skipped as too late or dropped by the sender
+    pub packets_dropped: u64,
+    /// Packets received twice
+    pub packets_duplicated: u64,
+    /// Packets that arrived after a following one without being retransmitted
+    pub packets_reordered: u64,
+    /// Packets that arrived after their data was delivered or skipped
+    pub packets_belated: u64,
+    /// Packets discarded as undecryptable or forged (not counted as received)
+    pub packets_undecrypted: u64,
+
+    /// Parity packets sent by the packet filter
+    pub packets_parity_sent: u64,
+    /// Parity packets received (not counted as received)
+    pub packets_parity_received: u64,
+    /// Lost packets rebuilt by the packet filter
+    pub packets_rebuilt: u64,
+}
+
+impl Counters {
+    /// Counts accumulated after `earlier`
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            packets_sent: self.packets_sent.saturating_sub(earlier.packets_sent),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            packets_retransmitted: self
+                .packets_retransmitted
+                .saturating_sub(earlier.packets_retransmitted),
+            bytes_retransmitted: self
+                .bytes_retransmitted
+                .saturating_sub(earlier.bytes_retransmitted),
+            packets_send_dropped: self
+                .packets_send_dropped
+                .saturating_sub(earlier.packets_send_dropped),
+            packets_received: self
+                .packets_received
+                .saturating_sub(earlier.packets_received),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+            packets_lost: self.packets_lost.saturating_sub(earlier.packets_lost),
+            packets_received_retransmitted: self
+                .packets_received_retransmitted
+                .saturating_sub(earlier.packets_received_retransmitted),
+            packets_dropped: self.packets_dropped.saturating_sub(earlier.packets_dropped),
+            packets_duplicated: self
+                .packets_duplicated
+                .saturating_sub(earlier.packets_duplicated),
+            packets_reordered: self
+                .packets_reordered
+                .saturating_sub(earlier.packets_reordered),
+            packets_belated: self.packets_belated.saturating_sub(earlier.packets_belated),
+            packets_undecrypted: self
+                .packets_undecrypted
+                .saturating_sub(earlier.packets_undecrypted),
+            packets_parity_sent: self
+                .packets_parity_sent
+                .saturating_sub(earlier.packets_parity_sent),
+            packets_parity_received: self
+                .packets_parity_received
+                .saturating_sub(earlier.packets_parity_received),
+            packets_rebuilt: self.packets_rebuilt.saturating_sub(earlier.packets_rebuilt),
+        }
+    }
+}
+
+/// Snapshot of a connection's statistics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    /// Since the connection was established
+    pub total: Counters,
+    /// Since the previous snapshot that cleared the interval
+    pub interval: Counters,
+    /// Age of the connection
+    pub elapsed: Duration,
+    /// Length of the interval
+    pub interval_duration: Duration,
+
+    /// Smoothed round-trip time
+    pub rtt: Duration,
+    pub rtt_var: Duration,
+    /// (Mbps) Rate of received payload over the interval
+    pub receive_rate: f64,
+    /// (Mbps) Link capacity estimated by the peer
+    pub bandwidth: f64,
+
+    /// Sent packets awaiting acknowledgement
+    pub send_buffer_packets: usize,
+    /// Received packets (and gaps) awaiting delivery
+    pub receive_buffer_packets: usize,
+    /// Negotiated TSBPD delay of received packets
+    pub latency: Option<Duration>,
+}
+
+/// (Mbps) `bytes` transferred over `duration`
+#[allow(clippy::cast_precision_loss)]
+pub fn mbps(bytes: u64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.0;
+    }
+
+    bytes as f64 * 8.0 / duration.as_secs_f64() / 1_000_000.0
+}
+
+/// (Mbps) Link capacity from (packets/s) `Estimated Link Capacity` of an ACK
+#[allow(clippy::cast_precision_loss)]
+pub fn capacity_mbps(packets_per_second: u32) -> f64 {
+    f64::from(packets_per_second) * (MAX_PACKET_SIZE * 8) as f64 / 1_000_000.0
+}
+
+/// Counters of a connection, with the mark its interval starts at
+pub struct Recorder {
+    pub counters: Counters,
+    mark: Counters,
+    marked: Instant,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self {
+            counters: Counters::default(),
+            mark: Counters::default(),
+            marked: Instant::now(),
+        }
+    }
+
+    /// Original data packet of `bytes` sent
+    pub fn sent(&mut self, bytes: usize) {
+        self.counters.packets_sent += 1;
+        self.counters.bytes_sent += bytes as u64;
+    }
+
+    pub fn retransmitted(&mut self, bytes: usize) {
+        self.counters.packets_retransmitted += 1;
+        self.counters.bytes_retransmitted += bytes as u64;
+    }
+
+    pub fn parity_sent(&mut self) {
+        self.counters.packets_parity_sent += 1;
+    }
+
+    pub fn parity_received(&mut self) {
+        self.counters.packets_parity_received += 1;
+    }
+
+    pub fn rebuilt(&mut self) {
+        self.counters.packets_rebuilt += 1;
+    }
+
+    /// Unacknowledged packets given up on
+    pub fn send_dropped(&mut self, drop_req: &DropReq) {
+        let dropped = sequence::offset(
+            drop_req.first_packet_sequence_number,
+            drop_req.last_packet_sequence_number,
+        );
+        self.counters.packets_send_dropped += u64::from(dropped.unsigned_abs()) + 1;
+    }
+
+    /// Data packet `data` received, with the receive buffer's verdict
+    pub fn received(&mut self, data: &DataPacketInfo, arrival: &Arrival) {
+        self.counters.packets_received += 1;
+        self.counters.bytes_received += data.content.len() as u64;
+        if data.retransmitted {
+            self.counters.packets_received_retransmitted += 1;
+        }
+
+        match arrival {
+            Arrival::Stored {
+                lost: Some((from, to)),
+                ..
+            } => {
+                self.counters.packets_lost +=
+                    u64::from(sequence::offset(*from, *to).unsigned_abs()) + 1;
+            }
+            Arrival::Stored {
+                recovered: true, ..
+            } if !data.retransmitted => self.counters.packets_reordered += 1,
+            Arrival::Duplicate => self.counters.packets_duplicated += 1,
+            Arrival::Belated => self.counters.packets_belated += 1,
+            Arrival::Stored { .. } | Arrival::Overflow => (),
+        }
+    }
+
+    /// Counts since the interval started and the interval's length
+    /// (`clear` starts a new interval)
+    ///
+    /// `total` is [`Self::counters`] completed by the connection.
+    pub fn interval(
+        &mut self,
+        total: &Counters,
+        now: Instant,
+        clear: bool,
+    ) -> (Counters, Duration) {
+        let interval = (total.since(&self.mark), now - self.marked);
+
+        if clear {
+            self.mark = *total;
+            self.marked = now;
+        }
+
+        interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::packet::data::{EncryptionFlag, PacketPosition};
+
+    fn packet(seq: u32, retransmitted: bool) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number: seq,
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted,
+            message_number: 1,
+            content: vec![0; 100],
+        }
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut recorder = Recorder::new();
+        let started = Instant::now();
+
+        recorder.received(
+            &packet(13, false),
+            &Arrival::Stored {
+                lost: Some((10, 12)),
+                recovered: false,
+            },
+        );
+        recorder.received(
+            &packet(11, true),
+            &Arrival::Stored {
+                lost: None,
+                recovered: true,
+            },
+        );
+        recorder.received(
+            &packet(12, false),
+            &Arrival::Stored {
+                lost: None,
+                recovered: true,
+            },
+        );
+        recorder.received(&packet(12, false), &Arrival::Duplicate);
+
+        let total = recorder.counters;
+        assert_eq!(total.packets_received, 4);
+        assert_eq!(total.bytes_received, 400);
+        assert_eq!(total.packets_lost, 3);
+        assert_eq!(total.packets_received_retransmitted, 1);
+        assert_eq!(total.packets_reordered, 1);
+        assert_eq!(total.packets_duplicated, 1);
+
+        let now = started + Duration::from_secs(1);
+        let (interval, _) = recorder.interval(&total, now, true);
+        assert_eq!(interval, total);
+
+        recorder.sent(1316);
+        let total = recorder.counters;
+        let (interval, duration) = recorder.interval(&total, now + Duration::from_secs(2), false);
+        assert_eq!(duration, Duration::from_secs(2));
+        assert_eq!(interval.packets_received, 0);
+        assert_eq!(interval.packets_sent, 1);
+        assert_eq!(interval.bytes_sent, 1316);
+
+        assert!((mbps(1_000_000, Duration::from_secs(2)) - 4.0).abs() < f64::EPSILON);
+    }
+}
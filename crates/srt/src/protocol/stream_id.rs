@@ -0,0 +1,238 @@
+//! Stream ID access-control syntax
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/access-control.md>
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Error, Result, bail};
+
+/// Prefix of IDs in the access-control syntax
+const PREFIX: &str = "#!::";
+
+/// Stream ID requested by a caller
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamId {
+    /// Free-form ID (not starting with `#!::`)
+    Plain(String),
+    /// `#!::key=value,...`
+    AccessControl(AccessControl),
+}
+
+impl StreamId {
+    /// `r`: name of the requested resource (the whole ID if plain)
+    pub fn resource(&self) -> Option<&str> {
+        match self {
+            Self::Plain(id) => Some(id),
+            Self::AccessControl(access_control) => access_control.resource.as_deref(),
+        }
+    }
+
+    /// `u`: user name
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            Self::Plain(_) => None,
+            Self::AccessControl(access_control) => access_control.user.as_deref(),
+        }
+    }
+}
+
+impl Default for StreamId {
+    fn default() -> Self {
+        Self::Plain(String::new())
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix(PREFIX) {
+            Some(entries) => Ok(Self::AccessControl(entries.parse()?)),
+            None => Ok(Self::Plain(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain(id) => f.write_str(id),
+            Self::AccessControl(access_control) => write!(f, "{PREFIX}{access_control}"),
+        }
+    }
+}
+
+/// `m`: what the caller wants to do with the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Receive the stream (default)
+    Request,
+    /// Send the stream
+    Publish,
+    /// Both send and receive
+    Bidirectional,
+}
+
+impl Mode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Request => "request",
+            Self::Publish => "publish",
+            Self::Bidirectional => "bidirectional",
+        }
+    }
+}
+
+/// `t`: kind of the connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceType {
+    /// Live stream or file transmission (default)
+    Stream,
+    /// Transfer of a file
+    File,
+    /// Authentication only, no data
+    Auth,
+}
+
+impl ResourceType {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Stream => "stream",
+            Self::File => "file",
+            Self::Auth => "auth",
+        }
+    }
+}
+
+/// Keys of a `#!::` stream ID (all optional)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessControl {
+    /// `r`: name of the requested resource
+    pub resource: Option<String>,
+    /// `m`
+    pub mode: Option<Mode>,
+    /// `u`: user name (e.g. to pick the passphrase)
+    pub user: Option<String>,
+    /// `h`: host name, for servers hosting several sites
+    pub host: Option<String>,
+    /// `s`: session ID, for reconnecting to a session
+    pub session: Option<String>,
+    /// `t`
+    pub r#type: Option<ResourceType>,
+    /// Application-specific keys, in order
+    pub custom: Vec<(String, String)>,
+}
+
+impl FromStr for AccessControl {
+    type Err = Error;
+
+    /// `key=value` entries separated by `,` (without the `#!::` prefix)
+    fn from_str(s: &str) -> Result<Self> {
+        let mut access_control = Self::default();
+
+        for entry in s.split(',').filter(|entry| !entry.is_empty()) {
+            let Some((key, value)) = entry.split_once('=') else {
+                bail!("Stream ID entry without a value: {entry:?}");
+            };
+            let value = value.to_owned();
+
+            match key {
+                "r" => access_control.resource = Some(value),
+                "m" => {
+                    access_control.mode = Some(match value.as_str() {
+                        "request" => Mode::Request,
+                        "publish" => Mode::Publish,
+                        "bidirectional" => Mode::Bidirectional,
+                        _ => bail!("Unknown stream ID mode: {value:?}"),
+                    });
+                }
+                "u" => access_control.user = Some(value),
+                "h" => access_control.host = Some(value),
+                "s" => access_control.session = Some(value),
+                "t" => {
+                    access_control.r#type = Some(match value.as_str() {
+                        "stream" => ResourceType::Stream,
+                        "file" => ResourceType::File,
+                        "auth" => ResourceType::Auth,
+                        _ => bail!("Unknown stream ID type: {value:?}"),
+                    });
+                }
+                _ => access_control.custom.push((key.to_owned(), value)),
+            }
+        }
+
+        Ok(access_control)
+    }
+}
+
+impl fmt::Display for AccessControl {
+    /// Entries in the order `r`, `m`, `u`, `h`, `s`, `t`, then the custom ones
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = [
+            ("r", self.resource.as_deref()),
+            ("m", self.mode.map(Mode::as_str)),
+            ("u", self.user.as_deref()),
+            ("h", self.host.as_deref()),
+            ("s", self.session.as_deref()),
+            ("t", self.r#type.map(ResourceType::as_str)),
+        ];
+        let entries = entries
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .chain(
+                self.custom
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            );
+
+        for (i, (key, value)) in entries.enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{key}={value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_control() -> Result<()> {
+        let raw = "#!::r=live/cam1,m=publish,u=alice,s=sess,t=stream";
+        let stream_id: StreamId = raw.parse()?;
+
+        let StreamId::AccessControl(access_control) = &stream_id else {
+            bail!("Expected the access-control syntax");
+        };
+        assert_eq!(access_control.resource.as_deref(), Some("live/cam1"));
+        assert_eq!(access_control.mode, Some(Mode::Publish));
+        assert_eq!(access_control.user.as_deref(), Some("alice"));
+        assert_eq!(access_control.session.as_deref(), Some("sess"));
+        assert_eq!(access_control.r#type, Some(ResourceType::Stream));
+        assert_eq!(stream_id.to_string(), raw);
+
+        let custom: StreamId = "#!::u=bob,x-token=abc".parse()?;
+        assert_eq!(custom.user(), Some("bob"));
+        assert_eq!(custom.to_string(), "#!::u=bob,x-token=abc");
+
+        assert!("#!::m=upload".parse::<StreamId>().is_err());
+        assert!("#!::r".parse::<StreamId>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain() -> Result<()> {
+        let stream_id: StreamId = "live/cam1".parse()?;
+
+        assert_eq!(stream_id, StreamId::Plain("live/cam1".to_owned()));
+        assert_eq!(stream_id.resource(), Some("live/cam1"));
+        assert_eq!(stream_id.to_string(), "live/cam1");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Stateless SYN cookies of a listener
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.1>
+
+use std::{net::SocketAddr, time::Instant};
+
+use sha1::{Digest, Sha1};
+
+use crate::protocol::constants::SYN_COOKIE_INTERVAL;
+
+/// Issues and verifies the `SYN Cookie` of Induction responses
+///
+/// A cookie depends only on the peer address, a time bucket and a secret,
+/// so no state is kept for a caller until its Conclusion proves the address.
+#[derive(Debug)]
+pub struct SynCookies {
+    secret: [u8; 16],
+    /// Start of the first time bucket
+    epoch: Instant,
+}
+
+impl SynCookies {
+    pub fn new() -> Self {
+        Self {
+            secret: rand::random(),
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Cookie for `addr` at `now`
+    pub fn generate(&self, addr: SocketAddr, now: Instant) -> u32 {
+        self.cookie(addr, self.bucket(now))
+    }
+
+    /// Whether `cookie` was issued to `addr` in the current or the previous time bucket
+    pub fn verify(&self, addr: SocketAddr, cookie: u32, now: Instant) -> bool {
+        let bucket = self.bucket(now);
+
+        cookie == self.cookie(addr, bucket) || bucket > 0 && cookie == self.cookie(addr, bucket - 1)
+    }
+
+    fn bucket(&self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.epoch);
+
+        (elapsed.as_micros() / u128::from(SYN_COOKIE_INTERVAL))
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    fn cookie(&self, addr: SocketAddr, bucket: u64) -> u32 {
+        let mut hasher = Sha1::new();
+        hasher.update(self.secret);
+        match addr {
+            SocketAddr::V4(addr) => hasher.update(addr.ip().octets()),
+            SocketAddr::V6(addr) => hasher.update(addr.ip().octets()),
+        }
+        hasher.update(addr.port().to_be_bytes());
+        hasher.update(bucket.to_be_bytes());
+
+        let hash = hasher.finalize();
+        u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+    }
+}
+
+impl Default for SynCookies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_syn_cookies() {
+        let cookies = SynCookies::new();
+        let addr: SocketAddr = ([192, 0, 2, 1], 9000).into();
+        let now = cookies.epoch + Duration::from_secs(100);
+
+        let cookie = cookies.generate(addr, now);
+        assert!(cookies.verify(addr, cookie, now));
+
+        // Different port or secret
+        assert!(!cookies.verify(([192, 0, 2, 1], 9001).into(), cookie, now));
+        assert!(!SynCookies::new().verify(addr, cookie, now));
+
+        // Accepted in the following bucket, expired after it
+        let interval = Duration::from_micros(SYN_COOKIE_INTERVAL.into());
+        assert!(cookies.verify(addr, cookie, now + interval));
+        assert!(!cookies.verify(addr, cookie, now + 2 * interval));
+    }
+}
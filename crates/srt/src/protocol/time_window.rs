@@ -0,0 +1,182 @@
+//! Receiver's estimates of arrival speed and link capacity, reported in full ACKs
+//! (`CPktTimeWindow` of `libsrt`)
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.4>
+
+use std::{collections::VecDeque, time::Instant};
+
+use crate::protocol::{
+    constants::{MAX_PACKET_SIZE, MAX_PAYLOAD_SIZE},
+    packet::data::DataPacketInfo,
+};
+
+/// Arrival intervals the receive speed is computed from
+const ARRIVAL_WINDOW: usize = 16;
+/// Packet-pair intervals the link capacity is computed from
+const PROBE_WINDOW: usize = 64;
+/// Every 16th packet and its successor are sent back to back as a probe
+const PROBE_PERIOD: u32 = 16;
+
+/// (bytes) IPv4/UDP and SRT headers, counted into the byte rate
+const HEADER_SIZE: u64 = (MAX_PACKET_SIZE - MAX_PAYLOAD_SIZE) as u64;
+
+pub struct TimeWindow {
+    /// (micros) Interval before each recent arrival, with the packet's payload size
+    arrivals: VecDeque<(u64, u64)>,
+    last_arrival: Option<Instant>,
+
+    /// (micros) Intervals within recent probe pairs
+    probes: VecDeque<u64>,
+    /// Arrival of the first packet of a probe pair
+    probe_start: Option<Instant>,
+}
+
+impl TimeWindow {
+    pub fn new() -> Self {
+        Self {
+            arrivals: VecDeque::with_capacity(ARRIVAL_WINDOW),
+            last_arrival: None,
+            probes: VecDeque::with_capacity(PROBE_WINDOW),
+            probe_start: None,
+        }
+    }
+
+    /// Data packet `data` arrived at `now`
+    pub fn arrival(&mut self, now: Instant, data: &DataPacketInfo) {
+        if let Some(last) = self.last_arrival.replace(now) {
+            if self.arrivals.len() == ARRIVAL_WINDOW {
+                self.arrivals.pop_front();
+            }
+            self.arrivals
+                .push_back((micros(now - last), data.content.len() as u64));
+        }
+
+        // Retransmissions are not sent back to back with their neighbours
+        if data.retransmitted {
+            self.probe_start = None;
+            return;
+        }
+        match data.packet_sequence_number % PROBE_PERIOD {
+            0 => self.probe_start = Some(now),
+            1 => {
+                if let Some(start) = self.probe_start.take() {
+                    if self.probes.len() == PROBE_WINDOW {
+                        self.probes.pop_front();
+                    }
+                    self.probes.push_back(micros(now - start));
+                }
+            }
+            _ => self.probe_start = None,
+        }
+    }
+
+    /// (packets/s, bytes/s) Recent receive speed (zeros until enough packets arrived)
+    pub fn receive_speed(&self) -> (u32, u32) {
+        let intervals: Vec<u64> = self
+            .arrivals
+            .iter()
+            .map(|(interval, _)| *interval)
+            .collect();
+        let Some((lower, upper)) = median_bounds(&intervals) else {
+            return (0, 0);
+        };
+
+        let (count, sum, bytes) = self
+            .arrivals
+            .iter()
+            .filter(|(interval, _)| (lower..upper).contains(interval))
+            .fold((0, 0, 0), |(count, sum, bytes), (interval, size)| {
+                (count + 1, sum + interval, bytes + size + HEADER_SIZE)
+            });
+        // Too irregular to tell
+        if count <= ARRIVAL_WINDOW as u64 / 2 || sum == 0 {
+            return (0, 0);
+        }
+
+        (
+            saturate(count * 1_000_000 / sum),
+            saturate(bytes * 1_000_000 / sum),
+        )
+    }
+
+    /// (packets/s) Link capacity estimated from probe pairs (zero before the first pair)
+    pub fn link_capacity(&self) -> u32 {
+        let intervals: Vec<u64> = self.probes.iter().copied().collect();
+        let Some((lower, upper)) = median_bounds(&intervals) else {
+            return 0;
+        };
+
+        let (count, sum) = intervals
+            .iter()
+            .filter(|interval| (lower..upper).contains(*interval))
+            .fold((0, 0), |(count, sum), interval| (count + 1, sum + interval));
+        if sum == 0 {
+            return 0;
+        }
+
+        saturate((count * 1_000_000 + sum / 2) / sum)
+    }
+}
+
+/// Range around the median of `values` that excludes outliers (`None` if empty)
+fn median_bounds(values: &[u64]) -> Option<(u64, u64)> {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let median = *sorted.get(sorted.len() / 2)?;
+
+    Some((median / 8, median.saturating_mul(8).max(1)))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn micros(duration: std::time::Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+fn saturate(value: u64) -> u32 {
+    value.try_into().unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::protocol::packet::data::{EncryptionFlag, PacketPosition};
+
+    fn packet(seq: u32) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number: seq,
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: 1,
+            content: vec![0; 1000],
+        }
+    }
+
+    #[test]
+    fn test_estimates() {
+        let mut window = TimeWindow::new();
+        assert_eq!(window.receive_speed(), (0, 0));
+        assert_eq!(window.link_capacity(), 0);
+
+        // Paced at 1 ms, except for probe pairs 100 us apart
+        let mut now = Instant::now();
+        for seq in 0..64 {
+            now += if seq % PROBE_PERIOD == 1 {
+                Duration::from_micros(100)
+            } else {
+                Duration::from_millis(1)
+            };
+            window.arrival(now, &packet(seq));
+        }
+
+        // Probe pairs fall outside the median filter
+        let (packets, bytes) = window.receive_speed();
+        assert!((900..=1100).contains(&packets), "{packets}");
+        assert_eq!(u64::from(bytes) / u64::from(packets), 1000 + HEADER_SIZE);
+
+        assert_eq!(window.link_capacity(), 10_000);
+    }
+}
@@ -0,0 +1,91 @@
+//! Timestamp-based packet delivery
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.5>
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Tsbpd {
+    /// Local time at which the peer's clock read `base_timestamp`
+    time_base: Instant,
+    base_timestamp: u64,
+
+    /// Extended (wrap-free) timestamp of the newest packet
+    last_timestamp: u64,
+
+    latency: Duration,
+}
+
+impl Tsbpd {
+    pub fn new(time_base: Instant, base_timestamp: u32, latency: Duration) -> Self {
+        Self {
+            time_base,
+            base_timestamp: base_timestamp.into(),
+            last_timestamp: base_timestamp.into(),
+            latency,
+        }
+    }
+
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Local time to deliver a packet with `Timestamp` of `timestamp`
+    pub fn delivery_time(&mut self, timestamp: u32) -> Instant {
+        let timestamp = self.extend(timestamp);
+
+        let sent = if timestamp >= self.base_timestamp {
+            self.time_base + Duration::from_micros(timestamp - self.base_timestamp)
+        } else {
+            self.time_base
+                .checked_sub(Duration::from_micros(self.base_timestamp - timestamp))
+                .unwrap_or(self.time_base)
+        };
+
+        sent + self.latency
+    }
+
+    /// Undo the 32-bit wrap-around (every ~71.6 minutes) of `timestamp`
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn extend(&mut self, timestamp: u32) -> u64 {
+        let diff = timestamp.wrapping_sub(self.last_timestamp as u32) as i32;
+        let extended = self.last_timestamp.saturating_add_signed(diff.into());
+
+        if diff > 0 {
+            self.last_timestamp = extended;
+        }
+
+        extended
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivery_time() {
+        let now = Instant::now();
+        let latency = Duration::from_millis(120);
+        let mut tsbpd = Tsbpd::new(now, 1_000, latency);
+
+        assert_eq!(tsbpd.delivery_time(1_000), now + latency);
+        assert_eq!(
+            tsbpd.delivery_time(3_000),
+            now + latency + Duration::from_millis(2)
+        );
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let now = Instant::now();
+        let mut tsbpd = Tsbpd::new(now, u32::MAX - 999, Duration::ZERO);
+
+        assert_eq!(tsbpd.delivery_time(1_000), now + Duration::from_millis(2));
+        // Reordered packet from before the wrap
+        assert_eq!(
+            tsbpd.delivery_time(u32::MAX),
+            now + Duration::from_micros(999)
+        );
+    }
+}
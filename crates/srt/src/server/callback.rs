@@ -1,2 +1,3 @@
+pub mod caller;
 pub mod connection;
 pub mod listener;
@@ -0,0 +1,112 @@
+use std::net::{Ipv4Addr, Ipv6Addr, ToSocketAddrs, UdpSocket};
+
+use anyhow::{Context, Result};
+
+use super::{
+    connection::CallbackConnection,
+    listener::{OnDataHandler, recv, timeout_until},
+};
+use crate::{
+    options::Options,
+    protocol::{
+        ops::CloseReason,
+        packet::{PacketContent, control::ControlPacketInfo},
+    },
+};
+
+type OnConnectHandler = dyn Fn(&CallbackConnection);
+type OnDiscnnectHandler = dyn Fn(&CallbackConnection, CloseReason);
+
+/// Initiating side of a connection (counterpart to [`super::listener::CallbackListener`])
+pub struct CallbackCaller {
+    options: Options,
+
+    on_connect: Option<Box<OnConnectHandler>>,
+    on_disconnect: Option<Box<OnDiscnnectHandler>>,
+    on_data: Option<Box<OnDataHandler>>,
+}
+
+impl CallbackCaller {
+    pub fn new() -> Self {
+        Self::with_options(Options::new())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        Self {
+            options,
+            on_connect: None,
+            on_disconnect: None,
+            on_data: None,
+        }
+    }
+
+    pub fn on_connect(&mut self, f: impl Fn(&CallbackConnection) + 'static) {
+        self.on_connect = Some(Box::new(f));
+    }
+
+    pub fn on_disconnect(&mut self, f: impl Fn(&CallbackConnection, CloseReason) + 'static) {
+        self.on_disconnect = Some(Box::new(f));
+    }
+
+    pub fn on_data(&mut self, f: impl Fn(&CallbackConnection, &[u8]) + 'static) {
+        self.on_data = Some(Box::new(f));
+    }
+
+    /// Connect to a listener and serve the connection until it is shut down
+    /// (or the listener goes idle)
+    pub fn run(&self, addr: impl ToSocketAddrs) -> Result<()> {
+        let _span = tracing::info_span!("srt_caller").entered();
+
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .context("Failed to resolve address")?;
+
+        let socket = if addr.is_ipv4() {
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+        } else {
+            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+        };
+
+        let conn =
+            CallbackConnection::connect_v5(&socket, addr, self.on_data.as_deref(), &self.options)?;
+        tracing::info!(?addr, "Connected");
+        self.on_connect.as_ref().inspect(|f| f(&conn));
+
+        loop {
+            socket.set_read_timeout(timeout_until(Some(conn.next_update()?)))?;
+
+            let received = recv(&socket)?;
+
+            conn.update()?;
+
+            if conn.idle()? {
+                conn.expire(CloseReason::PeerIdle);
+                self.on_disconnect
+                    .as_ref()
+                    .inspect(|f| f(&conn, CloseReason::PeerIdle));
+                return Ok(());
+            }
+
+            let Some((from, pack)) = received else {
+                continue;
+            };
+            if from != addr {
+                continue;
+            }
+
+            conn.handle(from, &pack)?;
+
+            if matches!(
+                pack.content,
+                PacketContent::Control(ControlPacketInfo::Shutdown)
+            ) {
+                tracing::info!(?addr, "Disconnect");
+                self.on_disconnect
+                    .as_ref()
+                    .inspect(|f| f(&conn, CloseReason::Peer));
+                return Ok(());
+            }
+        }
+    }
+}
@@ -2,155 +2,470 @@ use std::{
     net::{SocketAddr, UdpSocket},
     sync::{
         Mutex,
+        MutexGuard,
         atomic::{AtomicU32, Ordering},
     },
-    time::{Instant, SystemTime},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, anyhow, bail};
 use tracing::{Level, span};
 
 use super::listener::OnDataHandler;
-use crate::protocol::{
-    constants::{FULL_ACK_INTERVAL, HANDSHAKE_MAGIC_CODE, RTT_INIT, RTT_VAR_INIT},
-    packet::{
-        Packet,
-        PacketContent,
-        control::{ControlPacketInfo, ack::Ack, handshake::Handshake, nak::Nak},
-        data::DataPacketInfo,
+use crate::{
+    options::Options,
+    protocol::{
+        ack_history::AckHistory,
+        congestion::{self, AckInfo, CongestionController, Pacer},
+        constants::{
+            CONNECTION_TIMEOUT,
+            FULL_ACK_INTERVAL,
+            HANDSHAKE_RETRANSMIT_INTERVAL,
+            KEEPALIVE_INTERVAL,
+            LIGHT_ACK_PACKETS,
+            MAX_FLOW_WINDOW_SIZE,
+            MAX_PACKET_SIZE,
+            MAX_PAYLOAD_SIZE,
+            RTT_INIT,
+            RTT_VAR_INIT,
+        },
+        crypto::Crypto,
+        filter::fec::{self, Arq, Fec},
+        ops::{self, CloseReason},
+        packet::{
+            Packet,
+            PacketContent,
+            control::{
+                ControlPacketInfo,
+                ack::Ack,
+                ack_ack::AckAck,
+                drop_req::DropReq,
+                handshake::{
+                    Handshake,
+                    HandshakeType,
+                    extension::key_material::KeyMaterialExtension,
+                },
+                nak::Nak,
+                other_subtypes,
+            },
+            data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+        },
+        receive_buffer::{Arrival, ReceiveBuffer},
+        send_buffer::SendBuffer,
+        sequence,
+        stats::{self, Counters, Recorder, Stats},
+        stream_id::StreamId,
+        time_window::TimeWindow,
     },
 };
 
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| anyhow!("Connection state lock poisoned"))
+}
+
 pub struct CallbackConnection<'c> {
     socket: &'c UdpSocket,
     on_data: Option<&'c OnDataHandler>,
 
     // Srt info
-    pub stream_id: Option<String>,
+    pub stream_id: Option<StreamId>,
     pub established: SystemTime,
     pub addr: SocketAddr,
+    pub srt_socket_id: u32,
     pub peer_srt_socket_id: u32,
+    /// Negotiated TSBPD delay of received packets
+    pub latency: Option<Duration>,
 
-    // /// # of packets received since last ack was sent
-    // received_since_ack: AtomicU32,
+    /// # of data packets received since the last ACK was sent
+    received_since_ack: AtomicU32,
     /// Ack sequence number
     ack_counter: AtomicU32,
 
-    /// Timestamp of the last **sent** Ack
-    /// (used to calculate RTT)
+    /// Time of the last full ACK timer tick
     last_ack_timestamp: Mutex<Instant>,
-
-    /// Package sequence number of last received data packet
-    last_received: AtomicU32,
+    /// Full ACKs awaiting the peer's ACKACK (used to calculate RTT)
+    ack_history: Mutex<AckHistory>,
 
     /// <add link>
     rtt: AtomicU32,
     /// <add link>
     rtt_var: AtomicU32,
+
+    /// Sent data packets awaiting acknowledgement
+    send_buffer: Mutex<SendBuffer>,
+    /// Keep boundaries of sent messages (otherwise they form a byte stream)
+    message_api: bool,
+    /// Received data packets awaiting in-order delivery
+    receive_buffer: Mutex<ReceiveBuffer>,
+    /// (micros) Age at which unacknowledged packets are given up on
+    send_drop_threshold: Option<u32>,
+    /// Repeat the loss list every [`ops::nak_interval`]
+    periodic_nak: bool,
+    /// Time of the last periodic NAK report
+    last_nak_timestamp: Mutex<Instant>,
+    /// Report losses as soon as they are detected ([`Arq::Always`])
+    arq: Arq,
+    /// Parity packets of the packet filter (`None` without one)
+    fec: Option<Mutex<Fec>>,
+
+    /// Silence from the peer after which the connection is considered dead
+    peer_idle_timeout: Duration,
+    /// Time the last packet arrived from the peer
+    last_received: Mutex<Instant>,
+    /// Time the last packet was sent to the peer (for keepalives)
+    last_sent: Mutex<Instant>,
+
+    /// Payload keys (`None` without a passphrase)
+    crypto: Option<Mutex<Crypto>>,
+    /// # of received packets discarded as undecryptable or forged
+    undecrypted: AtomicU32,
+
+    /// Arrival times for the rates and link capacity reported in full ACKs
+    time_window: Mutex<TimeWindow>,
+    /// Packet counters for [`Self::stats`]
+    stats: Mutex<Recorder>,
+    /// (packets/s) `Estimated Link Capacity` of the peer's last full ACK
+    peer_link_capacity: AtomicU32,
+
+    /// Sending rate and window of data packets
+    congestion: Mutex<Box<dyn CongestionController>>,
+    /// Spaces sent data packets by [`CongestionController::send_period`]
+    pacer: Mutex<Pacer>,
+
+    /// Listener's answer to the peer's Conclusion request, repeated if the
+    /// request arrives again (the response was lost)
+    conclusion_response: Option<Handshake>,
+    /// `HSRSP` of an HSv4 connection, repeated if the caller's `HSREQ` arrives again
+    handshake_response: Option<ControlPacketInfo>,
 }
 
 impl<'c> CallbackConnection<'c> {
-    pub fn establish_v5(socket: &'c UdpSocket, on_data: Option<&'c OnDataHandler>) -> Result<Self> {
+    /// Complete the handshake as the listener, answering the Conclusion request
+    /// `in_packet` from `addr`
+    ///
+    /// The listener has already answered the Induction request and verified the SYN cookie.
+    pub fn establish_v5(
+        socket: &'c UdpSocket,
+        on_data: Option<&'c OnDataHandler>,
+        options: &Options,
+        addr: SocketAddr,
+        srt_socket_id: u32,
+        in_packet: &Packet,
+    ) -> Result<Self> {
         let _span = span!(Level::INFO, "srt_connection_handshake");
 
-        let mut buf = [0; 200];
-
-        tracing::debug!("Waiting for a handshake...");
+        tracing::debug!("Connection: {addr}");
 
         //
-        // Induction
+        // Conclusion
         //
 
-        let (n, addr) = socket.recv_from(&mut buf)?;
-        let data = &buf[..n];
-
-        tracing::debug!("Connection: {addr}");
-
-        let in_packet = Packet::from_raw(data)?;
-        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = in_packet.content
+        let time_base = Instant::now();
+        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &in_packet.content
         else {
             bail!("Failed to unwrap handshake");
         };
+        let Some(request) = handshake.handshake_extension() else {
+            bail!("Missing handshake extension");
+        };
+
+        let (extension, terms) = ops::handshake_response(request, options);
+        let (crypto, key_material) =
+            ops::key_material_response(handshake.key_material_extension(), options)?.unzip();
+
+        let negotiated = ops::Negotiated {
+            srt_socket_id,
+            peer_srt_socket_id: handshake.srt_socket_id,
+            initial_packet_sequence_number: handshake.initial_packet_sequence_number,
+            stream_id: ops::requested_stream_id(handshake)?,
+            congestion: ops::agreed_congestion(handshake, options)?,
+            filter: ops::agreed_filter(handshake, options)?,
+            terms,
+            peer_timestamp: in_packet.timestamp,
+            time_base,
+        };
 
+        // Timestamps of this side start with the response
+        let established = SystemTime::now();
+
+        let response = ops::conclusion_response(
+            handshake,
+            srt_socket_id,
+            extension,
+            key_material,
+            negotiated.congestion,
+            negotiated.filter.as_ref(),
+            None,
+        )?;
         let out_packet_v5 = Packet {
-            timestamp: in_packet.timestamp + 1,
+            timestamp: 0,
             dest_socket_id: handshake.srt_socket_id,
-            content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
-                version: 5,
-                extension_field: HANDSHAKE_MAGIC_CODE,
-                srt_socket_id: 42,
-                syn_cookie: 42,
-                ..handshake
-            })),
+            content: PacketContent::Control(ControlPacketInfo::Handshake(response.clone())),
         };
         socket.send_to(&out_packet_v5.to_raw(), addr)?;
 
-        tracing::debug!("Completed Induction");
+        tracing::debug!("Completed Conclusion");
+        tracing::debug!("Done!");
+
+        let mut connection = Self::new(
+            socket,
+            on_data,
+            established,
+            addr,
+            negotiated,
+            crypto,
+            options,
+        );
+        connection.conclusion_response = Some(response);
+
+        Ok(connection)
+    }
+
+    /// Complete the HSv4 handshake as the listener, once the caller at `addr` has sent
+    /// its SRT options (`legacy`) after the Conclusion `request` answered with `response`
+    ///
+    /// `peer_timestamp` and `time_base` are those of the Conclusion request, `established`
+    /// the time of the response.
+    #[allow(clippy::too_many_arguments)]
+    pub fn establish_legacy(
+        socket: &'c UdpSocket,
+        on_data: Option<&'c OnDataHandler>,
+        options: &Options,
+        addr: SocketAddr,
+        request: &Handshake,
+        response: Handshake,
+        peer_timestamp: u32,
+        time_base: Instant,
+        established: SystemTime,
+        legacy: &ops::LegacyRequest,
+    ) -> Result<Self> {
+        let (negotiated, crypto, replies) = legacy.respond(
+            request,
+            response.srt_socket_id,
+            peer_timestamp,
+            time_base,
+            options,
+        )?;
+
+        tracing::debug!("Completed HSv4 Handshake");
+
+        let mut connection = Self::new(
+            socket,
+            on_data,
+            established,
+            addr,
+            negotiated,
+            crypto,
+            options,
+        );
+        connection.conclusion_response = Some(response);
+        connection.handshake_response = replies.first().cloned();
+        for reply in replies {
+            connection.send(PacketContent::Control(reply))?;
+        }
+
+        Ok(connection)
+    }
+
+    /// Perform the handshake as the initiator (caller) against a listener at `addr`
+    pub fn connect_v5(
+        socket: &'c UdpSocket,
+        addr: SocketAddr,
+        on_data: Option<&'c OnDataHandler>,
+        options: &Options,
+    ) -> Result<Self> {
+        let _span = span!(Level::INFO, "srt_connection_handshake");
+
+        let started = Instant::now();
+        socket.set_read_timeout(Some(Duration::from_micros(
+            HANDSHAKE_RETRANSMIT_INTERVAL.into(),
+        )))?;
 
         //
-        // Conclusion
+        // Induction
         //
 
-        let (n, addr) = socket.recv_from(&mut buf)?;
-        let data = &buf[..n];
+        let srt_socket_id = ops::random_socket_id();
+        let induction_request =
+            ops::induction_request(srt_socket_id, ops::random_sequence_number(), addr.ip());
+        let (_, induction_response) = Self::exchange(socket, addr, &induction_request, started)?;
 
-        let in_packet = Packet::from_raw(data)?;
-        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = in_packet.content
-        else {
-            bail!("Failed to unwrap handshake");
-        };
+        tracing::debug!("Completed Induction");
 
-        let peer_srt_socket_id = handshake.srt_socket_id;
-        let stream_id = handshake
-            .stream_id_extension
-            .as_ref()
-            .map(|x| x.stream_id.clone());
+        //
+        // Conclusion
+        //
 
-        let out_packet_v5 = Packet {
-            timestamp: in_packet.timestamp + 1,
-            dest_socket_id: handshake.srt_socket_id,
-            content: PacketContent::Control(ControlPacketInfo::Handshake(handshake)),
-        };
-        socket.send_to(&out_packet_v5.to_raw(), addr)?;
+        let (crypto, key_material) = ops::key_material_request(options)?.unzip();
+        let conclusion_request = ops::conclusion_request(
+            &induction_response,
+            &induction_request,
+            options,
+            key_material,
+            None,
+        )?;
+        let (peer_timestamp, conclusion_response) =
+            Self::exchange(socket, addr, &conclusion_request, started)?;
+        if crypto.is_some() && conclusion_response.key_material_extension().is_none() {
+            bail!("Listener did not accept key material");
+        }
+        if let Some(extension) = conclusion_response.handshake_extension() {
+            ops::check_message_api(extension, options)?;
+        }
+        let time_base = Instant::now();
 
         tracing::debug!("Completed Conclusion");
         tracing::debug!("Done!");
 
+        socket.set_read_timeout(None)?;
+
+        let negotiated = ops::Negotiated {
+            srt_socket_id,
+            peer_srt_socket_id: conclusion_response.srt_socket_id,
+            initial_packet_sequence_number: induction_request.initial_packet_sequence_number,
+            stream_id: options.stream_id.clone(),
+            congestion: ops::response_congestion(&conclusion_response, options)?,
+            filter: ops::response_filter(&conclusion_response, options)?,
+            terms: conclusion_response
+                .handshake_extension()
+                .map(|extension| ops::response_terms(extension, options))
+                .unwrap_or_default(),
+            peer_timestamp,
+            time_base,
+        };
+
         let established = SystemTime::now();
 
         Ok(Self::new(
             socket,
             on_data,
-            stream_id,
             established,
             addr,
-            peer_srt_socket_id,
+            negotiated,
+            crypto,
+            options,
         ))
     }
 
+    /// Send a caller's handshake request until a response of the same type arrives
+    ///
+    /// Returns the response with its `Timestamp` (fails with the `RejectReason` of a refusal)
+    fn exchange(
+        socket: &UdpSocket,
+        addr: SocketAddr,
+        request: &Handshake,
+        started: Instant,
+    ) -> Result<(u32, Handshake)> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+
+        loop {
+            if started.elapsed() > Duration::from_micros(CONNECTION_TIMEOUT.into()) {
+                bail!("Connection timed out");
+            }
+
+            #[allow(clippy::cast_possible_truncation)]
+            let out_packet = Packet {
+                timestamp: started.elapsed().as_micros() as u32,
+                dest_socket_id: 0,
+                content: PacketContent::Control(ControlPacketInfo::Handshake(request.clone())),
+            };
+            socket.send_to(&out_packet.to_raw(), addr)?;
+
+            let (n, from) = match socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if from != addr {
+                continue;
+            }
+
+            let Ok(in_packet) = Packet::from_raw(&buf[..n]) else {
+                continue;
+            };
+            if let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) =
+                in_packet.content
+            {
+                if let HandshakeType::Rejected(reason) = handshake.handshake_type {
+                    return Err(reason.into());
+                }
+                if handshake.handshake_type == request.handshake_type {
+                    return Ok((in_packet.timestamp, handshake));
+                }
+            }
+        }
+    }
+
     fn new(
         socket: &'c UdpSocket,
         on_data: Option<&'c OnDataHandler>,
-        stream_id: Option<String>,
         established: SystemTime,
         addr: SocketAddr,
-        peer_srt_socket_id: u32,
+        negotiated: ops::Negotiated,
+        crypto: Option<Crypto>,
+        options: &Options,
     ) -> Self {
         Self {
             on_data,
             socket,
-            stream_id,
+            stream_id: negotiated.stream_id.clone(),
             established,
             addr,
-            peer_srt_socket_id,
+            srt_socket_id: negotiated.srt_socket_id,
+            peer_srt_socket_id: negotiated.peer_srt_socket_id,
+            latency: negotiated.terms.latency,
+            send_drop_threshold: negotiated.send_drop_threshold(),
+            message_api: options.message_api,
+            crypto: crypto.map(Mutex::new),
+            undecrypted: AtomicU32::new(0),
+            time_window: Mutex::new(TimeWindow::new()),
+            stats: Mutex::new(Recorder::new()),
+            peer_link_capacity: AtomicU32::new(0),
+            periodic_nak: negotiated.periodic_nak(),
+            last_nak_timestamp: Mutex::new(Instant::now()),
+            arq: negotiated.arq(),
+            fec: negotiated.fec().map(|config| {
+                Mutex::new(Fec::new(config, negotiated.initial_packet_sequence_number))
+            }),
+            peer_idle_timeout: options.peer_idle_timeout,
+            last_received: Mutex::new(Instant::now()),
+            last_sent: Mutex::new(Instant::now()),
 
             ack_counter: AtomicU32::new(1),
             last_ack_timestamp: Mutex::new(Instant::now()),
-            // received_since_ack: AtomicU32::new(0),
-            last_received: AtomicU32::new(0),
-
+            ack_history: Mutex::new(AckHistory::new()),
+            received_since_ack: AtomicU32::new(0),
             rtt: AtomicU32::new(RTT_INIT),
             rtt_var: AtomicU32::new(RTT_VAR_INIT),
+
+            send_buffer: Mutex::new(SendBuffer::new(
+                negotiated.initial_packet_sequence_number,
+                MAX_FLOW_WINDOW_SIZE as usize,
+            )),
+            receive_buffer: Mutex::new(ReceiveBuffer::new(
+                negotiated.initial_packet_sequence_number,
+                MAX_FLOW_WINDOW_SIZE as usize,
+                negotiated.tsbpd(),
+                negotiated.terms.too_late_drop,
+            )),
+            congestion: Mutex::new(congestion::controller(
+                negotiated.congestion,
+                options,
+                negotiated.initial_packet_sequence_number,
+            )),
+            pacer: Mutex::new(Pacer::new()),
+            conclusion_response: None,
+            handshake_response: None,
         }
     }
 
@@ -158,46 +473,286 @@ impl<'c> CallbackConnection<'c> {
         self.ack_counter.fetch_add(1, Ordering::Relaxed)
     }
 
-    // pub(crate) fn check_ack(&self) -> bool {
-    //     self.received_since_ack
-    //         .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| Some((x + 1) % 64))
-    //         == Ok(0)
-    // }
+    /// Count a received data packet, `true` once a light ACK is due
+    pub(crate) fn check_ack(&self) -> bool {
+        self.received_since_ack.fetch_add(1, Ordering::Relaxed) + 1 >= LIGHT_ACK_PACKETS
+    }
 
+    /// (micros) since the connection was established
     #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn timestamp(&self) -> Result<u32> {
+        Ok(SystemTime::now()
+            .duration_since(self.established)?
+            .as_micros() as u32)
+    }
+
     pub(crate) fn pack(&self, content: PacketContent) -> Result<Packet> {
         Ok(Packet {
-            timestamp: SystemTime::now()
-                .duration_since(self.established)?
-                .as_micros() as u32,
+            timestamp: self.timestamp()?,
             dest_socket_id: self.peer_srt_socket_id,
             content,
         })
     }
 
     pub fn send(&self, content: PacketContent) -> Result<()> {
-        self.socket
-            .send_to(&self.pack(content)?.to_raw(), self.addr)?;
+        self.send_packet(&self.pack(content)?)
+    }
+
+    fn send_packet(&self, pack: &Packet) -> Result<()> {
+        self.socket.send_to(&pack.to_raw(), self.addr)?;
+        *lock(&self.last_sent)? = Instant::now();
+
+        Ok(())
+    }
+
+    fn send_data_packet(&self, timestamp: u32, mut data: DataPacketInfo) -> Result<()> {
+        let announcement = match &self.crypto {
+            Some(crypto) => {
+                let mut crypto = lock(crypto)?;
+                crypto.encrypt(&mut data)?;
+                crypto.announcement(Instant::now())
+            }
+            None => None,
+        };
+
+        // Parity covers the packets as they are first sent
+        let parity = match &self.fec {
+            Some(fec) if !data.retransmitted => lock(fec)?.sent(timestamp, &data),
+            _ => Vec::new(),
+        };
+
+        let pack = Packet {
+            timestamp,
+            dest_socket_id: self.peer_srt_socket_id,
+            content: PacketContent::Data(data),
+        };
+        self.send_packet(&pack)?;
+
+        for (timestamp, parity) in parity {
+            lock(&self.stats)?.parity_sent();
+            self.send_packet(&Packet {
+                timestamp,
+                dest_socket_id: self.peer_srt_socket_id,
+                content: PacketContent::Data(parity),
+            })?;
+        }
+
+        if let Some(key_material) = announcement {
+            self.send_key_material(other_subtypes::KMREQ, &key_material)?;
+        }
 
         Ok(())
     }
 
-    fn handle_control(&self, control: &ControlPacketInfo) -> Result<()> {
+    /// Send in-band `KMREQ`/`KMRSP`
+    fn send_key_material(&self, subtype: u16, key_material: &KeyMaterialExtension) -> Result<()> {
+        let other = PacketContent::Control(ControlPacketInfo::Other {
+            subtype,
+            content: key_material.raw_message(),
+        });
+        tracing::trace!("srt | outbound | control | {other:?}");
+        self.send(other)
+    }
+
+    /// In-band `KMREQ` (keys refreshed by the peer) or `KMRSP` (refresh confirmed)
+    fn handle_key_material(&self, subtype: u16, content: &[u8]) -> Result<()> {
+        let Some(crypto) = &self.crypto else {
+            tracing::warn!("Ignoring key material without a passphrase");
+            return Ok(());
+        };
+        let key_material = match KeyMaterialExtension::from_raw_message(subtype, content) {
+            Ok(key_material) => key_material,
+            Err(e) => {
+                tracing::warn!("Invalid key material: {e}");
+                return Ok(());
+            }
+        };
+
+        if subtype == other_subtypes::KMRSP {
+            lock(crypto)?.confirm(&key_material);
+            return Ok(());
+        }
+
+        if let Err(e) = lock(crypto)?.update(&key_material) {
+            tracing::warn!("Rejected key material: {e}");
+            return Ok(());
+        }
+        self.send_key_material(other_subtypes::KMRSP, &key_material)
+    }
+
+    /// Send `payload` as a single data packet, paced by the congestion control
+    ///
+    /// Fails while the congestion window is full of unacknowledged packets.
+    pub fn send_data(&self, payload: &[u8]) -> Result<()> {
+        let max_payload_size = MAX_PAYLOAD_SIZE - self.encryption_overhead()?;
+        if payload.len() > max_payload_size {
+            bail!("Payload exceeds {max_payload_size} bytes");
+        }
+
+        self.check_window(1)?;
+        self.send_part(payload, PacketPosition::Single, false, None)
+    }
+
+    /// Send `message` split into as many data packets as it takes
+    ///
+    /// The message is given up on if not acknowledged within `ttl`. Unless `in_order`,
+    /// a receiver without TSBPD may deliver it ahead of earlier incomplete messages.
+    /// In stream mode ([`Options::message_api`] off) the bytes just extend the stream.
+    ///
+    /// Fails if the congestion window has no room for all of its packets.
+    pub fn send_message(
+        &self,
+        message: &[u8],
+        ttl: Option<Duration>,
+        in_order: bool,
+    ) -> Result<()> {
+        let max_payload_size = MAX_PAYLOAD_SIZE - self.encryption_overhead()?;
+        let expiry = match ttl {
+            Some(ttl) => Some(
+                self.timestamp()?
+                    .wrapping_add(ttl.as_micros().try_into().context("TTL too long")?),
+            ),
+            None => None,
+        };
+
+        let parts: Vec<_> = message.chunks(max_payload_size).collect();
+        self.check_window(parts.len())?;
+        for (i, part) in parts.iter().enumerate() {
+            let position = if self.message_api {
+                PacketPosition::of(i, parts.len())
+            } else {
+                PacketPosition::Single
+            };
+            self.send_part(part, position, in_order, expiry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fail unless the congestion window has room for `count` more packets
+    fn check_window(&self, count: usize) -> Result<()> {
+        let in_flight = lock(&self.send_buffer)?.len();
+        if in_flight + count > lock(&self.congestion)?.window() as usize {
+            bail!("Congestion window full");
+        }
+
+        Ok(())
+    }
+
+    /// Send a data packet carrying `content` at `position` of the current message
+    fn send_part(
+        &self,
+        content: &[u8],
+        position: PacketPosition,
+        in_order: bool,
+        expiry: Option<u32>,
+    ) -> Result<()> {
+        let period = lock(&self.congestion)?.send_period();
+        let wait = lock(&self.pacer)?.reserve(Instant::now(), period);
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+
+        let timestamp = self.timestamp()?;
+        let (data, evicted) = lock(&self.send_buffer)?.push_part(
+            timestamp,
+            Vec::from(content),
+            position,
+            in_order,
+            expiry,
+        );
+        lock(&self.stats)?.sent(content.len());
+        lock(&self.congestion)?.on_send(Instant::now(), content.len());
+
+        if let Some(drop_req) = evicted {
+            tracing::warn!(
+                "Send buffer is full, dropped packet {}",
+                drop_req.first_packet_sequence_number
+            );
+            lock(&self.stats)?.send_dropped(&drop_req);
+            self.send_drop_req(drop_req)?;
+        }
+        self.send_data_packet(timestamp, data)
+    }
+
+    /// Number of received packets discarded because they could not be decrypted
+    /// (including AES-GCM packets that failed authentication)
+    pub fn undecrypted_packets(&self) -> u32 {
+        self.undecrypted.load(Ordering::Relaxed)
+    }
+
+    /// (bytes) Added to each payload by encryption (AES-GCM authentication tag)
+    fn encryption_overhead(&self) -> Result<usize> {
+        Ok(match &self.crypto {
+            Some(crypto) => lock(crypto)?.overhead(),
+            None => 0,
+        })
+    }
+
+    /// Number of received packets skipped as too late or dropped by the sender
+    pub fn dropped_packets(&self) -> Result<u32> {
+        Ok(lock(&self.receive_buffer)?.dropped())
+    }
+
+    /// Snapshot of the connection's statistics (`clear` starts a new interval)
+    pub fn stats(&self, clear: bool) -> Result<Stats> {
+        let now = Instant::now();
+        let (receive_buffer_packets, dropped) = {
+            let receive_buffer = lock(&self.receive_buffer)?;
+            (receive_buffer.len(), receive_buffer.dropped())
+        };
+
+        let (total, (interval, interval_duration)) = {
+            let mut recorder = lock(&self.stats)?;
+            let total = Counters {
+                packets_dropped: dropped.into(),
+                packets_undecrypted: self.undecrypted_packets().into(),
+                ..recorder.counters
+            };
+            (total, recorder.interval(&total, now, clear))
+        };
+
+        Ok(Stats {
+            total,
+            interval,
+            elapsed: self.established.elapsed()?,
+            interval_duration,
+            rtt: Duration::from_micros(self.rtt.load(Ordering::Relaxed).into()),
+            rtt_var: Duration::from_micros(self.rtt_var.load(Ordering::Relaxed).into()),
+            receive_rate: stats::mbps(interval.bytes_received, interval_duration),
+            bandwidth: stats::capacity_mbps(self.peer_link_capacity.load(Ordering::Relaxed)),
+            send_buffer_packets: lock(&self.send_buffer)?.len(),
+            receive_buffer_packets,
+            latency: self.latency,
+        })
+    }
+
+    fn send_drop_req(&self, drop_req: DropReq) -> Result<()> {
+        let drop_req = PacketContent::Control(ControlPacketInfo::DropReq(drop_req));
+        tracing::trace!("srt | outbound | control | {drop_req:?}");
+        self.send(drop_req)
+    }
+
+    /// Returns whether the packet proved to be the peer's (see [`Self::handle`])
+    fn handle_control(&self, control: &ControlPacketInfo) -> Result<bool> {
         tracing::trace!("srt | inbound | control | {control:?}");
 
+        let mut from_peer = false;
         match control {
-            ControlPacketInfo::KeepAlive => {
-                let keep_alive = PacketContent::Control(ControlPacketInfo::KeepAlive);
-                tracing::trace!("srt | outbound | control | {keep_alive:?}");
-                self.send(keep_alive)?;
-            }
-            ControlPacketInfo::AckAck(_) => {
+            // Its arrival alone keeps the connection alive
+            ControlPacketInfo::KeepAlive => (),
+            ControlPacketInfo::AckAck(ack_ack) => {
                 // Calculate RTT
                 // RTT = 7/8 * RTT + 1/8 * rtt
                 // RTTVar = 3/4 * RTTVar + 1/4 * abs(RTT - rtt)
 
-                let sent = self.last_ack_timestamp.lock().unwrap();
-                let rtt_new: u32 = sent.elapsed().as_micros().try_into().unwrap();
+                let sample =
+                    lock(&self.ack_history)?.acknowledge(ack_ack.ack_number, Instant::now());
+                let Some(rtt_new) = sample else {
+                    tracing::debug!("ACKACK {} matches no pending ACK", ack_ack.ack_number);
+                    return Ok(false);
+                };
+                from_peer = true;
 
                 #[allow(
                     clippy::unwrap_used,
@@ -220,25 +775,110 @@ impl<'c> CallbackConnection<'c> {
                     })
                     .unwrap();
             }
-            _ => (),
-        }
+            ControlPacketInfo::Ack(ack) => {
+                from_peer =
+                    lock(&self.send_buffer)?.acknowledge(ack.last_ackd_packet_sequence_number());
 
-        Ok(())
-    }
+                if let Ack::Full {
+                    ack_number,
+                    last_ackd_packet_sequence_number,
+                    rtt,
+                    packets_receiving_rate,
+                    estimated_link_capacity,
+                    ..
+                } = ack
+                {
+                    self.peer_link_capacity
+                        .store(*estimated_link_capacity, Ordering::Relaxed);
+                    lock(&self.congestion)?.on_ack(
+                        Instant::now(),
+                        &AckInfo {
+                            sequence_number: *last_ackd_packet_sequence_number,
+                            rtt: *rtt,
+                            receive_rate: *packets_receiving_rate,
+                            link_capacity: *estimated_link_capacity,
+                        },
+                    );
+
+                    let ack_ack = PacketContent::Control(ControlPacketInfo::AckAck(AckAck {
+                        ack_number: *ack_number,
+                    }));
+                    tracing::trace!("srt | outbound | control | {ack_ack:?}");
+                    self.send(ack_ack)?;
+                }
+            }
+            ControlPacketInfo::Nak(nak) => {
+                let (released, lost) = {
+                    let send_buffer = lock(&self.send_buffer)?;
+                    let ranges = nak.ranges();
+                    lock(&self.congestion)?.on_loss(&ranges, send_buffer.next_sequence_number());
+                    (
+                        ranges
+                            .iter()
+                            .filter_map(|(from, to)| send_buffer.released(*from, *to))
+                            .collect::<Vec<_>>(),
+                        ranges
+                            .into_iter()
+                            .flat_map(|(from, to)| send_buffer.retransmit(from, to))
+                            .collect::<Vec<_>>(),
+                    )
+                };
 
-    fn handle_data(&self, data: &DataPacketInfo) -> Result<()> {
-        let packet_number = data.packet_sequence_number;
-        let prev_packet_number = self.last_received.swap(packet_number, Ordering::Relaxed);
-        if prev_packet_number + 1 != packet_number && data.message_number != 1 {
-            tracing::warn!("Missed {} packets", packet_number - prev_packet_number - 1);
+                // Message numbers of packets no longer stored are unknown
+                for (from, to) in released {
+                    self.send_drop_req(DropReq::new(0, from, to))?;
+                }
 
-            self.send(PacketContent::Control(ControlPacketInfo::Nak(
-                Nak::Single {
-                    lost_packet: packet_number - 1,
-                },
-            )))?;
+                for (timestamp, data) in lost {
+                    tracing::trace!(
+                        "srt | outbound | data | retransmit {}",
+                        data.packet_sequence_number
+                    );
+                    lock(&self.stats)?.retransmitted(data.content.len());
+                    self.send_data_packet(timestamp, data)?;
+                }
+            }
+            ControlPacketInfo::Other {
+                subtype: subtype @ (other_subtypes::KMREQ | other_subtypes::KMRSP),
+                content,
+            } => self.handle_key_material(*subtype, content)?,
+            ControlPacketInfo::DropReq(drop_req) => {
+                let dropped = lock(&self.receive_buffer)?.drop_range(
+                    drop_req.first_packet_sequence_number,
+                    drop_req.last_packet_sequence_number,
+                );
+                tracing::debug!("Peer dropped message {}", drop_req.message_number);
+                tracing::trace!("Skipping {dropped} missing packets");
+
+                self.deliver()?;
+            }
+            ControlPacketInfo::Other {
+                subtype: other_subtypes::HSREQ,
+                ..
+            } => {
+                if let Some(response) = &self.handshake_response {
+                    tracing::debug!("Repeating HSRSP");
+                    self.send(PacketContent::Control(response.clone()))?;
+                }
+            }
+            ControlPacketInfo::Handshake(handshake)
+                if handshake.handshake_type == HandshakeType::Conclusion =>
+            {
+                if let Some(response) = &self.conclusion_response {
+                    tracing::debug!("Repeating Conclusion response");
+                    let response =
+                        PacketContent::Control(ControlPacketInfo::Handshake(response.clone()));
+                    self.send(response)?;
+                }
+            }
+            _ => (),
         }
 
+        Ok(from_peer)
+    }
+
+    /// Returns whether the packet proved to be the peer's (see [`Self::handle`])
+    fn handle_data(&self, timestamp: u32, data: &DataPacketInfo) -> Result<bool> {
         tracing::trace!(
             "srt | inbound | data | Data {{ packet_sequence_number: {:?}, position: {:?}, order: {:?}, encryption: {:?}, retransmitted: {:?}, message_number: {:?}, length: {:?} }}",
             data.packet_sequence_number,
@@ -250,60 +890,292 @@ impl<'c> CallbackConnection<'c> {
             data.content.len()
         );
 
-        self.send_full_ack()?;
+        let mut parity = false;
+        let mut stored = false;
+        let mut rebuilt = Vec::new();
+        if let Some(fec) = &self.fec {
+            rebuilt = lock(fec)?.received(timestamp, data);
+            parity = data.message_number == fec::PARITY_MESSAGE_NUMBER;
+        }
 
-        // if self.check_ack() {
-        //     let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Light {
-        //         last_ackd_packet_sequence_number: data.packet_sequence_number + 1,
-        //     }));
-        //     tracing::trace!("srt | outbound | control | {ack:?}");
-        //     self.send(ack)?;
-        // }
+        if parity {
+            lock(&self.stats)?.parity_received();
+        } else {
+            lock(&self.time_window)?.arrival(Instant::now(), data);
+            stored = self.store(timestamp, data, false)?;
+        }
+        for (timestamp, data) in rebuilt {
+            tracing::trace!("Rebuilt packet {}", data.packet_sequence_number);
+            self.store(timestamp, &data, true)?;
+        }
 
-        let mpeg_packet = &data.content[..];
+        if self.check_ack() {
+            self.send_light_ack()?;
+        }
 
-        if let Some(callback) = &self.on_data {
-            callback(self, mpeg_packet);
+        self.deliver()?;
+        Ok(stored)
+    }
+
+    /// Decrypt a received (or `rebuilt`) data packet into the receive buffer
+    ///
+    /// Returns whether it was stored (new and within the receive window)
+    fn store(&self, timestamp: u32, data: &DataPacketInfo, rebuilt: bool) -> Result<bool> {
+        let Some(data) = self.decrypt(data)? else {
+            return Ok(false);
+        };
+        let data = &data;
+
+        let arrival = lock(&self.receive_buffer)?.push(timestamp, data.clone());
+        if rebuilt {
+            lock(&self.stats)?.rebuilt();
+        } else {
+            lock(&self.stats)?.received(data, &arrival);
+        }
+        match arrival {
+            Arrival::Stored {
+                lost: Some((from, to)),
+                ..
+            } => {
+                tracing::warn!("Missed {} packets", sequence::offset(from, to) + 1);
+
+                // Otherwise FEC gets a chance to rebuild them first
+                if self.arq == Arq::Always {
+                    let nak = PacketContent::Control(ControlPacketInfo::Nak(Nak::new(from, to)));
+                    tracing::trace!("srt | outbound | control | {nak:?}");
+                    self.send(nak)?;
+                }
+            }
+            Arrival::Stored { .. } => (),
+            Arrival::Duplicate | Arrival::Belated => {
+                tracing::trace!(
+                    "Discarded packet {}: {arrival:?}",
+                    data.packet_sequence_number
+                );
+            }
+            Arrival::Overflow => {
+                tracing::warn!(
+                    "Receive buffer is full, dropped packet {}",
+                    data.packet_sequence_number
+                );
+            }
+        }
+
+        Ok(matches!(arrival, Arrival::Stored { .. }))
+    }
+
+    /// Plain copy of `data` (`None` if it can't be decrypted)
+    fn decrypt(&self, data: &DataPacketInfo) -> Result<Option<DataPacketInfo>> {
+        let mut data = data.clone();
+
+        let decrypted = match &self.crypto {
+            Some(crypto) => lock(crypto)?.decrypt(&mut data),
+            None if data.encryption != EncryptionFlag::NoEncryption => {
+                Err(anyhow!("No passphrase set"))
+            }
+            None => Ok(()),
+        };
+        if let Err(e) = decrypted {
+            tracing::warn!("Discarded packet {}: {e}", data.packet_sequence_number);
+            self.undecrypted.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Pass due messages to the `on_data` callback
+    /// (buffer is not locked while the callback runs)
+    fn deliver(&self) -> Result<()> {
+        loop {
+            let Some((_, message)) = lock(&self.receive_buffer)?.pop_message(Instant::now()) else {
+                break;
+            };
+
+            if let Some(callback) = &self.on_data {
+                callback(self, &message);
+            }
         }
 
         Ok(())
     }
 
-    pub(crate) fn handle(&self, pack: &Packet) -> Result<()> {
+    /// Time at which [`Self::update`] has work to do (or the peer times out)
+    pub(crate) fn next_update(&self) -> Result<Instant> {
+        let ack =
+            *lock(&self.last_ack_timestamp)? + Duration::from_micros(FULL_ACK_INTERVAL.into());
+        let keep_alive = *lock(&self.last_sent)? + Duration::from_micros(KEEPALIVE_INTERVAL.into());
+        let idle = *lock(&self.last_received)? + self.peer_idle_timeout;
+        let next = ack.min(keep_alive).min(idle);
+
+        Ok(match lock(&self.receive_buffer)?.next_delivery() {
+            Some(delivery) => delivery.min(next),
+            None => next,
+        })
+    }
+
+    /// Whether nothing has arrived from the peer within the peer idle timeout
+    pub(crate) fn idle(&self) -> Result<bool> {
+        Ok(lock(&self.last_received)?.elapsed() >= self.peer_idle_timeout)
+    }
+
+    /// Tell the peer this side closed the connection (`reason`), in case it is still listening
+    pub(crate) fn expire(&self, reason: CloseReason) {
+        tracing::warn!(addr = ?self.addr, "{reason}");
+
+        if let Err(e) = self.send(PacketContent::Control(ControlPacketInfo::Shutdown)) {
+            tracing::debug!("Failed to send shutdown: {e}");
+        }
+    }
+
+    /// Handle a packet of the peer's, received `from` some address
+    ///
+    /// Returns whether it proved to be the peer's (stored data, an ACK of sent packets
+    /// or an ACKACK of a pending ACK, never a handshake), for the listener to follow
+    /// the peer to the address it came from. Only those, and keepalives from the peer's
+    /// current address, show that the peer is still there.
+    pub(crate) fn handle(&self, from: SocketAddr, pack: &Packet) -> Result<bool> {
         self.update()?;
 
-        match &pack.content {
+        let from_peer = match &pack.content {
             PacketContent::Control(control) => self.handle_control(control)?,
-            PacketContent::Data(data) => self.handle_data(data)?,
+            PacketContent::Data(data) => self.handle_data(pack.timestamp, data)?,
+        };
+        let keep_alive = matches!(
+            pack.content,
+            PacketContent::Control(ControlPacketInfo::KeepAlive)
+        );
+        if from_peer || (keep_alive && from == self.addr) {
+            *lock(&self.last_received)? = Instant::now();
         }
 
-        Ok(())
+        Ok(from_peer)
     }
 
+    /// Full ACK, sent by the ACK timer unless the peer has confirmed all received packets
     fn send_full_ack(&self) -> Result<()> {
+        let (last_ackd_packet_sequence_number, available) = {
+            let receive_buffer = lock(&self.receive_buffer)?;
+            (
+                receive_buffer.ack_sequence_number(),
+                receive_buffer.available(),
+            )
+        };
+        // Peer already knows (its ACKACK confirmed it)
+        if lock(&self.ack_history)?.confirmed() == Some(last_ackd_packet_sequence_number) {
+            return Ok(());
+        }
+        let ((packets_receiving_rate, receiving_rate), estimated_link_capacity) = {
+            let time_window = lock(&self.time_window)?;
+            (time_window.receive_speed(), time_window.link_capacity())
+        };
+
+        let ack_number = self.inc_ack();
         let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Full {
-            ack_number: self.inc_ack(),
-            last_ackd_packet_sequence_number: self.last_received.load(Ordering::Relaxed) + 1,
+            ack_number,
+            last_ackd_packet_sequence_number,
             rtt: self.rtt.load(Ordering::Relaxed),
             rtt_variance: self.rtt_var.load(Ordering::Relaxed),
-            available_buffer_size: 1,
-            packets_receiving_rate: 1,
-            estimated_link_capacity: 1,
-            receiving_rate: 1,
+            available_buffer_size: available.try_into().unwrap_or(u32::MAX),
+            packets_receiving_rate,
+            estimated_link_capacity,
+            receiving_rate,
+        }));
+        tracing::trace!("srt | outbound | control | {ack:?}");
+        self.send(ack)?;
+
+        self.received_since_ack.store(0, Ordering::Relaxed);
+        lock(&self.ack_history)?.push(ack_number, last_ackd_packet_sequence_number, Instant::now());
+
+        Ok(())
+    }
+
+    /// Light ACK, sent every [`LIGHT_ACK_PACKETS`] data packets between full ACKs
+    fn send_light_ack(&self) -> Result<()> {
+        self.received_since_ack.store(0, Ordering::Relaxed);
+
+        let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Light {
+            last_ackd_packet_sequence_number: lock(&self.receive_buffer)?.ack_sequence_number(),
         }));
         tracing::trace!("srt | outbound | control | {ack:?}");
         self.send(ack)
     }
 
+    /// Repeat the whole loss list, if [`ops::nak_interval`] has passed
+    fn send_periodic_nak(&self) -> Result<()> {
+        let mut last_nak_timestamp = lock(&self.last_nak_timestamp)?;
+        let interval = ops::nak_interval(
+            self.rtt.load(Ordering::Relaxed),
+            self.rtt_var.load(Ordering::Relaxed),
+        );
+        if last_nak_timestamp.elapsed() < Duration::from_micros(interval.into()) {
+            return Ok(());
+        }
+        *last_nak_timestamp = Instant::now();
+
+        let receive_buffer = lock(&self.receive_buffer)?;
+        if receive_buffer.loss_list().is_empty() {
+            return Ok(());
+        }
+        let nak = Nak::from_ranges(receive_buffer.loss_list().ranges());
+        drop(receive_buffer);
+
+        let nak = PacketContent::Control(ControlPacketInfo::Nak(nak));
+        tracing::trace!("srt | outbound | control | {nak:?}");
+        self.send(nak)
+    }
+
     pub(crate) fn update(&self) -> Result<()> {
-        let mut last_ack_timestamp = self.last_ack_timestamp.lock().unwrap();
-        let micros: u32 = last_ack_timestamp.elapsed().as_micros().try_into().unwrap();
+        let mut last_ack_timestamp = lock(&self.last_ack_timestamp)?;
+        let micros: u32 = last_ack_timestamp.elapsed().as_micros().try_into()?;
 
         if micros > FULL_ACK_INTERVAL {
             *last_ack_timestamp = Instant::now();
             self.send_full_ack()?;
         }
+        drop(last_ack_timestamp);
 
-        Ok(())
+        let in_flight = lock(&self.send_buffer)?.len();
+        lock(&self.congestion)?.on_timer(Instant::now(), in_flight);
+
+        if lock(&self.last_sent)?.elapsed() >= Duration::from_micros(KEEPALIVE_INTERVAL.into()) {
+            let keep_alive = PacketContent::Control(ControlPacketInfo::KeepAlive);
+            tracing::trace!("srt | outbound | control | {keep_alive:?}");
+            self.send(keep_alive)?;
+        }
+
+        if self.periodic_nak {
+            self.send_periodic_nak()?;
+        }
+
+        // Repeat unconfirmed key announcements
+        if let Some(crypto) = &self.crypto {
+            let announcement = lock(crypto)?.announcement(Instant::now());
+            if let Some(key_material) = announcement {
+                self.send_key_material(other_subtypes::KMREQ, &key_material)?;
+            }
+        }
+
+        let outdated = lock(&self.send_buffer)?.drop_outdated(self.timestamp()?);
+        for drop_req in outdated {
+            tracing::debug!("Message {} outlived its TTL", drop_req.message_number);
+            lock(&self.stats)?.send_dropped(&drop_req);
+            self.send_drop_req(drop_req)?;
+        }
+
+        if let Some(threshold) = self.send_drop_threshold {
+            let expired = lock(&self.send_buffer)?.drop_expired(self.timestamp()?, threshold);
+            if let Some(drop_req) = expired {
+                tracing::warn!(
+                    "Dropped unacknowledged packets {}..={}",
+                    drop_req.first_packet_sequence_number,
+                    drop_req.last_packet_sequence_number
+                );
+                lock(&self.stats)?.send_dropped(&drop_req);
+                self.send_drop_req(drop_req)?;
+            }
+        }
+
+        self.deliver()
     }
 }
@@ -1,21 +1,83 @@
 use std::{
-    collections::{HashMap, hash_map::Entry},
+    collections::HashMap,
+    io::ErrorKind,
     net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::Result;
 
 use super::connection::CallbackConnection;
-use crate::protocol::{
-    constants::MAX_PACKET_SIZE,
-    packet::{Packet, PacketContent, control::ControlPacketInfo},
+use crate::{
+    options::Options,
+    protocol::{
+        constants::{CONNECTION_TIMEOUT, MAX_PACKET_SIZE, TIMER_RESOLUTION},
+        ops::{self, AcceptRequest, CloseReason, LegacyRequest},
+        packet::{
+            Packet,
+            PacketContent,
+            control::{
+                ControlPacketInfo,
+                handshake::{Handshake, HandshakeType, reject_reason::RejectReason},
+            },
+        },
+        syn_cookie::SynCookies,
+    },
 };
 
+type OnAcceptHandler = dyn Fn(&AcceptRequest) -> Result<(), RejectReason>;
 type OnConnectHandler = dyn Fn(&CallbackConnection);
-type OnDiscnnectHandler = dyn Fn(&CallbackConnection);
+type OnDiscnnectHandler = dyn Fn(&CallbackConnection, CloseReason);
 pub type OnDataHandler = dyn Fn(&CallbackConnection, &[u8]);
 
+/// HSv4 caller between its Conclusion and the SRT options it sends once connected
+struct Legacy {
+    addr: SocketAddr,
+    request: Handshake,
+    response: Handshake,
+    /// `Timestamp` and local arrival time of the Conclusion request
+    peer_timestamp: u32,
+    time_base: Instant,
+    established: SystemTime,
+    options: LegacyRequest,
+    /// Packets sent meanwhile, handled once connected
+    early: Vec<Packet>,
+}
+
+/// Receive a packet, or `None` once the socket's read timeout expires
+/// (or if the datagram can't be decoded)
+pub(crate) fn recv(socket: &UdpSocket) -> Result<Option<(SocketAddr, Packet)>> {
+    let mut buf = [0; MAX_PACKET_SIZE];
+
+    let (n, addr) = match socket.recv_from(&mut buf) {
+        Ok(res) => res,
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match Packet::from_raw(&buf[..n]) {
+        Ok(pack) => Ok(Some((addr, pack))),
+        Err(e) => {
+            tracing::warn!(%addr, "Failed to parse packet: {e}");
+            Ok(None)
+        }
+    }
+}
+
+/// Read timeout that wakes the loop up for the earliest connection timer
+pub(crate) fn timeout_until(deadline: Option<Instant>) -> Option<Duration> {
+    deadline.map(|deadline| {
+        deadline
+            .saturating_duration_since(Instant::now())
+            .max(Duration::from_micros(TIMER_RESOLUTION.into()))
+    })
+}
+
 pub struct CallbackListener {
+    options: Options,
+
+    on_accept: Option<Box<OnAcceptHandler>>,
     on_connect: Option<Box<OnConnectHandler>>,
     on_disconnect: Option<Box<OnDiscnnectHandler>>,
     on_data: Option<Box<OnDataHandler>>,
@@ -23,18 +85,29 @@ pub struct CallbackListener {
 
 impl CallbackListener {
     pub fn new() -> Self {
+        Self::with_options(Options::new())
+    }
+
+    pub fn with_options(options: Options) -> Self {
         Self {
+            options,
+            on_accept: None,
             on_connect: None,
             on_disconnect: None,
             on_data: None,
         }
     }
 
+    /// Decides whether to accept a caller, between its Induction and Conclusion
+    pub fn on_accept(&mut self, f: impl Fn(&AcceptRequest) -> Result<(), RejectReason> + 'static) {
+        self.on_accept = Some(Box::new(f));
+    }
+
     pub fn on_connect(&mut self, f: impl Fn(&CallbackConnection) + 'static) {
         self.on_connect = Some(Box::new(f));
     }
 
-    pub fn on_disconnect(&mut self, f: impl Fn(&CallbackConnection) + 'static) {
+    pub fn on_disconnect(&mut self, f: impl Fn(&CallbackConnection, CloseReason) + 'static) {
         self.on_disconnect = Some(Box::new(f));
     }
 
@@ -42,53 +115,392 @@ impl CallbackListener {
         self.on_data = Some(Box::new(f));
     }
 
-    fn recv(&self, socket: &UdpSocket) -> Result<(SocketAddr, Packet)> {
-        let mut buf = [0; MAX_PACKET_SIZE];
-
-        let (n, addr) = socket.recv_from(&mut buf)?;
-        let data = &buf[..n];
-        let pack = Packet::from_raw(data)?;
-
-        Ok((addr, pack))
-    }
-
     pub fn run(&self, addr: impl ToSocketAddrs) -> Result<()> {
         let _span = tracing::info_span!("srt_server").entered();
 
         let socket = UdpSocket::bind(addr)?;
 
-        let mut connections = HashMap::<SocketAddr, CallbackConnection>::new();
+        // By local socket ID (`Destination Socket ID` of their packets)
+        let mut connections = HashMap::<u32, CallbackConnection>::new();
+        // HSv4 callers yet to send their SRT options, by local socket ID
+        let mut legacy = HashMap::<u32, Legacy>::new();
+        let cookies = SynCookies::new();
 
         loop {
-            let (addr, pack) = self.recv(&socket)?;
-            let entry = connections.entry(addr);
+            // (a connection failing to tell fails its update below)
+            let deadline = connections
+                .values()
+                .filter_map(|conn| conn.next_update().ok())
+                .min();
+            socket.set_read_timeout(timeout_until(deadline))?;
+
+            let received = recv(&socket).unwrap_or_else(|e| {
+                tracing::warn!("Failed to receive: {e}");
+                None
+            });
+
+            // Failed connections, and peers that went silent (e.g. lost power) without a shutdown
+            let mut closed = Vec::new();
+            for (&socket_id, conn) in &connections {
+                match conn.update().and_then(|()| conn.idle()) {
+                    Ok(false) => {}
+                    Ok(true) => closed.push((socket_id, CloseReason::PeerIdle)),
+                    Err(e) => {
+                        tracing::error!(socket_id, "Connection failed: {e}");
+                        closed.push((socket_id, CloseReason::Internal));
+                    }
+                }
+            }
+            for (socket_id, reason) in closed {
+                self.close(&mut connections, socket_id, reason);
+            }
+
+            legacy.retain(|_, caller| {
+                caller.time_base.elapsed() < Duration::from_micros(CONNECTION_TIMEOUT.into())
+            });
+
+            let Some((addr, pack)) = received else {
+                continue;
+            };
+
+            // HSv4 connection awaiting the caller's SRT options
+            let socket_id = pack.dest_socket_id;
+            if let Some(caller) = legacy.get_mut(&socket_id) {
+                match &pack.content {
+                    PacketContent::Control(ControlPacketInfo::Handshake(_)) => {
+                        Self::send_legacy_response(&socket, caller);
+                    }
+                    PacketContent::Control(control) if caller.options.collect(control) => {}
+                    _ => caller.early.push(pack),
+                }
 
-            match entry {
-                // New connection
-                Entry::Vacant(vacant_entry) => {
-                    match CallbackConnection::establish_v5(&socket, self.on_data.as_deref()) {
+                let complete = caller.options.is_complete(&self.options);
+                if complete && let Some(caller) = legacy.remove(&socket_id) {
+                    match CallbackConnection::establish_legacy(
+                        &socket,
+                        self.on_data.as_deref(),
+                        &self.options,
+                        caller.addr,
+                        &caller.request,
+                        caller.response,
+                        caller.peer_timestamp,
+                        caller.time_base,
+                        caller.established,
+                        &caller.options,
+                    ) {
                         Ok(conn) => {
-                            tracing::info!(?addr, "New connection");
-                            let conn = vacant_entry.insert(conn);
+                            tracing::info!(addr = ?caller.addr, "New HSv4 connection");
+                            let conn = connections.entry(socket_id).or_insert(conn);
                             self.on_connect.as_ref().inspect(|f| f(conn));
+                            if let Err(e) = caller
+                                .early
+                                .iter()
+                                .try_for_each(|pack| conn.handle(caller.addr, pack).map(drop))
+                            {
+                                self.fail(&mut connections, socket_id, &e);
+                            }
                         }
                         Err(e) => tracing::error!("Failed to establish connection: {e}"),
                     }
                 }
-                // Existing connection
-                Entry::Occupied(occupied_entry) => {
-                    occupied_entry.get().handle(&pack)?;
+                continue;
+            }
+
+            // Existing connection
+            if let Some(conn) = connections.get_mut(&socket_id) {
+                let from_peer = match conn.handle(addr, &pack) {
+                    Ok(from_peer) => from_peer,
+                    Err(e) => {
+                        self.fail(&mut connections, socket_id, &e);
+                        continue;
+                    }
+                };
+
+                // Follow the peer to a new address (e.g. after NAT rebinding),
+                // once a packet proved to be the peer's
+                if from_peer && conn.addr != addr {
+                    tracing::info!(socket_id, from = ?conn.addr, to = ?addr, "Peer address changed");
+                    conn.addr = addr;
+                }
+
+                if matches!(
+                    pack.content,
+                    PacketContent::Control(ControlPacketInfo::Shutdown)
+                ) {
+                    tracing::info!(?addr, "Disconnect");
+                    self.close(&mut connections, socket_id, CloseReason::Peer);
+                }
+                continue;
+            }
+
+            // New connection
+            let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &pack.content
+            else {
+                continue;
+            };
+
+            match handshake.handshake_type {
+                // Answered without keeping any state
+                HandshakeType::Induction => {
+                    let syn_cookie = cookies.generate(addr, Instant::now());
+                    let response = Packet {
+                        timestamp: pack.timestamp.wrapping_add(1),
+                        dest_socket_id: handshake.srt_socket_id,
+                        content: PacketContent::Control(ControlPacketInfo::Handshake(
+                            ops::induction_response(handshake, syn_cookie),
+                        )),
+                    };
+                    Self::send_response(&socket, &response, addr);
+                }
+                HandshakeType::Conclusion => {
+                    if !cookies.verify(addr, handshake.syn_cookie, Instant::now()) {
+                        tracing::warn!(?addr, "Rejected Conclusion with an invalid SYN cookie");
+                        continue;
+                    }
+
+                    // Repeated request of an established caller
+                    if let Some((&socket_id, conn)) = connections.iter().find(|(_, conn)| {
+                        conn.peer_srt_socket_id == handshake.srt_socket_id && conn.addr == addr
+                    }) {
+                        if let Err(e) = conn.handle(addr, &pack) {
+                            self.fail(&mut connections, socket_id, &e);
+                        }
+                        continue;
+                    }
+                    if let Some(caller) = legacy.values().find(|caller| {
+                        caller.request.srt_socket_id == handshake.srt_socket_id
+                            && caller.addr == addr
+                    }) {
+                        Self::send_legacy_response(&socket, caller);
+                        continue;
+                    }
+
+                    let admitted = ops::admit(addr, handshake, &self.options, |request| {
+                        self.on_accept.as_ref().map_or(Ok(()), |f| f(request))
+                    });
+                    if let Err(reason) = admitted {
+                        tracing::info!(?addr, %reason, "Rejected connection");
+                        let response = Packet {
+                            timestamp: pack.timestamp.wrapping_add(1),
+                            dest_socket_id: handshake.srt_socket_id,
+                            content: PacketContent::Control(ControlPacketInfo::Handshake(
+                                ops::rejection(handshake, reason),
+                            )),
+                        };
+                        Self::send_response(&socket, &response, addr);
+                        continue;
+                    }
+
+                    let socket_id = ops::unique_socket_id(|socket_id| {
+                        connections.contains_key(&socket_id) || legacy.contains_key(&socket_id)
+                    });
+
+                    if ops::is_legacy(handshake) {
+                        let caller = Legacy {
+                            addr,
+                            request: handshake.clone(),
+                            response: ops::legacy_conclusion_response(handshake, socket_id),
+                            peer_timestamp: pack.timestamp,
+                            time_base: Instant::now(),
+                            established: SystemTime::now(),
+                            options: LegacyRequest::new(),
+                            early: Vec::new(),
+                        };
+                        Self::send_legacy_response(&socket, &caller);
+                        tracing::debug!(?addr, "Completed HSv4 Conclusion");
+                        legacy.insert(socket_id, caller);
+                        continue;
+                    }
 
-                    if matches!(
-                        pack.content,
-                        PacketContent::Control(ControlPacketInfo::Shutdown)
+                    match CallbackConnection::establish_v5(
+                        &socket,
+                        self.on_data.as_deref(),
+                        &self.options,
+                        addr,
+                        socket_id,
+                        &pack,
                     ) {
-                        tracing::info!(?addr, "Disconnect");
-                        let conn = occupied_entry.remove();
-                        self.on_disconnect.as_ref().inspect(|f| f(&conn));
+                        Ok(conn) => {
+                            tracing::info!(?addr, "New connection");
+                            let conn = connections.entry(socket_id).or_insert(conn);
+                            self.on_connect.as_ref().inspect(|f| f(conn));
+                        }
+                        Err(e) => tracing::error!("Failed to establish connection: {e}"),
                     }
                 }
+                _ => {}
             }
         }
     }
+
+    /// Remove the connection `socket_id` closed for `reason`, telling the peer
+    /// (unless it closed it) and the disconnect handler
+    fn close(
+        &self,
+        connections: &mut HashMap<u32, CallbackConnection>,
+        socket_id: u32,
+        reason: CloseReason,
+    ) {
+        let Some(conn) = connections.remove(&socket_id) else {
+            return;
+        };
+        if reason != CloseReason::Peer {
+            conn.expire(reason);
+        }
+        self.on_disconnect.as_ref().inspect(|f| f(&conn, reason));
+    }
+
+    /// Close the connection `socket_id` on an error of its own, leaving the others be
+    fn fail(
+        &self,
+        connections: &mut HashMap<u32, CallbackConnection>,
+        socket_id: u32,
+        e: &anyhow::Error,
+    ) {
+        tracing::error!(socket_id, "Connection failed: {e}");
+        self.close(connections, socket_id, CloseReason::Internal);
+    }
+
+    /// Send a handshake response to a caller not connected yet
+    /// (a failure is left to the caller's retransmission)
+    fn send_response(socket: &UdpSocket, response: &Packet, addr: SocketAddr) {
+        if let Err(e) = socket.send_to(&response.to_raw(), addr) {
+            tracing::warn!(?addr, "Failed to send handshake response: {e}");
+        }
+    }
+
+    /// (Re)send the Conclusion response to an HSv4 `caller`
+    fn send_legacy_response(socket: &UdpSocket, caller: &Legacy) {
+        let response = Packet {
+            timestamp: 0,
+            dest_socket_id: caller.request.srt_socket_id,
+            content: PacketContent::Control(ControlPacketInfo::Handshake(caller.response.clone())),
+        };
+        Self::send_response(socket, &response, caller.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::Ipv4Addr,
+        sync::mpsc::{self, Receiver, RecvTimeoutError},
+        thread,
+    };
+
+    use super::*;
+    use crate::server::callback::caller::CallbackCaller;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Connected,
+        Data(Vec<u8>),
+        Disconnected(CloseReason),
+    }
+
+    /// Listener at `port`, run by a thread of its own, reporting its connections' events
+    fn spawn_listener(port: u16, options: Options) -> Receiver<Event> {
+        let (events_tx, events) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut listener = CallbackListener::with_options(options);
+            let connected = events_tx.clone();
+            listener.on_connect(move |_| {
+                connected.send(Event::Connected).ok();
+            });
+            let data = events_tx.clone();
+            listener.on_data(move |conn, payload| {
+                data.send(Event::Data(payload.to_vec())).ok();
+                conn.send_data(b"pong").ok();
+            });
+            listener.on_disconnect(move |_, reason| {
+                events_tx.send(Event::Disconnected(reason)).ok();
+            });
+            listener.run((Ipv4Addr::LOCALHOST, port))
+        });
+
+        events
+    }
+
+    #[test]
+    fn test_exchange() -> Result<()> {
+        let events = spawn_listener(19_720, Options::new());
+
+        let (replies_tx, replies) = mpsc::channel();
+        thread::spawn(move || {
+            let mut caller = CallbackCaller::new();
+            caller.on_connect(|conn| {
+                conn.send_data(b"ping").ok();
+            });
+            caller.on_data(move |_, payload| {
+                replies_tx.send(payload.to_vec()).ok();
+            });
+            caller.run((Ipv4Addr::LOCALHOST, 19_720))
+        });
+
+        let timeout = Duration::from_secs(2);
+        assert_eq!(events.recv_timeout(timeout)?, Event::Connected);
+        assert_eq!(events.recv_timeout(timeout)?, Event::Data(b"ping".to_vec()));
+        assert_eq!(replies.recv_timeout(timeout)?, b"pong");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_idle_timeout() -> Result<()> {
+        let options = Options {
+            peer_idle_timeout: Duration::from_millis(300),
+            ..Options::new()
+        };
+        let events = spawn_listener(19_721, options.clone());
+
+        // Connection never updated, so the caller sends nothing (not even keepalives)
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let _conn = CallbackConnection::connect_v5(
+            &socket,
+            (Ipv4Addr::LOCALHOST, 19_721).into(),
+            None,
+            &options,
+        )?;
+
+        let timeout = Duration::from_secs(2);
+        assert_eq!(events.recv_timeout(timeout)?, Event::Connected);
+        assert_eq!(
+            events.recv_timeout(timeout)?,
+            Event::Disconnected(CloseReason::PeerIdle)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keepalive() -> Result<()> {
+        let options = Options {
+            peer_idle_timeout: Duration::from_millis(1_500),
+            ..Options::new()
+        };
+        let events = spawn_listener(19_722, options.clone());
+
+        let (closed_tx, closed) = mpsc::channel();
+        thread::spawn(move || {
+            let mut caller = CallbackCaller::with_options(options);
+            caller.on_disconnect(move |_, reason| {
+                closed_tx.send(reason).ok();
+            });
+            caller.run((Ipv4Addr::LOCALHOST, 19_722))
+        });
+
+        // No data either way, only keepalives show the peers are still there
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(1))?,
+            Event::Connected
+        );
+        assert_eq!(
+            events.recv_timeout(Duration::from_millis(2_500)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert_eq!(closed.try_recv().ok(), None);
+
+        Ok(())
+    }
 }
@@ -1,2 +1,5 @@
+pub mod caller;
 pub mod connection;
+pub mod group;
 pub mod listener;
+pub mod rendezvous;
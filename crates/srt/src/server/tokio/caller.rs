@@ -0,0 +1,117 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket, lookup_host},
+    sync::{
+        mpsc::{Receiver, Sender, channel},
+        watch,
+    },
+};
+
+use super::{connection::AsyncConnection, listener::Stream};
+use crate::{
+    options::Options,
+    protocol::{constants::MAX_PACKET_SIZE, ops, packet::Packet},
+};
+
+/// Initiating side of a connection (counterpart to [`super::listener::AsyncListener`])
+pub struct AsyncCaller {
+    options: Options,
+}
+
+impl AsyncCaller {
+    pub fn new() -> Self {
+        Self::with_options(Options::new())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        Self { options }
+    }
+
+    async fn inbound_loop(
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        inbound_tx: Sender<(SocketAddr, Packet)>,
+    ) -> Result<()> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+
+        loop {
+            let (n, addr) = tokio::select! {
+                res = socket.recv_from(&mut buf) => res?,
+                () = inbound_tx.closed() => return Ok(()),
+            };
+            if addr != peer {
+                continue;
+            }
+
+            match Packet::from_raw(&buf[..n]) {
+                Ok(pack) => {
+                    if inbound_tx.send((addr, pack)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to parse packet: {e}"),
+            }
+        }
+    }
+
+    async fn outbound_loop(
+        socket: Arc<UdpSocket>,
+        mut outbound_rx: Receiver<(SocketAddr, Packet)>,
+    ) -> Result<()> {
+        while let Some((addr, pack)) = outbound_rx.recv().await {
+            socket.send_to(&pack.to_raw(), addr).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Connect to a listener and perform the handshake
+    pub async fn connect(&self, addr: impl ToSocketAddrs) -> Result<AsyncConnection> {
+        let stream = Self::open(addr, self.options.clone()).await?;
+
+        AsyncConnection::connect_v5(stream).await
+    }
+
+    /// Stream to the listener at `addr` over a socket of its own
+    pub(super) async fn open(addr: impl ToSocketAddrs, options: Options) -> Result<Stream> {
+        let addr = lookup_host(addr)
+            .await?
+            .next()
+            .context("Failed to resolve address")?;
+
+        let socket = if addr.is_ipv4() {
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?
+        } else {
+            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await?
+        };
+
+        Ok(Self::stream(socket, addr, options))
+    }
+
+    /// Stream of the packets exchanged with the single peer at `addr` over `socket`
+    pub(super) fn stream(socket: UdpSocket, addr: SocketAddr, options: Options) -> Stream {
+        let socket = Arc::new(socket);
+
+        let (inbound_tx, inbound_rx) = channel(100);
+        let (outbound_tx, outbound_rx) = channel(100);
+
+        // Inbound
+        tokio::spawn(Self::inbound_loop(socket.clone(), addr, inbound_tx));
+
+        // Outbound
+        tokio::spawn(Self::outbound_loop(socket, outbound_rx));
+
+        Stream::new(
+            watch::channel(addr).0,
+            ops::random_socket_id(),
+            options,
+            inbound_rx,
+            outbound_tx,
+        )
+    }
+}
@@ -1,6 +1,13 @@
 use std::{
-    sync::atomic::{AtomicBool, AtomicU32, Ordering},
-    time::{Instant, SystemTime},
+    collections::VecDeque,
+    convert::Infallible,
+    future,
+    sync::{
+        Arc,
+        OnceLock,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Result, anyhow, bail};
@@ -8,89 +15,273 @@ use tokio::sync::Mutex;
 
 use crate::{
     protocol::{
-        constants::{FULL_ACK_INTERVAL, HANDSHAKE_MAGIC_CODE, RTT_INIT, RTT_VAR_INIT},
+        ack_history::AckHistory,
+        congestion::{self, AckInfo, CongestionController, Pacer},
+        constants::{
+            CONNECTION_TIMEOUT,
+            FULL_ACK_INTERVAL,
+            HANDSHAKE_RETRANSMIT_INTERVAL,
+            KEEPALIVE_INTERVAL,
+            LIGHT_ACK_PACKETS,
+            MAX_FLOW_WINDOW_SIZE,
+            MAX_PAYLOAD_SIZE,
+            RTT_INIT,
+            RTT_VAR_INIT,
+        },
+        crypto::Crypto,
+        filter::fec::{self, Arq, Fec},
+        ops::{self, CloseReason, RendezvousRole},
         packet::{
             Packet,
             PacketContent,
-            control::{ControlPacketInfo, ack::Ack, handshake::Handshake, nak::Nak},
-            data::DataPacketInfo,
+            control::{
+                ControlPacketInfo,
+                ack::Ack,
+                ack_ack::AckAck,
+                drop_req::DropReq,
+                handshake::{
+                    Handshake,
+                    HandshakeType,
+                    extension::{
+                        extension_types,
+                        group_membership::GroupMembershipExtension,
+                        key_material::KeyMaterialExtension,
+                    },
+                },
+                nak::Nak,
+                other_subtypes,
+            },
+            data::{DataPacketInfo, EncryptionFlag, PacketPosition},
         },
+        receive_buffer::{Arrival, ReceiveBuffer},
+        send_buffer::SendBuffer,
+        sequence,
+        stats::{self, Counters, Recorder, Stats},
+        stream_id::StreamId,
+        time_window::TimeWindow,
     },
     server::tokio::listener::Stream,
 };
 
 pub struct AsyncConnection {
     // Srt info
-    pub stream_id: Option<String>,
+    pub stream_id: Option<StreamId>,
     pub established: SystemTime,
+    pub srt_socket_id: u32,
     pub peer_srt_socket_id: u32,
+    /// Negotiated TSBPD delay of received packets
+    pub latency: Option<Duration>,
+    /// Peer's group, if the connection is a member of one
+    pub peer_group: Option<GroupMembershipExtension>,
 
     stream: Stream,
-    running: AtomicBool,
+    /// Set once the connection ends
+    closed: OnceLock<CloseReason>,
 
     /// Ack sequence number
     ack_counter: AtomicU32,
+    /// # of data packets received since the last ACK was sent
+    received_since_ack: AtomicU32,
 
-    /// Timestamp of the last **sent** Ack
-    /// (used to calculate RTT)
+    /// Time of the last full ACK timer tick
     last_ack_timestamp: Mutex<Instant>,
-
-    /// Package sequence number of last received data packet
-    last_received: AtomicU32,
+    /// Full ACKs awaiting the peer's ACKACK (used to calculate RTT)
+    ack_history: Mutex<AckHistory>,
 
     /// <add link>
     rtt: AtomicU32,
     /// <add link>
     rtt_var: AtomicU32,
+
+    /// Sent data packets awaiting acknowledgement
+    send_buffer: Mutex<SendBuffer>,
+    /// Keep boundaries of sent messages (otherwise they form a byte stream)
+    message_api: bool,
+    /// Received data packets awaiting in-order delivery
+    receive_buffer: Mutex<ReceiveBuffer>,
+    /// (micros) Age at which unacknowledged packets are given up on
+    send_drop_threshold: Option<u32>,
+    /// Repeat the loss list every [`ops::nak_interval`]
+    periodic_nak: bool,
+    /// Time of the last periodic NAK report
+    last_nak_timestamp: Mutex<Instant>,
+    /// Report losses as soon as they are detected ([`Arq::Always`])
+    arq: Arq,
+    /// Parity packets of the packet filter (`None` without one)
+    fec: Option<Mutex<Fec>>,
+
+    /// Silence from the peer after which the connection is considered dead
+    peer_idle_timeout: Duration,
+    /// Time the last packet arrived from the peer (shared with the connection's group)
+    last_received: Arc<Mutex<Instant>>,
+    /// Time the last packet was sent to the peer (for keepalives)
+    last_sent: Mutex<Instant>,
+
+    /// Payload keys (`None` without a passphrase)
+    crypto: Option<Mutex<Crypto>>,
+    /// # of received packets discarded as undecryptable or forged
+    undecrypted: AtomicU32,
+
+    /// Arrival times for the rates and link capacity reported in full ACKs
+    time_window: Mutex<TimeWindow>,
+    /// Packet counters for [`Self::stats`]
+    stats: Mutex<Recorder>,
+    /// (packets/s) `Estimated Link Capacity` of the peer's last full ACK
+    peer_link_capacity: AtomicU32,
+
+    /// Sending rate and window of data packets
+    congestion: Mutex<Box<dyn CongestionController>>,
+    /// Spaces sent data packets by [`CongestionController::send_period`]
+    pacer: Mutex<Pacer>,
+
+    /// Messages released by the receive buffer, awaiting [`Self::recv_message`]
+    /// (with the sequence numbers of their first packets)
+    received: VecDeque<(u32, Box<[u8]>)>,
+
+    /// This side's answer to the peer's Conclusion request, repeated if the
+    /// request arrives again (the response was lost)
+    conclusion_response: Option<Handshake>,
+    /// `HSRSP` of an HSv4 connection, repeated if the caller's `HSREQ` arrives again
+    handshake_response: Option<ControlPacketInfo>,
 }
 
 impl AsyncConnection {
+    /// Complete the handshake as the listener
+    ///
+    /// The listener has already answered the Induction request and verified the
+    /// SYN cookie of the Conclusion request queued in `stream`. An HSv4 request
+    /// is answered in kind (see [`Self::respond_legacy`]).
     pub async fn establish_v5(mut stream: Stream) -> Result<Self> {
         //
-        // Induction phase
+        // Conclusion phase
         //
 
-        let induction_in = stream.recv().await.context("Failed to receive handshake")?;
-        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = induction_in.content
+        let conclusion_in = stream.recv().await.context("Failed to receive handshake")?;
+        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = conclusion_in.content
         else {
             bail!("Failed to unwrap handshake");
         };
 
-        let induction_out = Packet {
-            timestamp: induction_in.timestamp + 1,
-            dest_socket_id: handshake.srt_socket_id,
-            content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
-                version: 5,
-                extension_field: HANDSHAKE_MAGIC_CODE,
-                srt_socket_id: 42,
-                syn_cookie: 42,
-                ..handshake
-            })),
+        if ops::is_legacy(&handshake) {
+            return Self::respond_legacy(stream, conclusion_in.timestamp, handshake).await;
+        }
+
+        Self::respond(stream, conclusion_in.timestamp, handshake).await
+    }
+
+    /// Answer an HSv4 Conclusion `request`, then await the SRT options the caller
+    /// sends once connected (`HSREQ`, `KMREQ`) and answer those
+    ///
+    /// <https://github.com/Haivision/srt/blob/master/docs/features/handshake.md#hsv4-caller-to-hsv5-listener>
+    async fn respond_legacy(
+        mut stream: Stream,
+        peer_timestamp: u32,
+        request: Handshake,
+    ) -> Result<Self> {
+        let time_base = Instant::now();
+        let established = SystemTime::now();
+
+        let response = ops::legacy_conclusion_response(&request, stream.socket_id());
+        let conclusion_out = || Packet {
+            timestamp: 0,
+            dest_socket_id: request.srt_socket_id,
+            content: PacketContent::Control(ControlPacketInfo::Handshake(response.clone())),
         };
-        stream.send(induction_out).await?;
+        stream.send(conclusion_out()).await?;
 
-        tracing::debug!("Completed Induction");
+        tracing::debug!("Completed HSv4 Conclusion");
 
-        //
-        // Conclusion phase
-        //
+        // Packets the caller sent meanwhile are handled once connected
+        let mut early = Vec::new();
+        let mut legacy = ops::LegacyRequest::new();
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_micros(CONNECTION_TIMEOUT.into());
+        while !legacy.is_complete(stream.options()) {
+            let pack = tokio::time::timeout_at(deadline, stream.recv())
+                .await
+                .context("Timed out awaiting HSREQ")?
+                .context("Failed to receive HSREQ")?;
 
-        let conclusion_in = stream.recv().await.context("Failed to receive handshake")?;
-        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = conclusion_in.content
-        else {
-            bail!("Failed to unwrap handshake");
+            match &pack.content {
+                PacketContent::Control(ControlPacketInfo::Handshake(_)) => {
+                    tracing::debug!("Repeating Conclusion response");
+                    stream.send(conclusion_out()).await?;
+                }
+                PacketContent::Control(control) if legacy.collect(control) => {}
+                _ => early.push(pack),
+            }
+        }
+
+        let (negotiated, crypto, replies) = legacy.respond(
+            &request,
+            stream.socket_id(),
+            peer_timestamp,
+            time_base,
+            stream.options(),
+        )?;
+
+        tracing::debug!("Completed HSv4 Handshake");
+
+        let mut connection = Self::new(stream, established, negotiated, crypto).await;
+        connection.conclusion_response = Some(response);
+        connection.handshake_response = replies.first().cloned();
+        for reply in replies {
+            connection.send(PacketContent::Control(reply)).await?;
+        }
+        for pack in early {
+            connection.handle(&pack).await?;
+        }
+
+        Ok(connection)
+    }
+
+    /// Answer the peer's Conclusion `request` (with `HSREQ`) like a listener
+    async fn respond(stream: Stream, peer_timestamp: u32, request: Handshake) -> Result<Self> {
+        let time_base = Instant::now();
+        let Some(extension) = request.handshake_extension() else {
+            bail!("Missing handshake extension");
+        };
+
+        ops::check_message_api(extension, stream.options())?;
+        let (extension, terms) = ops::handshake_response(extension, stream.options());
+        let congestion = ops::agreed_congestion(&request, stream.options())?;
+        let filter = ops::agreed_filter(&request, stream.options())?;
+        // Listener places group members in its own group of the same type
+        let group = match ops::requested_group(&request)? {
+            Some(_) => Some(stream.group().cloned().context("No group to join")?),
+            None => None,
+        };
+        let (crypto, key_material) =
+            ops::key_material_response(request.key_material_extension(), stream.options())?.unzip();
+
+        let negotiated = ops::Negotiated {
+            srt_socket_id: stream.socket_id(),
+            peer_srt_socket_id: request.srt_socket_id,
+            initial_packet_sequence_number: request.initial_packet_sequence_number,
+            stream_id: ops::requested_stream_id(&request)?,
+            congestion,
+            filter,
+            terms,
+            peer_timestamp,
+            time_base,
         };
 
-        let peer_srt_socket_id = handshake.srt_socket_id;
-        let stream_id = handshake
-            .stream_id_extension
-            .as_ref()
-            .map(|x| x.stream_id.clone());
+        // Timestamps of this side start with the response
+        let established = SystemTime::now();
 
+        let response = ops::conclusion_response(
+            &request,
+            stream.socket_id(),
+            extension,
+            key_material,
+            congestion,
+            negotiated.filter.as_ref(),
+            group,
+        )?;
         let conclusion_out = Packet {
-            timestamp: conclusion_in.timestamp + 1,
-            dest_socket_id: handshake.srt_socket_id,
-            content: PacketContent::Control(ControlPacketInfo::Handshake(handshake)),
+            timestamp: 0,
+            dest_socket_id: request.srt_socket_id,
+            content: PacketContent::Control(ControlPacketInfo::Handshake(response.clone())),
         };
         stream.send(conclusion_out).await?;
 
@@ -102,31 +293,300 @@ impl AsyncConnection {
 
         tracing::debug!("Completed Handshake");
 
+        let mut connection = Self::new(stream, established, negotiated, crypto).await;
+        connection.peer_group = request.group_membership_extension().cloned();
+        connection.conclusion_response = Some(response);
+
+        Ok(connection)
+    }
+
+    /// Perform the handshake as the initiator (caller)
+    pub async fn connect_v5(stream: Stream) -> Result<Self> {
+        Self::connect_from(stream, ops::random_sequence_number()).await
+    }
+
+    /// Perform the handshake as the caller, starting at `initial_sequence_number`
+    /// (a group member joins at the group's current sequence number)
+    pub(crate) async fn connect_from(
+        mut stream: Stream,
+        initial_sequence_number: u32,
+    ) -> Result<Self> {
+        let started = Instant::now();
+
+        //
+        // Induction phase
+        //
+
+        let induction_request = ops::induction_request(
+            stream.socket_id(),
+            initial_sequence_number,
+            stream.addr().ip(),
+        );
+        let (_, induction_response) =
+            Self::exchange(&mut stream, &induction_request, started).await?;
+
+        tracing::debug!("Completed Induction");
+
+        //
+        // Conclusion phase
+        //
+
+        let (crypto, key_material) = ops::key_material_request(stream.options())?.unzip();
+        let conclusion_request = ops::conclusion_request(
+            &induction_response,
+            &induction_request,
+            stream.options(),
+            key_material,
+            stream.group().cloned(),
+        )?;
+        let (peer_timestamp, conclusion_response) =
+            Self::exchange(&mut stream, &conclusion_request, started).await?;
+
+        Self::concluded(
+            stream,
+            &conclusion_request,
+            peer_timestamp,
+            &conclusion_response,
+            crypto,
+        )
+        .await
+    }
+
+    /// Perform the symmetric handshake of a rendezvous with a peer doing the same
+    ///
+    /// The cookie contest of the Wave-A-Hand phase picks the initiator, which then
+    /// proposes the settings like a caller, while the responder answers like a listener.
+    ///
+    /// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.2>
+    pub async fn rendezvous_v5(mut stream: Stream) -> Result<Self> {
+        let started = Instant::now();
+
+        //
+        // Wave-A-Hand phase
+        //
+
+        let wave_hand = ops::wave_hand(
+            stream.socket_id(),
+            ops::random_sequence_number(),
+            stream.addr().ip(),
+        );
+        let (peer_timestamp, peer_handshake) =
+            Self::exchange_until(&mut stream, &wave_hand, started, |_| true).await?;
+        let role = ops::cookie_contest(wave_hand.syn_cookie, peer_handshake.syn_cookie)
+            .context("Cookie contest drawn")?;
+
+        tracing::debug!(?role, "Completed Wave-A-Hand");
+
+        //
+        // Conclusion phase
+        //
+
+        match role {
+            RendezvousRole::Initiator => {
+                let (crypto, key_material) = ops::key_material_request(stream.options())?.unzip();
+                let request = ops::rendezvous_request(&wave_hand, stream.options(), key_material)?;
+                let (peer_timestamp, response) =
+                    Self::exchange_until(&mut stream, &request, started, |handshake| {
+                        ops::is_conclusion_with(handshake, extension_types::HSRSP)
+                    })
+                    .await?;
+
+                #[allow(clippy::cast_possible_truncation)]
+                let agreement = Packet {
+                    timestamp: started.elapsed().as_micros() as u32,
+                    dest_socket_id: response.srt_socket_id,
+                    content: PacketContent::Control(ControlPacketInfo::Handshake(
+                        ops::rendezvous_agreement(&wave_hand),
+                    )),
+                };
+                stream.send(agreement).await?;
+
+                Self::concluded(stream, &request, peer_timestamp, &response, crypto).await
+            }
+            RendezvousRole::Responder => {
+                // Initiator may have skipped straight to its request
+                let (peer_timestamp, request) =
+                    if ops::is_conclusion_with(&peer_handshake, extension_types::HSREQ) {
+                        (peer_timestamp, peer_handshake)
+                    } else {
+                        let attention = ops::rendezvous_attention(&wave_hand);
+                        Self::exchange_until(&mut stream, &attention, started, |handshake| {
+                            ops::is_conclusion_with(handshake, extension_types::HSREQ)
+                        })
+                        .await?
+                    };
+
+                Self::respond(stream, peer_timestamp, request).await
+            }
+        }
+    }
+
+    /// Settle the connection from the peer's Conclusion `response` (with `HSRSP`)
+    /// to this side's `request`, like a caller
+    async fn concluded(
+        stream: Stream,
+        request: &Handshake,
+        peer_timestamp: u32,
+        response: &Handshake,
+        crypto: Option<Crypto>,
+    ) -> Result<Self> {
+        if crypto.is_some() && response.key_material_extension().is_none() {
+            bail!("Peer did not accept key material");
+        }
+        if let Some(extension) = response.handshake_extension() {
+            ops::check_message_api(extension, stream.options())?;
+        }
+        ops::check_response_group(request, response)?;
+        let time_base = Instant::now();
+
+        tracing::debug!("Completed Conclusion");
+
+        //
+        // Done
+        //
+
+        tracing::debug!("Completed Handshake");
+
+        let negotiated = ops::Negotiated {
+            srt_socket_id: stream.socket_id(),
+            peer_srt_socket_id: response.srt_socket_id,
+            initial_packet_sequence_number: request.initial_packet_sequence_number,
+            stream_id: stream.options().stream_id.clone(),
+            congestion: ops::response_congestion(response, stream.options())?,
+            filter: ops::response_filter(response, stream.options())?,
+            terms: response
+                .handshake_extension()
+                .map(|extension| ops::response_terms(extension, stream.options()))
+                .unwrap_or_default(),
+            peer_timestamp,
+            time_base,
+        };
+
         let established = SystemTime::now();
 
-        Ok(Self::new(stream, stream_id, established, peer_srt_socket_id).await)
+        let mut connection = Self::new(stream, established, negotiated, crypto).await;
+        connection.peer_group = response.group_membership_extension().cloned();
+
+        Ok(connection)
+    }
+
+    /// Send a caller's handshake request until a response of the same type arrives
+    ///
+    /// Returns the response with its `Timestamp`
+    async fn exchange(
+        stream: &mut Stream,
+        request: &Handshake,
+        started: Instant,
+    ) -> Result<(u32, Handshake)> {
+        Self::exchange_until(stream, request, started, |response| {
+            response.handshake_type == request.handshake_type
+        })
+        .await
+    }
+
+    /// Send a handshake request until a response that passes `accept` arrives
+    ///
+    /// Returns the response with its `Timestamp` (fails with the `RejectReason` of a refusal)
+    async fn exchange_until(
+        stream: &mut Stream,
+        request: &Handshake,
+        started: Instant,
+        accept: impl Fn(&Handshake) -> bool,
+    ) -> Result<(u32, Handshake)> {
+        loop {
+            if started.elapsed() > Duration::from_micros(CONNECTION_TIMEOUT.into()) {
+                bail!("Connection timed out");
+            }
+
+            #[allow(clippy::cast_possible_truncation)]
+            let out_packet = Packet {
+                timestamp: started.elapsed().as_micros() as u32,
+                dest_socket_id: 0,
+                content: PacketContent::Control(ControlPacketInfo::Handshake(request.clone())),
+            };
+            stream.send(out_packet).await?;
+
+            let deadline = tokio::time::Instant::now()
+                + Duration::from_micros(HANDSHAKE_RETRANSMIT_INTERVAL.into());
+
+            while let Ok(in_packet) = tokio::time::timeout_at(deadline, stream.recv()).await {
+                let in_packet = in_packet.context("Failed to receive handshake")?;
+
+                if let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) =
+                    in_packet.content
+                {
+                    if let HandshakeType::Rejected(reason) = handshake.handshake_type {
+                        return Err(reason.into());
+                    }
+                    if accept(&handshake) {
+                        return Ok((in_packet.timestamp, handshake));
+                    }
+                }
+            }
+        }
     }
 
     async fn new(
         stream: Stream,
-        stream_id: Option<String>,
         established: SystemTime,
-        peer_srt_socket_id: u32,
+        negotiated: ops::Negotiated,
+        crypto: Option<Crypto>,
     ) -> Self {
         Self {
-            stream_id,
+            stream_id: negotiated.stream_id.clone(),
             established,
-            peer_srt_socket_id,
+            srt_socket_id: negotiated.srt_socket_id,
+            peer_srt_socket_id: negotiated.peer_srt_socket_id,
+            latency: negotiated.terms.latency,
+            peer_group: None,
+
+            peer_idle_timeout: stream.options().peer_idle_timeout,
+            message_api: stream.options().message_api,
+            congestion: Mutex::new(congestion::controller(
+                negotiated.congestion,
+                stream.options(),
+                negotiated.initial_packet_sequence_number,
+            )),
+            pacer: Mutex::new(Pacer::new()),
+            last_received: Arc::new(Mutex::new(Instant::now())),
+            last_sent: Mutex::new(Instant::now()),
 
             stream,
-            running: AtomicBool::new(true),
+            closed: OnceLock::new(),
 
             ack_counter: AtomicU32::new(1),
+            received_since_ack: AtomicU32::new(0),
             last_ack_timestamp: Mutex::new(Instant::now()),
-            last_received: AtomicU32::new(0),
+            ack_history: Mutex::new(AckHistory::new()),
 
             rtt: AtomicU32::new(RTT_INIT),
             rtt_var: AtomicU32::new(RTT_VAR_INIT),
+
+            send_buffer: Mutex::new(SendBuffer::new(
+                negotiated.initial_packet_sequence_number,
+                MAX_FLOW_WINDOW_SIZE as usize,
+            )),
+            receive_buffer: Mutex::new(ReceiveBuffer::new(
+                negotiated.initial_packet_sequence_number,
+                MAX_FLOW_WINDOW_SIZE as usize,
+                negotiated.tsbpd(),
+                negotiated.terms.too_late_drop,
+            )),
+            send_drop_threshold: negotiated.send_drop_threshold(),
+            periodic_nak: negotiated.periodic_nak(),
+            last_nak_timestamp: Mutex::new(Instant::now()),
+            arq: negotiated.arq(),
+            fec: negotiated.fec().map(|config| {
+                Mutex::new(Fec::new(config, negotiated.initial_packet_sequence_number))
+            }),
+            crypto: crypto.map(Mutex::new),
+            undecrypted: AtomicU32::new(0),
+            time_window: Mutex::new(TimeWindow::new()),
+            stats: Mutex::new(Recorder::new()),
+            peer_link_capacity: AtomicU32::new(0),
+            received: VecDeque::new(),
+            conclusion_response: None,
+            handshake_response: None,
         }
     }
 
@@ -134,54 +594,388 @@ impl AsyncConnection {
         self.ack_counter.fetch_add(1, Ordering::Relaxed)
     }
 
+    /// Count a received data packet, `true` once a light ACK is due
+    pub(crate) fn check_ack(&self) -> bool {
+        self.received_since_ack.fetch_add(1, Ordering::Relaxed) + 1 >= LIGHT_ACK_PACKETS
+    }
+
+    /// (micros) since the connection was established
     #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn timestamp(&self) -> Result<u32> {
+        Ok(SystemTime::now()
+            .duration_since(self.established)?
+            .as_micros() as u32)
+    }
+
     pub(crate) fn pack(&self, content: PacketContent) -> Result<Packet> {
         Ok(Packet {
-            timestamp: SystemTime::now()
-                .duration_since(self.established)?
-                .as_micros() as u32,
+            timestamp: self.timestamp()?,
             dest_socket_id: self.peer_srt_socket_id,
             content,
         })
     }
 
     pub async fn send(&self, content: PacketContent) -> Result<()> {
-        self.stream.send(self.pack(content)?).await?;
+        self.send_packet(self.pack(content)?).await
+    }
+
+    async fn send_packet(&self, pack: Packet) -> Result<()> {
+        self.stream.send(pack).await?;
+        *self.last_sent.lock().await = Instant::now();
+
+        Ok(())
+    }
+
+    async fn send_data_packet(&self, timestamp: u32, mut data: DataPacketInfo) -> Result<()> {
+        let announcement = match &self.crypto {
+            Some(crypto) => {
+                let mut crypto = crypto.lock().await;
+                crypto.encrypt(&mut data)?;
+                crypto.announcement(Instant::now())
+            }
+            None => None,
+        };
+
+        // Parity covers the packets as they are first sent
+        let parity = match &self.fec {
+            Some(fec) if !data.retransmitted => fec.lock().await.sent(timestamp, &data),
+            _ => Vec::new(),
+        };
+
+        self.send_packet(Packet {
+            timestamp,
+            dest_socket_id: self.peer_srt_socket_id,
+            content: PacketContent::Data(data),
+        })
+        .await?;
+
+        for (timestamp, parity) in parity {
+            self.stats.lock().await.parity_sent();
+            self.send_packet(Packet {
+                timestamp,
+                dest_socket_id: self.peer_srt_socket_id,
+                content: PacketContent::Data(parity),
+            })
+            .await?;
+        }
+
+        if let Some(key_material) = announcement {
+            self.send_key_material(other_subtypes::KMREQ, &key_material)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send in-band `KMREQ`/`KMRSP`
+    async fn send_key_material(
+        &self,
+        subtype: u16,
+        key_material: &KeyMaterialExtension,
+    ) -> Result<()> {
+        let other = PacketContent::Control(ControlPacketInfo::Other {
+            subtype,
+            content: key_material.raw_message(),
+        });
+        tracing::trace!("srt | outbound | control | {other:?}");
+        self.send(other).await
+    }
+
+    /// In-band `KMREQ` (keys refreshed by the peer) or `KMRSP` (refresh confirmed)
+    async fn handle_key_material(&self, subtype: u16, content: &[u8]) -> Result<()> {
+        let Some(crypto) = &self.crypto else {
+            tracing::warn!("Ignoring key material without a passphrase");
+            return Ok(());
+        };
+        let key_material = match KeyMaterialExtension::from_raw_message(subtype, content) {
+            Ok(key_material) => key_material,
+            Err(e) => {
+                tracing::warn!("Invalid key material: {e}");
+                return Ok(());
+            }
+        };
+
+        if subtype == other_subtypes::KMRSP {
+            crypto.lock().await.confirm(&key_material);
+            return Ok(());
+        }
+
+        if let Err(e) = crypto.lock().await.update(&key_material) {
+            tracing::warn!("Rejected key material: {e}");
+            return Ok(());
+        }
+        self.send_key_material(other_subtypes::KMRSP, &key_material)
+            .await
+    }
+
+    /// Send `payload` as a single data packet, paced by the congestion control
+    pub async fn send_data(&mut self, payload: &[u8]) -> Result<()> {
+        if let Some(reason) = self.close_reason() {
+            return Err(reason.into());
+        }
+        let max_payload_size = MAX_PAYLOAD_SIZE - self.encryption_overhead().await;
+        if payload.len() > max_payload_size {
+            bail!("Payload exceeds {max_payload_size} bytes");
+        }
+
+        self.send_part(payload, PacketPosition::Single, false, None)
+            .await
+    }
+
+    /// Send `message` split into as many data packets as it takes
+    ///
+    /// The message is given up on if not acknowledged within `ttl`. Unless `in_order`,
+    /// a receiver without TSBPD may deliver it ahead of earlier incomplete messages.
+    /// In stream mode ([`Options::message_api`] off) the bytes just extend the stream.
+    ///
+    /// [`Options::message_api`]: crate::Options::message_api
+    pub async fn send_message(
+        &mut self,
+        message: &[u8],
+        ttl: Option<Duration>,
+        in_order: bool,
+    ) -> Result<()> {
+        if let Some(reason) = self.close_reason() {
+            return Err(reason.into());
+        }
+        let max_payload_size = MAX_PAYLOAD_SIZE - self.encryption_overhead().await;
+        let expiry = match ttl {
+            Some(ttl) => Some(
+                self.timestamp()?
+                    .wrapping_add(ttl.as_micros().try_into().context("TTL too long")?),
+            ),
+            None => None,
+        };
+
+        let parts: Vec<_> = message.chunks(max_payload_size).collect();
+        for (i, part) in parts.iter().enumerate() {
+            let position = if self.message_api {
+                PacketPosition::of(i, parts.len())
+            } else {
+                PacketPosition::Single
+            };
+            self.send_part(part, position, in_order, expiry).await?;
+        }
 
         Ok(())
     }
 
+    /// Send a data packet carrying `content` at `position` of the current message
+    ///
+    /// Control packets that arrived in the meantime (ACK, NAK) are handled first,
+    /// and more are awaited while the congestion window is full.
+    async fn send_part(
+        &mut self,
+        content: &[u8],
+        position: PacketPosition,
+        in_order: bool,
+        expiry: Option<u32>,
+    ) -> Result<()> {
+        while let Some(pack) = self.stream.try_recv() {
+            self.handle(&pack).await?;
+        }
+        self.update().await?;
+        self.release().await;
+
+        let window = self.congestion.lock().await.window() as usize;
+        self.await_in_flight(window.saturating_sub(1)).await?;
+
+        let period = self.congestion.lock().await.send_period();
+        let wait = self.pacer.lock().await.reserve(Instant::now(), period);
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+
+        let timestamp = self.timestamp()?;
+        let (data, evicted) = self.send_buffer.lock().await.push_part(
+            timestamp,
+            Vec::from(content),
+            position,
+            in_order,
+            expiry,
+        );
+        self.stats.lock().await.sent(content.len());
+        self.congestion
+            .lock()
+            .await
+            .on_send(Instant::now(), content.len());
+
+        if let Some(drop_req) = evicted {
+            tracing::warn!(
+                "Send buffer is full, dropped packet {}",
+                drop_req.first_packet_sequence_number
+            );
+            self.stats.lock().await.send_dropped(&drop_req);
+            self.send_drop_req(drop_req).await?;
+        }
+        self.send_data_packet(timestamp, data).await
+    }
+
+    /// Waits until the peer has acknowledged all sent packets
+    /// (or they were given up on)
+    pub async fn flush(&mut self) -> Result<()> {
+        self.await_in_flight(0).await
+    }
+
+    /// Handle incoming packets until at most `max` sent packets are unacknowledged
+    async fn await_in_flight(&mut self, max: usize) -> Result<()> {
+        while self.send_buffer.lock().await.len() > max {
+            if let Some(reason) = self.close_reason() {
+                return Err(reason.into());
+            }
+
+            let deadline = tokio::time::Instant::from_std(self.next_update().await);
+            tokio::select! {
+                pack = self.stream.recv() => {
+                    let pack = pack.context("Connection packet receive error")?;
+                    self.handle(&pack).await?;
+                }
+                () = tokio::time::sleep_until(deadline) => self.update().await?,
+            }
+            self.release().await;
+        }
+
+        Ok(())
+    }
+
+    /// Full ACK, sent by the ACK timer unless the peer has confirmed all received packets
     async fn send_full_ack(&self) -> Result<()> {
+        let (last_ackd_packet_sequence_number, available) = {
+            let receive_buffer = self.receive_buffer.lock().await;
+            (
+                receive_buffer.ack_sequence_number(),
+                receive_buffer.available(),
+            )
+        };
+        // Peer already knows (its ACKACK confirmed it)
+        if self.ack_history.lock().await.confirmed() == Some(last_ackd_packet_sequence_number) {
+            return Ok(());
+        }
+        let ((packets_receiving_rate, receiving_rate), estimated_link_capacity) = {
+            let time_window = self.time_window.lock().await;
+            (time_window.receive_speed(), time_window.link_capacity())
+        };
+
+        let ack_number = self.inc_ack();
         let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Full {
-            ack_number: self.inc_ack(),
-            last_ackd_packet_sequence_number: self.last_received.load(Ordering::Relaxed) + 1,
+            ack_number,
+            last_ackd_packet_sequence_number,
             rtt: self.rtt.load(Ordering::Relaxed),
             rtt_variance: self.rtt_var.load(Ordering::Relaxed),
-            available_buffer_size: 1,
-            packets_receiving_rate: 1,
-            estimated_link_capacity: 1,
-            receiving_rate: 1,
+            available_buffer_size: available.try_into().unwrap_or(u32::MAX),
+            packets_receiving_rate,
+            estimated_link_capacity,
+            receiving_rate,
+        }));
+        tracing::trace!("srt | outbound | control | {ack:?}");
+        self.send(ack).await?;
+
+        self.received_since_ack.store(0, Ordering::Relaxed);
+        self.ack_history.lock().await.push(
+            ack_number,
+            last_ackd_packet_sequence_number,
+            Instant::now(),
+        );
+
+        Ok(())
+    }
+
+    /// Light ACK, sent every [`LIGHT_ACK_PACKETS`] data packets between full ACKs
+    async fn send_light_ack(&self) -> Result<()> {
+        self.received_since_ack.store(0, Ordering::Relaxed);
+
+        let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Light {
+            last_ackd_packet_sequence_number: self
+                .receive_buffer
+                .lock()
+                .await
+                .ack_sequence_number(),
         }));
         tracing::trace!("srt | outbound | control | {ack:?}");
         self.send(ack).await
     }
 
-    async fn handle_control(&self, control: &ControlPacketInfo) -> Result<()> {
+    /// Number of received packets discarded because they could not be decrypted
+    /// (including AES-GCM packets that failed authentication)
+    pub async fn undecrypted_packets(&self) -> u32 {
+        self.undecrypted.load(Ordering::Relaxed)
+    }
+
+    /// (bytes) Added to each payload by encryption (AES-GCM authentication tag)
+    async fn encryption_overhead(&self) -> usize {
+        match &self.crypto {
+            Some(crypto) => crypto.lock().await.overhead(),
+            None => 0,
+        }
+    }
+
+    /// Number of received packets skipped as too late or dropped by the sender
+    pub async fn dropped_packets(&self) -> u32 {
+        self.receive_buffer.lock().await.dropped()
+    }
+
+    /// Snapshot of the connection's statistics (`clear` starts a new interval)
+    pub async fn stats(&self, clear: bool) -> Result<Stats> {
+        let now = Instant::now();
+        let (receive_buffer_packets, dropped) = {
+            let receive_buffer = self.receive_buffer.lock().await;
+            (receive_buffer.len(), receive_buffer.dropped())
+        };
+
+        let (total, (interval, interval_duration)) = {
+            let mut recorder = self.stats.lock().await;
+            let total = Counters {
+                packets_dropped: dropped.into(),
+                packets_undecrypted: self.undecrypted_packets().await.into(),
+                ..recorder.counters
+            };
+            (total, recorder.interval(&total, now, clear))
+        };
+
+        Ok(Stats {
+            total,
+            interval,
+            elapsed: self.established.elapsed()?,
+            interval_duration,
+            rtt: Duration::from_micros(self.rtt.load(Ordering::Relaxed).into()),
+            rtt_var: Duration::from_micros(self.rtt_var.load(Ordering::Relaxed).into()),
+            receive_rate: stats::mbps(interval.bytes_received, interval_duration),
+            bandwidth: stats::capacity_mbps(self.peer_link_capacity.load(Ordering::Relaxed)),
+            send_buffer_packets: self.send_buffer.lock().await.len(),
+            receive_buffer_packets,
+            latency: self.latency,
+        })
+    }
+
+    async fn send_drop_req(&self, drop_req: DropReq) -> Result<()> {
+        let drop_req = PacketContent::Control(ControlPacketInfo::DropReq(drop_req));
+        tracing::trace!("srt | outbound | control | {drop_req:?}");
+        self.send(drop_req).await
+    }
+
+    /// Returns whether the packet proved to be the peer's (see [`Self::handle`])
+    async fn handle_control(&self, control: &ControlPacketInfo) -> Result<bool> {
         tracing::trace!("srt | inbound | control | {control:?}");
 
+        let mut from_peer = false;
         match control {
-            ControlPacketInfo::KeepAlive => {
-                let keep_alive = PacketContent::Control(ControlPacketInfo::KeepAlive);
-                tracing::trace!("srt | outbound | control | {keep_alive:?}");
-                self.send(keep_alive).await?;
-            }
-            ControlPacketInfo::AckAck(_) => {
+            // Its arrival alone keeps the connection alive
+            ControlPacketInfo::KeepAlive => (),
+            ControlPacketInfo::AckAck(ack_ack) => {
                 // Calculate RTT
                 // RTT = 7/8 * RTT + 1/8 * rtt
                 // RTTVar = 3/4 * RTTVar + 1/4 * abs(RTT - rtt)
 
-                let sent = self.last_ack_timestamp.lock().await;
-                let rtt_new: u32 = sent.elapsed().as_micros().try_into()?;
+                let sample = self
+                    .ack_history
+                    .lock()
+                    .await
+                    .acknowledge(ack_ack.ack_number, Instant::now());
+                let Some(rtt_new) = sample else {
+                    tracing::debug!("ACKACK {} matches no pending ACK", ack_ack.ack_number);
+                    return Ok(false);
+                };
+                from_peer = true;
 
                 #[allow(
                     clippy::unwrap_used,
@@ -204,30 +998,117 @@ impl AsyncConnection {
                     })
                     .unwrap();
             }
+            ControlPacketInfo::Ack(ack) => {
+                from_peer = self
+                    .send_buffer
+                    .lock()
+                    .await
+                    .acknowledge(ack.last_ackd_packet_sequence_number());
+
+                if let Ack::Full {
+                    ack_number,
+                    last_ackd_packet_sequence_number,
+                    rtt,
+                    packets_receiving_rate,
+                    estimated_link_capacity,
+                    ..
+                } = ack
+                {
+                    self.peer_link_capacity
+                        .store(*estimated_link_capacity, Ordering::Relaxed);
+                    self.congestion.lock().await.on_ack(
+                        Instant::now(),
+                        &AckInfo {
+                            sequence_number: *last_ackd_packet_sequence_number,
+                            rtt: *rtt,
+                            receive_rate: *packets_receiving_rate,
+                            link_capacity: *estimated_link_capacity,
+                        },
+                    );
+
+                    let ack_ack = PacketContent::Control(ControlPacketInfo::AckAck(AckAck {
+                        ack_number: *ack_number,
+                    }));
+                    tracing::trace!("srt | outbound | control | {ack_ack:?}");
+                    self.send(ack_ack).await?;
+                }
+            }
+            ControlPacketInfo::Nak(nak) => {
+                let (released, lost) = {
+                    let send_buffer = self.send_buffer.lock().await;
+                    let ranges = nak.ranges();
+                    self.congestion
+                        .lock()
+                        .await
+                        .on_loss(&ranges, send_buffer.next_sequence_number());
+                    (
+                        ranges
+                            .iter()
+                            .filter_map(|(from, to)| send_buffer.released(*from, *to))
+                            .collect::<Vec<_>>(),
+                        ranges
+                            .into_iter()
+                            .flat_map(|(from, to)| send_buffer.retransmit(from, to))
+                            .collect::<Vec<_>>(),
+                    )
+                };
+
+                // Message numbers of packets no longer stored are unknown
+                for (from, to) in released {
+                    self.send_drop_req(DropReq::new(0, from, to)).await?;
+                }
+
+                for (timestamp, data) in lost {
+                    tracing::trace!(
+                        "srt | outbound | data | retransmit {}",
+                        data.packet_sequence_number
+                    );
+                    self.stats.lock().await.retransmitted(data.content.len());
+                    self.send_data_packet(timestamp, data).await?;
+                }
+            }
+            ControlPacketInfo::Other {
+                subtype: subtype @ (other_subtypes::KMREQ | other_subtypes::KMRSP),
+                content,
+            } => self.handle_key_material(*subtype, content).await?,
+            ControlPacketInfo::DropReq(drop_req) => {
+                let dropped = self.receive_buffer.lock().await.drop_range(
+                    drop_req.first_packet_sequence_number,
+                    drop_req.last_packet_sequence_number,
+                );
+                tracing::debug!("Peer dropped message {}", drop_req.message_number);
+                tracing::trace!("Skipping {dropped} missing packets");
+            }
+            ControlPacketInfo::Other {
+                subtype: other_subtypes::HSREQ,
+                ..
+            } => {
+                if let Some(response) = &self.handshake_response {
+                    tracing::debug!("Repeating HSRSP");
+                    self.send(PacketContent::Control(response.clone())).await?;
+                }
+            }
+            ControlPacketInfo::Handshake(handshake)
+                if handshake.handshake_type == HandshakeType::Conclusion =>
+            {
+                if let Some(response) = &self.conclusion_response {
+                    tracing::debug!("Repeating Conclusion response");
+                    let response =
+                        PacketContent::Control(ControlPacketInfo::Handshake(response.clone()));
+                    self.send(response).await?;
+                }
+            }
             ControlPacketInfo::Shutdown => {
-                self.running.store(false, Ordering::Relaxed);
+                self.closed.set(CloseReason::Peer).ok();
             }
             _ => (),
         }
 
-        Ok(())
+        Ok(from_peer)
     }
 
-    async fn handle_data(&self, data_packet: &DataPacketInfo) -> Result<Box<[u8]>> {
-        let packet_number = data_packet.packet_sequence_number;
-        let prev_packet_number = self.last_received.swap(packet_number, Ordering::Relaxed);
-
-        if prev_packet_number + 1 != packet_number && data_packet.message_number != 1 {
-            tracing::warn!("Missed {} packets", packet_number - prev_packet_number - 1);
-
-            self.send(PacketContent::Control(ControlPacketInfo::Nak(
-                Nak::Single {
-                    lost_packet: packet_number - 1,
-                },
-            )))
-            .await?;
-        }
-
+    /// Returns whether the packet proved to be the peer's (see [`Self::handle`])
+    async fn handle_data(&self, timestamp: u32, data_packet: &DataPacketInfo) -> Result<bool> {
         tracing::trace!(
             "srt | inbound | data | Data {{ packet_sequence_number: {:?}, position: {:?}, order: {:?}, encryption: {:?}, retransmitted: {:?}, message_number: {:?}, length: {:?} }}",
             data_packet.packet_sequence_number,
@@ -239,14 +1120,195 @@ impl AsyncConnection {
             data_packet.content.len()
         );
 
-        self.send_full_ack().await?;
+        let mut parity = false;
+        let mut stored = false;
+        let mut rebuilt = Vec::new();
+        if let Some(fec) = &self.fec {
+            rebuilt = fec.lock().await.received(timestamp, data_packet);
+            parity = data_packet.message_number == fec::PARITY_MESSAGE_NUMBER;
+        }
+
+        if parity {
+            self.stats.lock().await.parity_received();
+        } else {
+            self.time_window
+                .lock()
+                .await
+                .arrival(Instant::now(), data_packet);
+            stored = self.store(timestamp, data_packet, false).await?;
+        }
+        for (timestamp, data) in rebuilt {
+            tracing::trace!("Rebuilt packet {}", data.packet_sequence_number);
+            self.store(timestamp, &data, true).await?;
+        }
+
+        if self.check_ack() {
+            self.send_light_ack().await?;
+        }
+
+        Ok(stored)
+    }
+
+    /// Decrypt a received (or `rebuilt`) data packet into the receive buffer
+    ///
+    /// Returns whether it was stored (new and within the receive window)
+    async fn store(&self, timestamp: u32, data: &DataPacketInfo, rebuilt: bool) -> Result<bool> {
+        let Some(data_packet) = self.decrypt(data).await else {
+            return Ok(false);
+        };
+        let data_packet = &data_packet;
+
+        let mut receive_buffer = self.receive_buffer.lock().await;
+
+        let arrival = receive_buffer.push(timestamp, data_packet.clone());
+        if rebuilt {
+            self.stats.lock().await.rebuilt();
+        } else {
+            self.stats.lock().await.received(data_packet, &arrival);
+        }
+        match arrival {
+            Arrival::Stored {
+                lost: Some((from, to)),
+                ..
+            } => {
+                tracing::warn!("Missed {} packets", sequence::offset(from, to) + 1);
+
+                // Otherwise FEC gets a chance to rebuild them first
+                if self.arq == Arq::Always {
+                    let nak = PacketContent::Control(ControlPacketInfo::Nak(Nak::new(from, to)));
+                    tracing::trace!("srt | outbound | control | {nak:?}");
+                    self.send(nak).await?;
+                }
+            }
+            Arrival::Stored { .. } => (),
+            Arrival::Duplicate | Arrival::Belated => {
+                tracing::trace!(
+                    "Discarded packet {}: {arrival:?}",
+                    data_packet.packet_sequence_number
+                );
+            }
+            Arrival::Overflow => {
+                tracing::warn!(
+                    "Receive buffer is full, dropped packet {}",
+                    data_packet.packet_sequence_number
+                );
+            }
+        }
+
+        Ok(matches!(arrival, Arrival::Stored { .. }))
+    }
+
+    /// Plain copy of `data` (`None` if it can't be decrypted)
+    async fn decrypt(&self, data: &DataPacketInfo) -> Option<DataPacketInfo> {
+        let mut data = data.clone();
+
+        let decrypted = match &self.crypto {
+            Some(crypto) => crypto.lock().await.decrypt(&mut data),
+            None if data.encryption != EncryptionFlag::NoEncryption => {
+                Err(anyhow!("No passphrase set"))
+            }
+            None => Ok(()),
+        };
+        if let Err(e) = decrypted {
+            tracing::warn!("Discarded packet {}: {e}", data.packet_sequence_number);
+            self.undecrypted.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        Some(data)
+    }
+
+    /// Handle a packet of the peer's, following the peer to the address it came from
+    /// if the packet proved to be the peer's (stored data, an ACK of sent packets
+    /// or an ACKACK of a pending ACK, never a handshake)
+    ///
+    /// Only those, and keepalives from the peer's current address, show that the peer
+    /// is still there.
+    async fn handle(&self, pack: &Packet) -> Result<()> {
+        let from_peer = match &pack.content {
+            PacketContent::Control(control) => self.handle_control(control).await?,
+            PacketContent::Data(data) => self.handle_data(pack.timestamp, data).await?,
+        };
+        let keep_alive = matches!(
+            pack.content,
+            PacketContent::Control(ControlPacketInfo::KeepAlive)
+        );
+        if from_peer || (keep_alive && self.stream.came_from_peer_addr()) {
+            *self.last_received.lock().await = Instant::now();
+        }
+        if from_peer {
+            self.stream.follow_peer();
+        }
+
+        Ok(())
+    }
 
-        let data = &data_packet.content[..];
+    /// Move due messages from the receive buffer to [`Self::received`]
+    async fn release(&mut self) {
+        let now = Instant::now();
+        let mut receive_buffer = self.receive_buffer.lock().await;
 
-        Ok(Box::from(data))
+        while let Some(message) = receive_buffer.pop_message(now) {
+            let (sequence_number, message) = message;
+            self.received
+                .push_back((sequence_number, message.into_boxed_slice()));
+        }
+    }
+
+    /// Time at which [`Self::update`] or [`Self::release`] have work to do
+    /// (or the peer times out)
+    async fn next_update(&self) -> Instant {
+        let ack =
+            *self.last_ack_timestamp.lock().await + Duration::from_micros(FULL_ACK_INTERVAL.into());
+        let keep_alive =
+            *self.last_sent.lock().await + Duration::from_micros(KEEPALIVE_INTERVAL.into());
+        let idle = *self.last_received.lock().await + self.peer_idle_timeout;
+        let next = ack.min(keep_alive).min(idle);
+
+        match self.receive_buffer.lock().await.next_delivery() {
+            Some(delivery) => delivery.min(next),
+            None => next,
+        }
+    }
+
+    /// Repeat the whole loss list, if [`ops::nak_interval`] has passed
+    async fn send_periodic_nak(&self) -> Result<()> {
+        let mut last_nak_timestamp = self.last_nak_timestamp.lock().await;
+        let interval = ops::nak_interval(
+            self.rtt.load(Ordering::Relaxed),
+            self.rtt_var.load(Ordering::Relaxed),
+        );
+        if last_nak_timestamp.elapsed() < Duration::from_micros(interval.into()) {
+            return Ok(());
+        }
+        *last_nak_timestamp = Instant::now();
+
+        let receive_buffer = self.receive_buffer.lock().await;
+        if receive_buffer.loss_list().is_empty() {
+            return Ok(());
+        }
+        let nak = Nak::from_ranges(receive_buffer.loss_list().ranges());
+        drop(receive_buffer);
+
+        let nak = PacketContent::Control(ControlPacketInfo::Nak(nak));
+        tracing::trace!("srt | outbound | control | {nak:?}");
+        self.send(nak).await
     }
 
     async fn update(&self) -> Result<()> {
+        // Peer went silent (e.g. lost power) without a shutdown
+        if self.close_reason().is_none()
+            && self.last_received.lock().await.elapsed() >= self.peer_idle_timeout
+        {
+            tracing::warn!(addr = ?self.stream.addr(), "Peer idle timeout");
+            self.closed.set(CloseReason::PeerIdle).ok();
+
+            // In case it is still listening (also releases the listener's route)
+            self.send(PacketContent::Control(ControlPacketInfo::Shutdown))
+                .await?;
+            return Ok(());
+        }
+
         let mut last_ack_timestamp = self.last_ack_timestamp.lock().await;
         let micros: u32 = last_ack_timestamp.elapsed().as_micros().try_into()?;
 
@@ -254,38 +1316,150 @@ impl AsyncConnection {
             *last_ack_timestamp = Instant::now();
             self.send_full_ack().await?;
         }
+        drop(last_ack_timestamp);
+
+        let in_flight = self.send_buffer.lock().await.len();
+        self.congestion
+            .lock()
+            .await
+            .on_timer(Instant::now(), in_flight);
+
+        if self.last_sent.lock().await.elapsed() >= Duration::from_micros(KEEPALIVE_INTERVAL.into())
+        {
+            let keep_alive = PacketContent::Control(ControlPacketInfo::KeepAlive);
+            tracing::trace!("srt | outbound | control | {keep_alive:?}");
+            self.send(keep_alive).await?;
+        }
+
+        if self.periodic_nak {
+            self.send_periodic_nak().await?;
+        }
+
+        // Repeat unconfirmed key announcements
+        if let Some(crypto) = &self.crypto {
+            let announcement = crypto.lock().await.announcement(Instant::now());
+            if let Some(key_material) = announcement {
+                self.send_key_material(other_subtypes::KMREQ, &key_material)
+                    .await?;
+            }
+        }
+
+        let outdated = self
+            .send_buffer
+            .lock()
+            .await
+            .drop_outdated(self.timestamp()?);
+        for drop_req in outdated {
+            tracing::debug!("Message {} outlived its TTL", drop_req.message_number);
+            self.stats.lock().await.send_dropped(&drop_req);
+            self.send_drop_req(drop_req).await?;
+        }
+
+        if let Some(threshold) = self.send_drop_threshold {
+            let expired = self
+                .send_buffer
+                .lock()
+                .await
+                .drop_expired(self.timestamp()?, threshold);
+            if let Some(drop_req) = expired {
+                tracing::warn!(
+                    "Dropped unacknowledged packets {}..={}",
+                    drop_req.first_packet_sequence_number,
+                    drop_req.last_packet_sequence_number
+                );
+                self.stats.lock().await.send_dropped(&drop_req);
+                self.send_drop_req(drop_req).await?;
+            }
+        }
 
         Ok(())
     }
 
+    /// Waits until the next payload is due for delivery
+    /// (a whole message, see [`Self::recv_message`])
+    ///
+    /// Fails with the [`CloseReason`] once the connection has ended.
     pub async fn recv_data(&mut self) -> Result<Box<[u8]>> {
-        if !self.running.load(Ordering::Relaxed) {
-            return Err(anyhow!("Shut down"));
+        self.recv_message().await
+    }
+
+    /// Waits until the next message is due for delivery, rebuilt from all its packets
+    ///
+    /// In stream mode it returns the next bytes of the stream instead.
+    /// Fails with the [`CloseReason`] once the connection has ended.
+    pub async fn recv_message(&mut self) -> Result<Box<[u8]>> {
+        match self
+            .recv_message_until(future::pending::<Infallible>())
+            .await?
+        {
+            Ok((_, message)) => Ok(message),
+            Err(never) => match never {},
         }
+    }
 
-        let data = loop {
-            let pack = self
-                .stream
-                .recv()
-                .await
-                .context("Connection packet receive error")?;
+    /// Like [`Self::recv_message`] (with the sequence number of the message's first
+    /// packet), unless `interrupt` completes first
+    pub(crate) async fn recv_message_until<T>(
+        &mut self,
+        interrupt: impl Future<Output = T>,
+    ) -> Result<Result<(u32, Box<[u8]>), T>> {
+        let mut interrupt = std::pin::pin!(interrupt);
 
-            self.update().await?;
+        loop {
+            self.release().await;
 
-            match &pack.content {
-                PacketContent::Control(control) => self.handle_control(control).await?,
-                PacketContent::Data(data) => {
-                    let data = self.handle_data(data).await?;
-                    break data;
+            if let Some(message) = self.received.pop_front() {
+                return Ok(Ok(message));
+            }
+
+            if let Some(reason) = self.close_reason() {
+                return Err(reason.into());
+            }
+
+            let deadline = tokio::time::Instant::from_std(self.next_update().await);
+
+            tokio::select! {
+                pack = self.stream.recv() => {
+                    let pack = pack.context("Connection packet receive error")?;
+                    self.update().await?;
+                    self.handle(&pack).await?;
                 }
+                () = tokio::time::sleep_until(deadline) => self.update().await?,
+                value = &mut interrupt => return Ok(Err(value)),
             }
-        };
+        }
+    }
+
+    /// Time the last packet arrived from the peer, kept up to date
+    pub(crate) fn last_received(&self) -> Arc<Mutex<Instant>> {
+        self.last_received.clone()
+    }
 
-        Ok(data)
+    /// Sequence number of the next data packet sent
+    pub(crate) async fn next_sequence_number(&self) -> u32 {
+        self.send_buffer.lock().await.next_sequence_number()
+    }
+
+    /// Continue sending at `sequence_number`, giving up on unacknowledged packets
+    /// (a group member catching up with the group)
+    pub(crate) async fn skip_to(&self, sequence_number: u32) -> Result<()> {
+        let skipped = self.send_buffer.lock().await.skip_to(sequence_number);
+        if let Some(drop_req) = skipped {
+            tracing::debug!("Skipping to packet {sequence_number}");
+            self.stats.lock().await.send_dropped(&drop_req);
+            self.send_drop_req(drop_req).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Why the connection ended (`None` while it is open)
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.closed.get().copied()
     }
 
     pub async fn shutdown(&self) -> Result<()> {
-        self.running.store(false, Ordering::Relaxed);
+        self.closed.set(CloseReason::Api).ok();
 
         self.send(PacketContent::Control(ControlPacketInfo::Shutdown))
             .await?;
@@ -293,3 +1467,300 @@ impl AsyncConnection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::{net::UdpSocket, time::timeout};
+
+    use super::*;
+    use crate::{
+        options::Options,
+        protocol::packet::control::handshake::reject_reason::{CoreReason, RejectReason},
+        server::tokio::{caller::AsyncCaller, listener::AsyncListener},
+    };
+
+    /// Caller connected to a listener bound at `port`, and the listener's side
+    async fn connect(port: u16, options: Options) -> Result<(AsyncConnection, AsyncConnection)> {
+        let mut incoming =
+            AsyncListener::bind_with_options((Ipv4Addr::LOCALHOST, port), options.clone())
+                .await?
+                .incoming();
+        let caller = AsyncCaller::with_options(options);
+        let (caller, listener) = tokio::join!(caller.connect((Ipv4Addr::LOCALHOST, port)), async {
+            let stream = incoming.poll_next().await.context("Listener closed")?;
+            AsyncConnection::establish_v5(stream).await
+        });
+
+        Ok((caller?, listener?))
+    }
+
+    /// Listener's refusal of a caller connecting to it at `port`
+    async fn rejection(port: u16, listener: Options, caller: Options) -> Result<RejectReason> {
+        let _incoming = AsyncListener::bind_with_options((Ipv4Addr::LOCALHOST, port), listener)
+            .await?
+            .incoming();
+        let caller = AsyncCaller::with_options(caller);
+        let connected = timeout(
+            Duration::from_secs(2),
+            caller.connect((Ipv4Addr::LOCALHOST, port)),
+        )
+        .await?;
+
+        match connected {
+            Ok(_) => bail!("Connected"),
+            Err(e) => e.downcast(),
+        }
+    }
+
+    fn encrypted(passphrase: &str) -> Options {
+        Options {
+            passphrase: Some(passphrase.to_owned()),
+            ..Options::new()
+        }
+    }
+
+    fn data_packet(dest_socket_id: u32, timestamp: u32, sequence_number: u32) -> Packet {
+        Packet {
+            timestamp,
+            dest_socket_id,
+            content: PacketContent::Data(DataPacketInfo {
+                packet_sequence_number: sequence_number,
+                position: PacketPosition::Single,
+                order: false,
+                encryption: EncryptionFlag::NoEncryption,
+                retransmitted: false,
+                message_number: 1,
+                content: b"moved".to_vec(),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exchange() -> Result<()> {
+        let (mut caller, mut listener) = connect(19_710, Options::new()).await?;
+
+        caller.send_message(b"ping", None, true).await?;
+        let ping = timeout(Duration::from_secs(2), listener.recv_message()).await??;
+        assert_eq!(&*ping, b"ping");
+
+        listener.send_message(b"pong", None, true).await?;
+        let pong = timeout(Duration::from_secs(2), caller.recv_message()).await??;
+        assert_eq!(&*pong, b"pong");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encryption_mismatch() -> Result<()> {
+        let unsecure = RejectReason::Core(CoreReason::Unsecure);
+        assert_eq!(
+            rejection(19_714, Options::new(), encrypted("passphrase")).await?,
+            unsecure
+        );
+        assert_eq!(
+            rejection(19_715, encrypted("passphrase"), Options::new()).await?,
+            unsecure
+        );
+        assert_eq!(
+            rejection(
+                19_716,
+                encrypted("passphrase"),
+                encrypted("other passphrase")
+            )
+            .await?,
+            RejectReason::Core(CoreReason::BadSecret)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_require_gcm() -> Result<()> {
+        let listener = Options {
+            require_gcm: true,
+            ..encrypted("passphrase")
+        };
+        assert_eq!(
+            rejection(19_717, listener, encrypted("passphrase")).await?,
+            RejectReason::Core(CoreReason::Crypto)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unread_connection() -> Result<()> {
+        let mut incoming = AsyncListener::bind((Ipv4Addr::LOCALHOST, 19_719))
+            .await?
+            .incoming();
+        let caller = AsyncCaller::new();
+        let (_caller, unread) =
+            tokio::join!(caller.connect((Ipv4Addr::LOCALHOST, 19_719)), async {
+                let stream = incoming.poll_next().await.context("Listener closed")?;
+                AsyncConnection::establish_v5(stream).await
+            });
+
+        // Far more packets than its queue holds, for a connection that never reads them
+        let unread = unread?;
+        let keep_alive = Packet {
+            timestamp: 0,
+            dest_socket_id: unread.srt_socket_id,
+            content: PacketContent::Control(ControlPacketInfo::KeepAlive),
+        };
+        let other = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        for _ in 0..500 {
+            other
+                .send_to(&keep_alive.to_raw(), (Ipv4Addr::LOCALHOST, 19_719))
+                .await?;
+        }
+
+        let caller = AsyncCaller::new();
+        let (caller, listener) = timeout(Duration::from_secs(2), async {
+            tokio::join!(caller.connect((Ipv4Addr::LOCALHOST, 19_719)), async {
+                let stream = incoming.poll_next().await.context("Listener closed")?;
+                AsyncConnection::establish_v5(stream).await
+            })
+        })
+        .await?;
+        caller?;
+        listener?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_address_follows_accepted_packets() -> Result<()> {
+        let (mut caller, mut listener) = connect(19_711, Options::new()).await?;
+        let peer = listener.stream.addr();
+        let socket_id = listener.srt_socket_id;
+
+        // Packets carrying the right `Destination Socket ID`, none of them the peer's
+        let other = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let next = caller.next_sequence_number().await;
+        let spoofed = [
+            Packet {
+                timestamp: 0,
+                dest_socket_id: socket_id,
+                content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
+                    handshake_type: HandshakeType::Conclusion,
+                    ..ops::induction_request(1, 2, Ipv4Addr::LOCALHOST.into())
+                })),
+            },
+            Packet {
+                timestamp: 0,
+                dest_socket_id: socket_id,
+                content: PacketContent::Control(ControlPacketInfo::KeepAlive),
+            },
+            data_packet(socket_id, 0, sequence::add(next, 1_000_000)),
+            Packet {
+                timestamp: 0,
+                dest_socket_id: socket_id,
+                content: PacketContent::Control(ControlPacketInfo::Ack(Ack::Light {
+                    last_ackd_packet_sequence_number: 1_000,
+                })),
+            },
+        ];
+        for pack in spoofed {
+            other
+                .send_to(&pack.to_raw(), (Ipv4Addr::LOCALHOST, 19_711))
+                .await?;
+        }
+
+        let handled = tokio::time::sleep(Duration::from_millis(100));
+        assert!(listener.recv_message_until(handled).await?.is_err());
+        assert_eq!(listener.stream.addr(), peer);
+
+        caller.send_message(b"stay", None, true).await?;
+        assert_eq!(&*listener.recv_message().await?, b"stay");
+
+        // Next data packet of the caller's, arriving from a new address (e.g. NAT rebinding)
+        let pack = data_packet(
+            socket_id,
+            caller.timestamp()?,
+            caller.next_sequence_number().await,
+        );
+        other
+            .send_to(&pack.to_raw(), (Ipv4Addr::LOCALHOST, 19_711))
+            .await?;
+
+        assert_eq!(&*listener.recv_message().await?, b"moved");
+        assert_eq!(listener.stream.addr(), other.local_addr()?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_idle_timeout() -> Result<()> {
+        let options = Options {
+            peer_idle_timeout: Duration::from_millis(300),
+            ..Options::new()
+        };
+        // Caller is never polled again, so it sends nothing (not even keepalives)
+        let (_caller, mut listener) = connect(19_712, options).await?;
+
+        let closed = tokio::time::timeout(Duration::from_secs(2), listener.recv_message()).await?;
+        let reason = closed.err().and_then(|e| e.downcast::<CloseReason>().ok());
+        assert_eq!(reason, Some(CloseReason::PeerIdle));
+        assert_eq!(listener.close_reason(), Some(CloseReason::PeerIdle));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_keepalive() -> Result<()> {
+        let options = Options {
+            peer_idle_timeout: Duration::from_millis(300),
+            ..Options::new()
+        };
+        let (_caller, mut listener) = connect(19_718, options).await?;
+        let keep_alive = Packet {
+            timestamp: 0,
+            dest_socket_id: listener.srt_socket_id,
+            content: PacketContent::Control(ControlPacketInfo::KeepAlive),
+        };
+
+        // Keepalives carrying the right `Destination Socket ID`, but not from the peer
+        let other = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let spoof = async {
+            loop {
+                other
+                    .send_to(&keep_alive.to_raw(), (Ipv4Addr::LOCALHOST, 19_718))
+                    .await?;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+
+        let closed = tokio::select! {
+            closed = timeout(Duration::from_secs(2), listener.recv_message()) => closed?,
+            spoofed = spoof => return spoofed,
+        };
+        let reason = closed.err().and_then(|e| e.downcast::<CloseReason>().ok());
+        assert_eq!(reason, Some(CloseReason::PeerIdle));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keepalive() -> Result<()> {
+        let options = Options {
+            peer_idle_timeout: Duration::from_millis(1_500),
+            ..Options::new()
+        };
+        let (mut caller, mut listener) = connect(19_713, options).await?;
+
+        // No data either way, only keepalives show the peers are still there
+        let quiet = Duration::from_millis(2_500);
+        let (caller_quiet, listener_quiet) = tokio::join!(
+            caller.recv_message_until(tokio::time::sleep(quiet)),
+            listener.recv_message_until(tokio::time::sleep(quiet)),
+        );
+        assert!(caller_quiet?.is_err());
+        assert!(listener_quiet?.is_err());
+        assert_eq!(caller.close_reason(), None);
+        assert_eq!(listener.close_reason(), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow};
+use tokio::{
+    net::ToSocketAddrs,
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+
+use super::{caller::AsyncCaller, connection::AsyncConnection, listener::Stream};
+use crate::{
+    options::Options,
+    protocol::{
+        constants::GROUP_STABILITY_TIMEOUT,
+        group::{self, Backup, GroupType, History, Merger},
+        ops,
+        packet::control::handshake::extension::group_membership::GroupMembershipExtension,
+        sequence,
+    },
+};
+
+/// Message handed to a member, to be sent starting at `sequence_number`
+struct Outgoing {
+    sequence_number: u32,
+    message: Arc<[u8]>,
+    /// Member's next sequence number once sent
+    sent: oneshot::Sender<Result<u32>>,
+}
+
+enum Event {
+    Joined(Box<AsyncConnection>),
+    Message(u32, Box<[u8]>),
+    Closed(u32, anyhow::Error),
+}
+
+/// Member connection, driven by a task of its own
+struct Member {
+    socket_id: u32,
+    outgoing: Sender<Outgoing>,
+    last_received: Arc<Mutex<Instant>>,
+}
+
+/// Socket group: several member connections carrying one stream
+/// (e.g. over redundant network paths)
+///
+/// A broadcast group sends every message over all members, a main/backup group over
+/// the member with the highest weight until it goes quiet. The receiving side passes on
+/// the first copy of each message, whichever member it arrives over.
+///
+/// <https://github.com/Haivision/srt/blob/master/docs/features/socket-groups.md>
+pub struct AsyncGroup {
+    group_type: GroupType,
+    options: Options,
+    /// This side's group ID, type and flags, announced to the peer
+    membership: GroupMembershipExtension,
+    members: Vec<Member>,
+    events_tx: UnboundedSender<Event>,
+    events: UnboundedReceiver<Event>,
+    /// Sequence number the next message starts at, on every member
+    next_sequence_number: Option<u32>,
+    merger: Merger,
+    /// Messages passed by the merger, awaiting [`Self::recv_message`]
+    received: VecDeque<Box<[u8]>>,
+    backup: Backup,
+    history: History,
+}
+
+impl AsyncGroup {
+    /// Group without members yet, joining listeners with [`Self::connect`]
+    pub fn new(group_type: GroupType) -> Self {
+        Self::with_options(group_type, Options::new())
+    }
+
+    pub fn with_options(group_type: GroupType, options: Options) -> Self {
+        let membership = GroupMembershipExtension {
+            group_id: group::random_group_id(),
+            r#type: group_type.code(),
+            flags: 0,
+            weight: 0,
+        };
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let history = History::new(options.latency);
+
+        Self {
+            group_type,
+            options,
+            membership,
+            members: Vec::new(),
+            events_tx,
+            events,
+            next_sequence_number: None,
+            merger: Merger::new(),
+            received: VecDeque::new(),
+            backup: Backup::new(Duration::from_micros(GROUP_STABILITY_TIMEOUT.into())),
+            history,
+        }
+    }
+
+    /// Complete the handshake of the first member of a caller's group as the listener
+    ///
+    /// The group's further members are established as they arrive at the listener.
+    pub async fn establish(mut stream: Stream) -> Result<Self> {
+        let membership = stream
+            .group()
+            .cloned()
+            .context("Stream is not a group member")?;
+        let group_type =
+            GroupType::from_code(membership.r#type).context("Unsupported group type")?;
+        let joins = stream.take_joins();
+
+        let mut group = Self::with_options(group_type, stream.options().clone());
+        group.membership = membership;
+
+        let connection = AsyncConnection::establish_v5(stream).await?;
+        group.add_established(connection).await;
+
+        if let Some(joins) = joins {
+            tokio::spawn(Self::join_loop(joins, group.events_tx.clone()));
+        }
+
+        Ok(group)
+    }
+
+    /// Connect a member to the listener at `addr` and perform the handshake
+    ///
+    /// Members of a main/backup group with a higher `weight` are preferred.
+    pub async fn connect(&mut self, addr: impl ToSocketAddrs, weight: u16) -> Result<()> {
+        let stream = AsyncCaller::open(addr, self.options.clone())
+            .await?
+            .with_group(GroupMembershipExtension {
+                weight,
+                ..self.membership.clone()
+            });
+        let initial_sequence_number = self
+            .next_sequence_number
+            .unwrap_or_else(ops::random_sequence_number);
+
+        let connection = AsyncConnection::connect_from(stream, initial_sequence_number).await?;
+        self.add(connection, weight).await;
+
+        Ok(())
+    }
+
+    /// Number of connected members
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
+    async fn join_loop(mut joins: Receiver<Stream>, events: UnboundedSender<Event>) {
+        loop {
+            let stream = tokio::select! {
+                stream = joins.recv() => stream,
+                () = events.closed() => None,
+            };
+            let Some(stream) = stream else {
+                return;
+            };
+
+            match AsyncConnection::establish_v5(stream).await {
+                Ok(connection) => {
+                    if events.send(Event::Joined(Box::new(connection))).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to establish group member: {e}"),
+            }
+        }
+    }
+
+    /// Add a member established as the listener, which follows the weights
+    /// of the caller's members
+    async fn add_established(&mut self, connection: AsyncConnection) {
+        let weight = connection
+            .peer_group
+            .as_ref()
+            .map_or(0, |peer_group| peer_group.weight);
+        self.add(connection, weight).await;
+    }
+
+    async fn add(&mut self, connection: AsyncConnection, weight: u16) {
+        let socket_id = connection.srt_socket_id;
+        let next = connection.next_sequence_number().await;
+        self.next_sequence_number = Some(match self.next_sequence_number {
+            Some(current) if !sequence::lt(current, next) => current,
+            _ => next,
+        });
+
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(1);
+        self.members.push(Member {
+            socket_id,
+            outgoing: outgoing_tx,
+            last_received: connection.last_received(),
+        });
+        self.backup.add(socket_id, weight);
+
+        tracing::debug!(socket_id, "Group member added");
+        tokio::spawn(Self::member_loop(
+            connection,
+            outgoing_rx,
+            self.events_tx.clone(),
+        ));
+    }
+
+    /// Receive over `connection` until an outgoing message arrives, send it,
+    /// and start over (until the connection or the group closes)
+    async fn member_loop(
+        mut connection: AsyncConnection,
+        mut outgoing: Receiver<Outgoing>,
+        events: UnboundedSender<Event>,
+    ) {
+        let socket_id = connection.srt_socket_id;
+
+        loop {
+            match connection.recv_message_until(outgoing.recv()).await {
+                Ok(Ok((sequence_number, message))) => {
+                    events.send(Event::Message(sequence_number, message)).ok();
+                }
+                Ok(Err(Some(request))) => {
+                    let result = Self::send_aligned(
+                        &mut connection,
+                        request.sequence_number,
+                        &request.message,
+                    )
+                    .await;
+                    let failed = result.as_ref().err().map(ToString::to_string);
+                    request.sent.send(result).ok();
+
+                    if let Some(e) = failed {
+                        events.send(Event::Closed(socket_id, anyhow!(e))).ok();
+                        return;
+                    }
+                }
+                // Group dropped
+                Ok(Err(None)) => {
+                    connection.shutdown().await.ok();
+                    return;
+                }
+                Err(e) => {
+                    events.send(Event::Closed(socket_id, e)).ok();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Send `message` over `connection` starting at `sequence_number`, unless already sent
+    async fn send_aligned(
+        connection: &mut AsyncConnection,
+        sequence_number: u32,
+        message: &[u8],
+    ) -> Result<u32> {
+        let next = connection.next_sequence_number().await;
+        if sequence::lt(next, sequence_number) {
+            connection.skip_to(sequence_number).await?;
+        }
+        if !sequence::lt(sequence_number, next) {
+            connection.send_message(message, None, true).await?;
+        }
+
+        Ok(connection.next_sequence_number().await)
+    }
+
+    async fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Joined(connection) => self.add_established(*connection).await,
+            Event::Message(sequence_number, message) => {
+                if self.merger.accept(sequence_number) {
+                    self.received.push_back(message);
+                }
+            }
+            Event::Closed(socket_id, e) => {
+                tracing::info!(socket_id, "Group member closed: {e}");
+                self.members.retain(|member| member.socket_id != socket_id);
+                self.backup.remove(socket_id);
+                if self.members.is_empty() {
+                    return Err(e.context("All group members closed"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle the events already queued
+    async fn handle_queued(&mut self) -> Result<()> {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send `message` over the group's members
+    ///
+    /// A broadcast group sends it over all of them, a main/backup group over the active
+    /// member (after repeating the recent messages over a member taking over).
+    pub async fn send_message(&mut self, message: &[u8]) -> Result<()> {
+        self.handle_queued().await?;
+        let sequence_number = self.next_sequence_number.context("Group has no members")?;
+        let message: Arc<[u8]> = message.into();
+
+        let sent = match self.group_type {
+            GroupType::Broadcast => {
+                let targets: Vec<_> = (0..self.members.len()).collect();
+                self.send_over(&targets, sequence_number, &message).await
+            }
+            GroupType::MainBackup => {
+                let now = Instant::now();
+                for member in &self.members {
+                    let last_received = *member.last_received.lock().await;
+                    self.backup.heard(member.socket_id, last_received);
+                }
+                let (socket_id, switched) =
+                    self.backup.active(now).context("Group has no members")?;
+                let index = self
+                    .members
+                    .iter()
+                    .position(|member| member.socket_id == socket_id)
+                    .context("Active member missing")?;
+
+                if switched {
+                    tracing::info!(socket_id, "Switching to backup member");
+                    for (sequence_number, message) in self.history.recent(now) {
+                        self.send_over(&[index], sequence_number, &message).await;
+                    }
+                }
+
+                let sent = self.send_over(&[index], sequence_number, &message).await;
+                self.backup.sent(socket_id, now);
+                self.history.push(now, sequence_number, message);
+                sent
+            }
+        };
+
+        self.next_sequence_number = Some(sent.context("Failed to send over any group member")?);
+        self.handle_queued().await
+    }
+
+    /// Send `message` starting at `sequence_number` over the members at `indices`,
+    /// returning the furthest next sequence number (`None` if all failed)
+    async fn send_over(
+        &self,
+        indices: &[usize],
+        sequence_number: u32,
+        message: &Arc<[u8]>,
+    ) -> Option<u32> {
+        let mut replies = Vec::new();
+        for &index in indices {
+            let member = &self.members[index];
+            let (sent_tx, sent_rx) = oneshot::channel();
+            let outgoing = Outgoing {
+                sequence_number,
+                message: message.clone(),
+                sent: sent_tx,
+            };
+            if member.outgoing.send(outgoing).await.is_ok() {
+                replies.push(sent_rx);
+            }
+        }
+
+        let mut next: Option<u32> = None;
+        for reply in replies {
+            if let Ok(Ok(sent)) = reply.await
+                && next.is_none_or(|next| sequence::lt(next, sent))
+            {
+                next = Some(sent);
+            }
+        }
+
+        next
+    }
+
+    /// Receive the next message over any member (the first copy of it)
+    pub async fn recv_message(&mut self) -> Result<Box<[u8]>> {
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(message);
+            }
+
+            let event = self.events.recv().await.context("Group closed")?;
+            self.handle(event).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{
+        protocol::packet::control::handshake::reject_reason::{CoreReason, RejectReason},
+        server::tokio::listener::AsyncListener,
+    };
+
+    #[tokio::test]
+    async fn test_group_type_mismatch() -> Result<()> {
+        let mut incoming = AsyncListener::bind((Ipv4Addr::LOCALHOST, 19_731))
+            .await?
+            .incoming();
+        let listener = tokio::spawn(async move {
+            let stream = incoming.poll_next().await.context("Listener closed")?;
+            AsyncGroup::establish(stream).await
+        });
+
+        let mut broadcast = AsyncGroup::new(GroupType::Broadcast);
+        broadcast.connect((Ipv4Addr::LOCALHOST, 19_731), 0).await?;
+        let _listener = listener.await??;
+
+        // Member of another type claiming the broadcast group's ID
+        let mut main_backup = AsyncGroup::new(GroupType::MainBackup);
+        main_backup.membership.group_id = broadcast.membership.group_id;
+        let joined = main_backup.connect((Ipv4Addr::LOCALHOST, 19_731), 0).await;
+        let reason = joined.err().and_then(|e| e.downcast::<RejectReason>().ok());
+        assert_eq!(reason, Some(RejectReason::Core(CoreReason::Group)));
+        assert_eq!(main_backup.member_count(), 0);
+
+        Ok(())
+    }
+}
@@ -1,114 +1,384 @@
 use std::{
-    collections::{BTreeMap, btree_map::Entry},
+    collections::BTreeMap,
     net::SocketAddr,
-    sync::Arc,
+    sync::{Arc, RwLock},
+    time::Instant,
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use tokio::{
     net::{ToSocketAddrs, UdpSocket},
     sync::{
         Mutex,
-        mpsc::{Receiver, Sender, channel},
+        mpsc::{Receiver, Sender, channel, error::TrySendError},
+        watch,
     },
 };
 
-use crate::protocol::packet::{Packet, PacketContent, control::ControlPacketInfo};
+use crate::{
+    options::Options,
+    protocol::{
+        group,
+        ops::{self, AcceptRequest},
+        packet::{
+            Packet,
+            PacketContent,
+            control::{
+                ControlPacketInfo,
+                handshake::{
+                    HandshakeType,
+                    extension::group_membership::GroupMembershipExtension,
+                    reject_reason::{CoreReason, RejectReason},
+                },
+            },
+        },
+        syn_cookie::SynCookies,
+    },
+};
 
 const MAX_PACKET_SIZE: usize = 1500;
 
+/// Inbound side of an established connection
+struct Route {
+    /// Current peer address, followed by the connection's [`Stream`]
+    addr: watch::Receiver<SocketAddr>,
+    /// Caller's socket ID from its handshake
+    peer_socket_id: u32,
+    /// Packets with the address they came from
+    inbound: Sender<(SocketAddr, Packet)>,
+}
+
+/// Established connections by local socket ID (`Destination Socket ID` of their packets)
+type Routes = BTreeMap<u32, Route>;
+
+/// Local side of a caller's group, taking its further members
+struct Group {
+    membership: GroupMembershipExtension,
+    joins: Sender<Stream>,
+}
+
+/// Local groups by the caller's group ID
+type Groups = BTreeMap<u32, Group>;
+
+type OnAcceptHandler = dyn Fn(&AcceptRequest) -> Result<(), RejectReason> + Send + Sync;
+/// Accept callback, shared with the inbound loop
+type SharedOnAccept = Arc<RwLock<Option<Box<OnAcceptHandler>>>>;
+
 pub struct Stream {
-    addr: SocketAddr,
-    inbound: Receiver<Packet>,
+    /// Follows the peer to a new address (e.g. after NAT rebinding, see [`Self::follow_peer`])
+    addr: watch::Sender<SocketAddr>,
+    /// Where the last received packet came from
+    from: SocketAddr,
+    socket_id: u32,
+    options: Options,
+    inbound: Receiver<(SocketAddr, Packet)>,
     outbound: Sender<(SocketAddr, Packet)>,
+    /// This side's group, if the stream is a group member
+    group: Option<GroupMembershipExtension>,
+    /// Further members of the group, with the group's first stream
+    joins: Option<Receiver<Stream>>,
 }
 
 impl Stream {
+    pub(crate) fn new(
+        addr: watch::Sender<SocketAddr>,
+        socket_id: u32,
+        options: Options,
+        inbound: Receiver<(SocketAddr, Packet)>,
+        outbound: Sender<(SocketAddr, Packet)>,
+    ) -> Self {
+        let from = *addr.borrow();
+
+        Self {
+            addr,
+            from,
+            socket_id,
+            options,
+            inbound,
+            outbound,
+            group: None,
+            joins: None,
+        }
+    }
+
+    /// Makes the stream a member of `group`
+    pub(crate) fn with_group(mut self, group: GroupMembershipExtension) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    /// This side's group, if the stream is a group member
+    pub fn group(&self) -> Option<&GroupMembershipExtension> {
+        self.group.as_ref()
+    }
+
+    /// Further members of the group started by this stream
+    /// (see [`super::group::AsyncGroup::establish`])
+    pub(crate) fn take_joins(&mut self) -> Option<Receiver<Stream>> {
+        self.joins.take()
+    }
+
+    /// Remote address
+    pub fn addr(&self) -> SocketAddr {
+        *self.addr.borrow()
+    }
+
+    /// Local SRT socket ID
+    pub fn socket_id(&self) -> u32 {
+        self.socket_id
+    }
+
+    /// Settings of the listener or caller this stream belongs to
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
     /// Waits until message
     pub async fn recv(&mut self) -> Option<Packet> {
-        self.inbound.recv().await
+        let (from, pack) = self.inbound.recv().await?;
+        self.from = from;
+        Some(pack)
+    }
+
+    /// Takes a message if one is already queued
+    pub fn try_recv(&mut self) -> Option<Packet> {
+        let (from, pack) = self.inbound.try_recv().ok()?;
+        self.from = from;
+        Some(pack)
+    }
+
+    /// Whether the last received packet came from the peer's current address
+    pub(crate) fn came_from_peer_addr(&self) -> bool {
+        self.from == *self.addr.borrow()
+    }
+
+    /// Move to the address the last received packet came from (e.g. after NAT rebinding)
+    ///
+    /// Called once the connection has accepted that packet as the peer's, so a datagram
+    /// merely carrying the right `Destination Socket ID` can't redirect the connection.
+    pub(crate) fn follow_peer(&self) {
+        self.addr.send_if_modified(|peer| {
+            if *peer == self.from {
+                return false;
+            }
+            tracing::info!(socket_id = self.socket_id, from = ?peer, to = ?self.from, "Peer address changed");
+            *peer = self.from;
+            true
+        });
     }
 
     pub async fn send(&self, pack: Packet) -> Result<()> {
-        self.outbound.send((self.addr, pack)).await?;
+        self.outbound.send((self.addr(), pack)).await?;
         Ok(())
     }
 }
 
 pub struct AsyncListener {
     connection_queue: Receiver<Stream>,
+    on_accept: SharedOnAccept,
 }
 
 impl AsyncListener {
+    /// Receive the next packet, skipping datagrams that can't be decoded
     async fn recv(socket: &UdpSocket) -> Result<(SocketAddr, Packet)> {
         let mut buf = [0; MAX_PACKET_SIZE];
 
-        let (n, addr) = socket.recv_from(&mut buf).await?;
-        let data = &buf[..n];
-        let pack = Packet::from_raw(data)?;
-
-        Ok((addr, pack))
+        loop {
+            let (n, addr) = socket.recv_from(&mut buf).await?;
+            match Packet::from_raw(&buf[..n]) {
+                Ok(pack) => return Ok((addr, pack)),
+                Err(e) => tracing::warn!(%addr, "Failed to parse packet: {e}"),
+            }
+        }
     }
 
     async fn inbound_loop(
         socket: Arc<UdpSocket>,
+        options: Options,
         connection_channel: Sender<Stream>,
-        inbound: Arc<Mutex<BTreeMap<SocketAddr, Sender<Packet>>>>,
+        on_accept: SharedOnAccept,
+        inbound: Arc<Mutex<Routes>>,
         outbound_tx: Sender<(SocketAddr, Packet)>,
     ) -> Result<()> {
+        let cookies = SynCookies::new();
+        let mut groups = Groups::new();
+
         loop {
             // let addr = socket.peek_sender();
             let (addr, pack) = Self::recv(&socket).await?;
 
-            let mut inbound_lock = inbound.lock().await;
-            let entry = inbound_lock.entry(addr);
-
-            match entry {
-                // New connection
-                Entry::Vacant(vacant_entry) => {
-                    if !matches!(
-                        pack,
-                        Packet {
-                            content: PacketContent::Control(ControlPacketInfo::Handshake(_)),
-                            ..
-                        }
-                    ) {
+            // Existing connection (which follows the peer to a new address itself)
+            //
+            // Never waits for a connection's queue: one connection not reading its stream
+            // must not hold up the others, nor new handshakes.
+            let socket_id = pack.dest_socket_id;
+            let route_inbound = (inbound.lock().await)
+                .get(&socket_id)
+                .map(|route| route.inbound.clone());
+            if let Some(route_inbound) = route_inbound {
+                let shutdown = matches!(
+                    pack.content,
+                    PacketContent::Control(ControlPacketInfo::Shutdown)
+                );
+                if shutdown {
+                    tracing::info!(?addr, "Disconnect");
+                }
+                let dropped = match route_inbound.try_send((addr, pack)) {
+                    Ok(()) => false,
+                    Err(TrySendError::Full(_)) => {
+                        tracing::warn!(socket_id, "Connection queue is full, dropping packet");
+                        false
+                    }
+                    // Connection may have been dropped without a shutdown
+                    Err(TrySendError::Closed(_)) => true,
+                };
+                if dropped || shutdown {
+                    inbound.lock().await.remove(&socket_id);
+                }
+                continue;
+            }
+
+            // New connection
+            let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &pack.content
+            else {
+                continue;
+            };
+
+            match handshake.handshake_type {
+                // Answered without keeping any state
+                HandshakeType::Induction => {
+                    let syn_cookie = cookies.generate(addr, Instant::now());
+                    let response = Packet {
+                        timestamp: pack.timestamp.wrapping_add(1),
+                        dest_socket_id: handshake.srt_socket_id,
+                        content: PacketContent::Control(ControlPacketInfo::Handshake(
+                            ops::induction_response(handshake, syn_cookie),
+                        )),
+                    };
+                    outbound_tx.send((addr, response)).await?;
+                }
+                HandshakeType::Conclusion => {
+                    if !cookies.verify(addr, handshake.syn_cookie, Instant::now()) {
+                        tracing::warn!(?addr, "Rejected Conclusion with an invalid SYN cookie");
                         continue;
                     }
 
-                    let (inbound_tx, inbound_rx) = channel(100);
+                    // Repeated request of an established caller
+                    let repeated = (inbound.lock().await)
+                        .values()
+                        .find(|route| {
+                            route.peer_socket_id == handshake.srt_socket_id
+                                && *route.addr.borrow() == addr
+                        })
+                        .map(|route| route.inbound.clone());
+                    if let Some(route_inbound) = repeated {
+                        route_inbound.try_send((addr, pack)).ok();
+                        continue;
+                    }
 
-                    inbound_tx.send(pack).await?;
-                    vacant_entry.insert(inbound_tx);
+                    // Groups no longer taking members are forgotten
+                    groups.retain(|_, group| !group.joins.is_closed());
+
+                    // A member joins a group of its own type only
+                    let mismatched = handshake
+                        .group_membership_extension()
+                        .is_some_and(|member| {
+                            groups
+                                .get(&member.group_id)
+                                .is_some_and(|group| group.membership.r#type != member.r#type)
+                        });
+                    let admitted = if mismatched {
+                        Err(RejectReason::Core(CoreReason::Group))
+                    } else {
+                        let on_accept = on_accept
+                            .read()
+                            .map_err(|_| anyhow!("Accept callback poisoned"))?;
+                        ops::admit(addr, handshake, &options, |request| {
+                            on_accept.as_ref().map_or(Ok(()), |f| f(request))
+                        })
+                    };
+                    if let Err(reason) = admitted {
+                        tracing::info!(?addr, %reason, "Rejected connection");
+                        let response = Packet {
+                            timestamp: pack.timestamp.wrapping_add(1),
+                            dest_socket_id: handshake.srt_socket_id,
+                            content: PacketContent::Control(ControlPacketInfo::Handshake(
+                                ops::rejection(handshake, reason),
+                            )),
+                        };
+                        outbound_tx.send((addr, response)).await?;
+                        continue;
+                    }
 
-                    let stream = Stream {
-                        addr,
-                        inbound: inbound_rx,
-                        outbound: outbound_tx.clone(),
+                    let (inbound_tx, inbound_rx) = channel(100);
+                    let (addr_tx, addr_rx) = watch::channel(addr);
+
+                    let peer_socket_id = handshake.srt_socket_id;
+                    let peer_group = handshake.group_membership_extension().cloned();
+                    inbound_tx.send((addr, pack)).await?;
+
+                    let socket_id = {
+                        let mut inbound_lock = inbound.lock().await;
+                        let socket_id = ops::unique_socket_id(|socket_id| {
+                            inbound_lock.contains_key(&socket_id)
+                        });
+                        inbound_lock.insert(
+                            socket_id,
+                            Route {
+                                addr: addr_rx,
+                                peer_socket_id,
+                                inbound: inbound_tx,
+                            },
+                        );
+                        socket_id
                     };
 
-                    connection_channel.send(stream).await?;
-                }
+                    let stream = Stream::new(
+                        addr_tx,
+                        socket_id,
+                        options.clone(),
+                        inbound_rx,
+                        outbound_tx.clone(),
+                    );
 
-                // Existing connection
-                Entry::Occupied(mut occupied_entry) => {
-                    if matches!(
-                        pack.content,
-                        PacketContent::Control(ControlPacketInfo::Shutdown)
-                    ) {
-                        tracing::info!(?addr, "Disconnect");
-                        occupied_entry.remove().send(pack).await?;
-                    } else {
-                        occupied_entry.get_mut().send(pack).await?;
+                    let Some(peer_group) = peer_group else {
+                        connection_channel.send(stream).await?;
+                        continue;
+                    };
+
+                    if let Some(group) = groups.get(&peer_group.group_id) {
+                        let stream = stream.with_group(group.membership.clone());
+                        group.joins.send(stream).await.ok();
+                        continue;
                     }
+
+                    let membership = GroupMembershipExtension {
+                        group_id: group::random_group_id(),
+                        r#type: peer_group.r#type,
+                        flags: 0,
+                        weight: 0,
+                    };
+                    let (joins_tx, joins_rx) = channel(100);
+                    let mut stream = stream.with_group(membership.clone());
+                    stream.joins = Some(joins_rx);
+                    groups.insert(
+                        peer_group.group_id,
+                        Group {
+                            membership,
+                            joins: joins_tx,
+                        },
+                    );
+
+                    connection_channel.send(stream).await?;
                 }
+                _ => {}
             }
         }
     }
 
     async fn outbound_loop(
         socket: Arc<UdpSocket>,
-        inbound: Arc<Mutex<BTreeMap<SocketAddr, Sender<Packet>>>>,
+        inbound: Arc<Mutex<Routes>>,
         mut outbound_rx: Receiver<(SocketAddr, Packet)>,
     ) -> Result<()> {
         while let Some((addr, pack)) = outbound_rx.recv().await {
@@ -119,7 +389,9 @@ impl AsyncListener {
                     ..
                 }
             ) {
-                inbound.lock().await.remove(&addr);
+                inbound.lock().await.retain(|_, route| {
+                    route.peer_socket_id != pack.dest_socket_id || *route.addr.borrow() != addr
+                });
             }
 
             socket.send_to(&pack.to_raw(), addr).await?;
@@ -129,17 +401,24 @@ impl AsyncListener {
     }
 
     pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        Self::bind_with_options(addr, Options::new()).await
+    }
+
+    pub async fn bind_with_options(addr: impl ToSocketAddrs, options: Options) -> Result<Self> {
         let inbound = Arc::new(Mutex::new(BTreeMap::new()));
         let (outbound_tx, outbound_rx) = channel(100);
 
         let socket = Arc::new(UdpSocket::bind(addr).await?);
 
         let connection_channel = channel(100);
+        let on_accept = SharedOnAccept::default();
 
         // Inbound
         tokio::spawn(Self::inbound_loop(
             socket.clone(),
+            options,
             connection_channel.0,
+            on_accept.clone(),
             inbound.clone(),
             outbound_tx.clone(),
         ));
@@ -153,9 +432,22 @@ impl AsyncListener {
 
         Ok(Self {
             connection_queue: connection_channel.1,
+            on_accept,
         })
     }
 
+    /// Decides whether to accept a caller, between its Induction and Conclusion
+    pub fn on_accept(
+        &self,
+        f: impl Fn(&AcceptRequest) -> Result<(), RejectReason> + Send + Sync + 'static,
+    ) -> Result<()> {
+        *self
+            .on_accept
+            .write()
+            .map_err(|_| anyhow!("Accept callback poisoned"))? = Some(Box::new(f));
+        Ok(())
+    }
+
     pub fn incoming(self) -> Incoming {
         Incoming { listener: self }
     }
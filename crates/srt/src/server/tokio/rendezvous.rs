@@ -0,0 +1,41 @@
+use anyhow::{Context, Result};
+use tokio::net::{ToSocketAddrs, UdpSocket, lookup_host};
+
+use super::{caller::AsyncCaller, connection::AsyncConnection};
+use crate::options::Options;
+
+/// Either side of a rendezvous connection, where both peers connect to each other
+/// (no listener needed, e.g. between two firewalled hosts)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.2>
+pub struct AsyncRendezvous {
+    options: Options,
+}
+
+impl AsyncRendezvous {
+    pub fn new() -> Self {
+        Self::with_options(Options::new())
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        Self { options }
+    }
+
+    /// Bind `local` and perform the rendezvous handshake with the peer at `remote`
+    /// (which does the same towards this side's `local` address)
+    pub async fn connect(
+        &self,
+        local: impl ToSocketAddrs,
+        remote: impl ToSocketAddrs,
+    ) -> Result<AsyncConnection> {
+        let remote = lookup_host(remote)
+            .await?
+            .next()
+            .context("Failed to resolve address")?;
+
+        let socket = UdpSocket::bind(local).await?;
+        let stream = AsyncCaller::stream(socket, remote, self.options.clone());
+
+        AsyncConnection::rendezvous_v5(stream).await
+    }
+}
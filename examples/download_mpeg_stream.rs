@@ -10,13 +10,13 @@ fn main() -> anyhow::Result<()> {
 
     srt_server.on_connect(|conn| {
         let id = conn.stream_id.clone().unwrap_or_default();
-        tracing::info!("Client connected: {id:?}");
+        tracing::info!("Client connected: {id}");
         fs::write(format!("_local/stream_{id}.mpg"), []).unwrap();
     });
 
-    srt_server.on_disconnect(|conn| {
+    srt_server.on_disconnect(|conn, reason| {
         let id = conn.stream_id.clone().unwrap_or_default();
-        tracing::info!("Client disconnected: {id:?}");
+        tracing::info!("Client disconnected: {id} ({reason})");
     });
 
     srt_server.on_data(|conn, mpeg_packet| {
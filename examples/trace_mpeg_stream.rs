@@ -13,14 +13,14 @@ fn main() -> anyhow::Result<()> {
 
     srt_server.on_connect(|conn| {
         tracing::info!(
-            "Client connected: {:?}",
+            "Client connected: {}",
             conn.stream_id.clone().unwrap_or_default()
         );
     });
 
-    srt_server.on_disconnect(|conn| {
+    srt_server.on_disconnect(|conn, reason| {
         tracing::info!(
-            "Client disconnected: {:?}",
+            "Client disconnected: {} ({reason})",
             conn.stream_id.clone().unwrap_or_default()
         );
     });
@@ -29,7 +29,7 @@ fn main() -> anyhow::Result<()> {
 
     let on_data = move |conn: &CallbackConnection, mpeg_data: &[u8]| {
         let id = conn.stream_id.clone().unwrap_or_default();
-        tracing::info!("Packet from {id:?}");
+        tracing::info!("Packet from {id}");
 
         for chunk in mpeg_data.chunks_exact(188) {
             let pack = MpegPacket::from_raw(chunk, &pids_pmt.lock().unwrap()).unwrap();
@@ -10,12 +10,12 @@ use tokio::sync::Mutex;
 
 async fn handle_connection(mut con: SrtConnection) -> Result<()> {
     let stream_id = con.stream_id.clone().unwrap_or_default();
-    tracing::info!("Client connected: {stream_id:?}",);
+    tracing::info!("Client connected: {stream_id}",);
 
     let pids_pmt = Arc::new(Mutex::new(Vec::<u16>::new()));
 
     while let Ok(data) = con.recv_data().await {
-        tracing::info!("Packet from {stream_id:?}");
+        tracing::info!("Packet from {stream_id}");
 
         for chunk in data.chunks_exact(188) {
             let pack = MpegPacket::from_raw(chunk, &pids_pmt.lock().await).unwrap();
@@ -56,7 +56,7 @@ async fn handle_connection(mut con: SrtConnection) -> Result<()> {
         }
     }
 
-    tracing::info!("Client disconnected: {stream_id:?}",);
+    tracing::info!("Client disconnected: {stream_id}",);
 
     Ok(())
 }
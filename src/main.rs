@@ -34,14 +34,14 @@ fn run_srt(
         let running = running.clone();
         move |conn| {
             let id = conn.stream_id.clone().unwrap_or_default();
-            tracing::info!("Stream started: {id:?}");
+            tracing::info!("Stream started: {id}");
             running.store(true, Ordering::Relaxed);
         }
     });
 
-    srt_server.on_disconnect(move |conn| {
+    srt_server.on_disconnect(move |conn, reason| {
         let id = conn.stream_id.clone().unwrap_or_default();
-        tracing::info!("Stream ended: {id:?}");
+        tracing::info!("Stream ended: {id} ({reason})");
         running.store(false, Ordering::Relaxed);
     });
 